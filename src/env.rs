@@ -1,17 +1,51 @@
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::mem;
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
 
 use parser::SExpr;
-use parser::SExprs;
 use serr::{SErr, SResult};
 use utils::{new_rc_ref_cell, RcRefCell};
+use expander::resolve_alias;
 
 pub type VarName = String;
-pub type EnvValues = HashMap<VarName, SExpr>;
+pub type EnvValues = HashMap<VarName, SExpr, BuildHasherDefault<NameHasher>>;
 
-#[derive(Debug, Clone, PartialEq)]
+/// FNV-1a hashing for variable names. The default hasher resists collision
+/// attacks, which makes it slow for the short names that are looked up on
+/// every step of the evaluator.
+pub struct NameHasher(u64);
+
+impl Default for NameHasher {
+    fn default() -> NameHasher {
+        NameHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for NameHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct EnvRef(RcRefCell<Option<Env>>);
 
+impl PartialEq for EnvRef {
+    /// Environments are equal only if they are the same environment,
+    /// comparing the contents may never end for recursive procedures.
+    fn eq(&self, other: &EnvRef) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
 impl EnvRef {
     /// A null environment.
     /// Used as parent environment of global environment.
@@ -31,6 +65,11 @@ impl EnvRef {
         EnvRef(Rc::clone(&self.0))
     }
 
+    /// A reference that doesn't keep the environment alive.
+    pub fn downgrade(&self) -> WeakEnvRef {
+        WeakEnvRef(Rc::downgrade(&self.0))
+    }
+
     pub fn get(&self, name: &str) -> SResult<SExpr> {
         self.0.borrow()
             .as_ref()
@@ -81,22 +120,75 @@ impl EnvRef {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct WeakEnvRef(Weak<RefCell<Option<Env>>>);
+
+impl WeakEnvRef {
+    pub fn upgrade(&self) -> Option<EnvRef> {
+        self.0.upgrade().map(EnvRef)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Env {
     parent: EnvRef,
-    values: EnvValues,
+    values: Values,
+}
+
+/// The variables of an environment. Procedure calls make environments
+/// with a few variables, searching a list is faster for them than hashing.
+#[derive(Debug, PartialEq)]
+enum Values {
+    Few(Vec<(VarName, SExpr)>),
+    Many(EnvValues),
+}
+
+/// How many variables are kept in a list before switching to a table.
+const MAX_FEW_VALUES: usize = 8;
+
+impl Values {
+    fn get(&self, name: &str) -> Option<&SExpr> {
+        match self {
+            Values::Few(xs) => xs.iter().find(|x| x.0 == name).map(|x| &x.1),
+            Values::Many(xs) => xs.get(name)
+        }
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut SExpr> {
+        match self {
+            Values::Few(xs) => xs.iter_mut().find(|x| x.0 == name).map(|x| &mut x.1),
+            Values::Many(xs) => xs.get_mut(name)
+        }
+    }
+
+    fn insert(&mut self, key: VarName, val: SExpr) {
+        if let Some(value) = self.get_mut(&key) {
+            *value = val;
+            return
+        }
+
+        match self {
+            Values::Few(xs) if xs.len() < MAX_FEW_VALUES => xs.push((key, val)),
+            Values::Few(xs) => {
+                let mut values = mem::replace(xs, vec![]).into_iter().collect::<EnvValues>();
+                values.insert(key, val);
+                *self = Values::Many(values);
+            },
+            Values::Many(xs) => { xs.insert(key, val); }
+        }
+    }
 }
 
 impl Env {
     pub fn new(parent: EnvRef) -> Env {
         Env {
             parent,
-            values: HashMap::new(),
+            values: Values::Few(vec![]),
         }
     }
 
     pub fn with_values(parent: EnvRef, values: EnvValues) -> Env {
-        Env { parent, values }
+        Env { parent, values: Values::Many(values) }
     }
 
     /// Converts `Env` into a `EnvRef`.
@@ -109,10 +201,12 @@ impl Env {
     }
 
     pub fn get(&self, name: &str) -> SResult<SExpr> {
-        if self.values.contains_key(name) {
-            Ok(self.values[name].clone())
+        if let Some(value) = self.values.get(name) {
+            Ok(value.clone())
         } else if self.parent.is_some() {
             self.parent.get(name)
+        } else if let Some((renamed, macro_env)) = resolve_alias(name) {
+            macro_env.get(renamed)
         } else {
             bail!(UnboundVar => name)
         }
@@ -120,11 +214,12 @@ impl Env {
 
     pub fn with_ref<F,T>(&self, name: &str, mut f: F) -> SResult<T>
     where F: FnMut(&SExpr)->SResult<T> {
-        if self.values.contains_key(name) {
-            let sexpr = &self.values[name];
+        if let Some(sexpr) = self.values.get(name) {
             f(sexpr)
         } else if self.parent.is_some() {
             self.parent.with_ref(name, f)
+        } else if let Some((renamed, macro_env)) = resolve_alias(name) {
+            macro_env.with_ref(renamed, f)
         } else {
             bail!(UnboundVar => name)
         }
@@ -132,11 +227,12 @@ impl Env {
 
     pub fn with_mut_ref<F,T>(&mut self, name: &str, mut f: F) -> SResult<T>
     where F: FnMut(&mut SExpr)->SResult<T>{
-        if self.values.contains_key(name) {
-            let sexpr = self.values.get_mut(name).unwrap();
+        if let Some(sexpr) = self.values.get_mut(name) {
             f(sexpr)
         } else if self.parent.is_some() {
             self.parent.with_mut_ref(name, f)
+        } else if let Some((renamed, macro_env)) = resolve_alias(name) {
+            macro_env.with_mut_ref(renamed, f)
         } else {
             bail!(UnboundVar => name)
         }
@@ -147,30 +243,29 @@ impl Env {
     }
 
     pub fn set(&mut self, key: String, val: SExpr) -> SResult<SExpr> {
-        if self.values.contains_key(&key) {
-            self.values.insert(key.clone(), val)
-                .ok_or_else(|| SErr::new_unbound_var(&key))
+        if let Some(value) = self.values.get_mut(&key) {
+            Ok(mem::replace(value, val))
         } else if self.parent.is_some() {
             self.parent.set(key, val)
+        } else if let Some((renamed, macro_env)) = resolve_alias(&key) {
+            macro_env.set(renamed.to_string(), val)
         } else {
             bail!(UnboundVar => key)
         }
     }
 
     pub fn remove(&mut self, key: &str) -> SResult<SExpr> {
-        if self.values.contains_key(key) {
-            self.values.remove(key)
-                .ok_or_else(|| SErr::new_unbound_var(key))
+        let removed = match self.values {
+            Values::Few(ref mut xs) => xs.iter().position(|x| x.0 == key).map(|i| xs.remove(i).1),
+            Values::Many(ref mut xs) => xs.remove(key)
+        };
+
+        if let Some(value) = removed {
+            Ok(value)
         } else if self.parent.is_some() {
             self.parent.remove(key)
         } else {
             bail!(UnboundVar => key)
         }
     }
-
-    pub fn pack(&mut self, keys: &[String], vals: SExprs) {
-        for (i, arg) in vals.into_iter().enumerate() {
-            self.values.insert(keys[i].clone(), arg);
-        }
-    }
 }
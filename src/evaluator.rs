@@ -1,11 +1,15 @@
+use std::cell::{Cell, RefCell};
+use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
 use std::vec::IntoIter;
 
-use lexer::Token;
+use lexer::{Token, Span};
 use parser::SExpr;
 use parser::SExprs;
 use env::EnvRef;
-use procedure::ProcedureData;
+use procedure::{ProcedureData, PrimitiveData, CompoundData, ParameterData};
+use primitives::lang;
 use serr::{SErr, SResult};
 
 pub fn eval_mut_ref<F,T>(sexpr: &SExpr, env: &EnvRef, mut f: F) -> SResult<T>
@@ -32,107 +36,734 @@ where F: FnMut(&SExpr)->SResult<T> {
     }
 }
 
-pub fn eval(sexpr_: &SExpr, env_: &EnvRef) -> SResult<SExpr> {
-    let mut sexpr = sexpr_.clone();
-    let mut env = env_.clone_ref();
+pub fn eval(sexpr: &SExpr, env: &EnvRef) -> SResult<SExpr> {
+    run(State::Eval(sexpr.clone(), env.clone_ref(), Cont::halt()))
+}
 
-    loop {
-        match sexpr {
-            SExpr::Atom(Token::Symbol(x)) => {
-                return env.get(&x)
-            },
-            x@SExpr::Atom(_) | x@SExpr::Procedure(_)
-                | x@SExpr::Port(_) | x@SExpr::Unspecified => {
-                return Ok(x)
-            },
-            list@SExpr::DottedList(_,_) => {
-                fn flatten(list: SExpr) -> SExprs {
-                    match list {
-                        SExpr::DottedList(xs, sexpr) => {
-                            let mut ys = xs;
-                            match *sexpr {
-                                SExpr::List(mut xs) => ys.append(&mut xs),
-                                dl@SExpr::DottedList(_,_) => ys.append(&mut flatten(dl)),
-                                x => ys.push(x)
-                            };
-                            ys
-                        },
-                        SExpr::List(xs) => xs,
-                        x => vec![x]
-                    }
+/// Evaluates `sexpr` that is read from `span`. Pairs know where they're
+/// read from but atoms don't, so this is where errors about an atom are
+/// located.
+pub fn eval_at(sexpr: &SExpr, span: &Span, env: &EnvRef) -> SResult<SExpr> {
+    let location = current_location();
+    set_location(Some(span.clone()));
+    let result = eval(sexpr, env);
+    set_location(location);
+    result
+}
+
+/// Applies `procedure` to already evaluated `args`.
+/// Used by primitives that need to call back into Scheme code.
+pub fn apply(procedure: &SExpr, args: SExprs, env: &EnvRef) -> SResult<SExpr> {
+    run(State::Apply(procedure.clone(), args, env.clone_ref(), Cont::halt()))
+}
+
+//
+// The evaluator is a loop over `State`s. Instead of recursing on the Rust
+// stack, everything that is left to do after evaluating an expression is
+// kept in a linked list of heap allocated frames, the continuation. This
+// makes it possible to capture the continuation with `call/cc` and to
+// reinstate it any number of times.
+//
+
+/// The next step of the evaluator.
+pub enum State {
+    Eval(SExpr, EnvRef, ContRef),
+    Return(SExpr, ContRef),
+    Apply(SExpr, SExprs, EnvRef, ContRef),
+}
+
+pub type ContRef = Rc<Cont>;
+
+/// Called with the value of an expression, the saved expressions and the
+/// environment of a `Frame::Call`. Lets special forms written outside of
+/// the evaluator continue in tail position.
+pub type Callback = fn(SExpr, SExprs, EnvRef, ContRef) -> SResult<State>;
+
+#[derive(Debug, Clone)]
+pub struct Cont {
+    frame: Frame,
+    next: Option<ContRef>,
+    /// The location of the form and the call that were being evaluated
+    /// when the frame is pushed, they are reinstated when the frame gets
+    /// the value.
+    location: Option<Span>,
+    call: Calls,
+}
+
+#[derive(Debug, Clone)]
+pub enum Frame {
+    /// Ends the evaluation started by `eval` or `apply`.
+    Halt,
+    /// Waiting for the test of an `if`: consequent and alternative.
+    If(SExpr, SExpr, EnvRef),
+    /// Evaluating a body, the index points to the next expression.
+    Begin(SExprs, usize, EnvRef),
+    /// Waiting for the value of a `define`.
+    Define(String, EnvRef),
+    /// Waiting for the value of a `set!`.
+    Set(String, EnvRef),
+    /// Waiting for the operator of a combination: operator expression
+    /// and operand expressions.
+    Operator(SExpr, SExprs, EnvRef),
+    /// Evaluating operands: the procedure, the operands and the index of
+    /// the one that is being evaluated. The operands before it are values
+    /// already, the ones after it are still expressions.
+    Operands(SExpr, SExprs, usize, EnvRef),
+    /// Waiting for a value that will be handed to the callback.
+    Call(Callback, SExprs, EnvRef),
+    /// Leaving the extent of a `with-exception-handler` thunk or an
+    /// exception handler, reinstates the handlers.
+    Handlers(Handlers),
+    /// Leaving the extent of a `dynamic-wind` thunk or a `parameterize`
+    /// body.
+    Unwind,
+}
+
+impl Cont {
+    pub fn halt() -> ContRef {
+        Rc::new(Cont { frame: Frame::Halt, next: None, location: None, call: None })
+    }
+
+    pub fn push(frame: Frame, next: ContRef) -> ContRef {
+        let (location, call) = current_context();
+        Rc::new(Cont { frame, next: Some(next), location, call })
+    }
+
+    fn is_halt(&self) -> bool {
+        match self.frame {
+            Frame::Halt => true,
+            _ => false
+        }
+    }
+
+    /// Takes the frame out of the continuation and reinstates the location
+    /// and the call of it. The frame is copied only if the continuation is
+    /// shared, e.g. it has been captured by `call/cc`.
+    fn pop(k: ContRef) -> (Frame, ContRef) {
+        let mut cont = Rc::try_unwrap(k)
+            .unwrap_or_else(|shared| (*shared).clone());
+        let frame = mem::replace(&mut cont.frame, Frame::Halt);
+        let next = cont.next.take().unwrap_or_else(Cont::halt);
+        set_context(cont.location.take(), cont.call.take());
+        (frame, next)
+    }
+}
+
+impl Drop for Cont {
+    // Deep continuations would overflow the stack if they were dropped
+    // recursively.
+    fn drop(&mut self) {
+        let mut next = self.next.take();
+        while let Some(k) = next {
+            match Rc::try_unwrap(k) {
+                Ok(mut cont) => next = cont.next.take(),
+                Err(_) => break
+            }
+        }
+    }
+}
+
+/// An entry of the `dynamic-wind` stack, `parameterize` uses it too.
+#[derive(Debug)]
+pub struct Winder {
+    wind: Wind,
+    depth: usize,
+    parent: Winders,
+}
+
+/// What is done when a dynamic extent is entered and left.
+#[derive(Debug)]
+enum Wind {
+    /// The `before` and `after` thunks of a `dynamic-wind`.
+    Thunks(SExpr, SExpr, EnvRef),
+    /// Parameters bound by `parameterize`, with the values that they are
+    /// swapped with on both entering and leaving.
+    Parameters(Vec<(ParameterData, RefCell<SExpr>)>),
+}
+
+impl Winder {
+    fn enter(&self) -> SResult<()> {
+        match self.wind {
+            Wind::Thunks(ref before, _, ref env) => apply(before, vec![], env).map(|_| ()),
+            Wind::Parameters(ref bindings) => Ok(swap_parameters(bindings))
+        }
+    }
+
+    fn leave(&self) -> SResult<()> {
+        match self.wind {
+            Wind::Thunks(_, ref after, ref env) => apply(after, vec![], env).map(|_| ()),
+            Wind::Parameters(ref bindings) => Ok(swap_parameters(bindings))
+        }
+    }
+}
+
+fn swap_parameters(bindings: &[(ParameterData, RefCell<SExpr>)]) {
+    for (parameter, value) in bindings {
+        mem::swap(&mut *parameter.value.borrow_mut(), &mut *value.borrow_mut());
+    }
+}
+
+pub type Winders = Option<Rc<Winder>>;
+
+/// An entry of the exception handler stack.
+#[derive(Debug)]
+pub struct Handler {
+    pub handler: SExpr,
+    pub env: EnvRef,
+    pub parent: Handlers,
+}
+
+pub type Handlers = Option<Rc<Handler>>;
+
+/// A record of a compound procedure call, kept for backtraces. A tail
+/// call takes the place of the caller, like it does with its frames.
+#[derive(Debug)]
+pub struct Call {
+    name: Option<Rc<String>>,
+    site: Option<Span>,
+    /// Identifies the continuation that the call returns to.
+    k: usize,
+    parent: Calls,
+}
+
+pub type Calls = Option<Rc<Call>>;
+
+impl Drop for Call {
+    // Same as `Cont`, deep recursions make long chains.
+    fn drop(&mut self) {
+        let mut parent = self.parent.take();
+        while let Some(call) = parent {
+            match Rc::try_unwrap(call) {
+                Ok(mut call) => parent = call.parent.take(),
+                Err(_) => break
+            }
+        }
+    }
+}
+
+/// How many calls are shown in a backtrace, the outermost ones are left out.
+const BACKTRACE_LIMIT: usize = 20;
+
+thread_local! {
+    /// `dynamic-wind` and `parameterize` entries that are currently in
+    /// effect.
+    static WINDERS: RefCell<Winders> = RefCell::new(None);
+    /// Exception handlers that are currently installed, innermost first.
+    static HANDLERS: RefCell<Handlers> = RefCell::new(None);
+    /// Ids of the evaluator loops that are currently running. A primitive
+    /// that calls back into Scheme starts a new loop on top of the others.
+    static RUNS: RefCell<Vec<usize>> = RefCell::new(vec![]);
+    static RUN_COUNT: Cell<usize> = Cell::new(0);
+    /// The location of the innermost form that is being evaluated and the
+    /// compound procedure call that is being evaluated. They're kept
+    /// together since every frame saves and restores both.
+    static CONTEXT: RefCell<(Option<Span>, Calls)> = RefCell::new((None, None));
+}
+
+pub fn current_winders() -> Winders {
+    WINDERS.with(|w| w.borrow().clone())
+}
+
+fn set_winders(winders: Winders) {
+    WINDERS.with(|w| *w.borrow_mut() = winders);
+}
+
+pub fn push_winder(before: SExpr, after: SExpr, env: &EnvRef) {
+    let parent = current_winders();
+    let depth = parent.as_ref().map_or(0, |w| w.depth) + 1;
+    set_winders(Some(Rc::new(Winder { wind: Wind::Thunks(before, after, env.clone_ref()), depth, parent })));
+}
+
+/// Gives the parameters their new values until the winder is left, see
+/// `Frame::Unwind`.
+pub fn push_parameters(bindings: Vec<(ParameterData, SExpr)>) {
+    let bindings = bindings.into_iter()
+        .map(|(parameter, value)| (parameter, RefCell::new(value)))
+        .collect::<Vec<_>>();
+    swap_parameters(&bindings);
+
+    let parent = current_winders();
+    let depth = parent.as_ref().map_or(0, |w| w.depth) + 1;
+    set_winders(Some(Rc::new(Winder { wind: Wind::Parameters(bindings), depth, parent })));
+}
+
+pub fn current_handlers() -> Handlers {
+    HANDLERS.with(|h| h.borrow().clone())
+}
+
+pub fn set_handlers(handlers: Handlers) {
+    HANDLERS.with(|h| *h.borrow_mut() = handlers);
+}
+
+pub fn current_location() -> Option<Span> {
+    CONTEXT.with(|c| c.borrow().0.clone())
+}
+
+fn set_location(location: Option<Span>) {
+    CONTEXT.with(|c| c.borrow_mut().0 = location);
+}
+
+fn current_call() -> Calls {
+    CONTEXT.with(|c| c.borrow().1.clone())
+}
+
+fn current_context() -> (Option<Span>, Calls) {
+    CONTEXT.with(|c| c.borrow().clone())
+}
+
+fn set_context(location: Option<Span>, call: Calls) {
+    CONTEXT.with(|c| *c.borrow_mut() = (location, call));
+}
+
+fn enter_call(procedure: &CompoundData, k: &ContRef) {
+    let k_id = &**k as *const Cont as usize;
+    CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        let parent = match c.1.take() {
+            // A tail call returns to where its caller returns
+            Some(ref caller) if caller.k == k_id => caller.parent.clone(),
+            caller => caller
+        };
+
+        c.1 = Some(Rc::new(Call {
+            name: procedure.name.clone(),
+            site: c.0.clone(),
+            k: k_id,
+            parent
+        }));
+    });
+}
+
+/// Describes the calls that are being evaluated, innermost first. The
+/// same call made over and over again is shown once.
+fn backtrace() -> Vec<String> {
+    let mut calls: Vec<(Rc<Call>, usize)> = vec![];
+    let mut current = current_call();
+    while let Some(call) = current {
+        current = call.parent.clone();
+        if let Some(last) = calls.last_mut() {
+            let same_site = match (&last.0.site, &call.site) {
+                (Some(x), Some(y)) => Rc::ptr_eq(&x.source, &y.source) && x.line == y.line && x.column == y.column,
+                (None, None) => true,
+                _ => false
+            };
+            if last.0.name == call.name && same_site {
+                last.1 += 1;
+                continue
+            }
+        }
+        calls.push((call, 1));
+    }
+
+    let mut lines = calls.iter()
+        .take(BACKTRACE_LIMIT)
+        .map(|(call, count)| {
+            let mut line = match call.name {
+                Some(ref name) => format!("in {}", name),
+                None => "in an anonymous procedure".to_string()
+            };
+            if let Some(ref site) = call.site {
+                line.push_str(&format!(", called at {}", site));
+            }
+            if *count > 1 {
+                line.push_str(&format!(" ({} times)", count));
+            }
+            line
+        })
+        .collect::<Vec<_>>();
+
+    if calls.len() > BACKTRACE_LIMIT {
+        lines.push(format!("... {} more", calls.len() - BACKTRACE_LIMIT));
+    }
+
+    lines
+}
+
+pub fn current_run() -> usize {
+    RUNS.with(|runs| *runs.borrow().last().unwrap_or(&0))
+}
+
+fn is_running(run: usize) -> bool {
+    RUNS.with(|runs| runs.borrow().contains(&run))
+}
+
+/// Runs the `after` thunks of the winders that are left and the `before`
+/// thunks of the winders that are entered while going from the current
+/// dynamic extent to the one of `to`.
+fn rewind(to: &Winders) -> SResult<()> {
+    fn depth(w: &Winders) -> usize {
+        w.as_ref().map_or(0, |w| w.depth)
+    }
+
+    fn parent(w: &Winders) -> Winders {
+        w.as_ref().and_then(|w| w.parent.clone())
+    }
+
+    fn same(a: &Winders, b: &Winders) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false
+        }
+    }
+
+    let from = current_winders();
+    let mut common = from.clone();
+    let mut other = to.clone();
+    while depth(&common) > depth(&other) { common = parent(&common); }
+    while depth(&other) > depth(&common) { other = parent(&other); }
+    while !same(&common, &other) {
+        common = parent(&common);
+        other = parent(&other);
+    }
+
+    let mut current = from;
+    while !same(&current, &common) {
+        let winder = current.unwrap();
+        set_winders(winder.parent.clone());
+        winder.leave()?;
+        current = winder.parent.clone();
+    }
+
+    let mut entering = vec![];
+    let mut current = to.clone();
+    while !same(&current, &common) {
+        let winder = current.unwrap();
+        current = winder.parent.clone();
+        entering.push(winder);
+    }
+
+    for winder in entering.into_iter().rev() {
+        winder.enter()?;
+        set_winders(Some(winder));
+    }
+
+    Ok(())
+}
+
+fn run(start: State) -> SResult<SExpr> {
+    let id = RUN_COUNT.with(|c| {
+        c.set(c.get() + 1);
+        c.get()
+    });
+    RUNS.with(|runs| runs.borrow_mut().push(id));
+    let winders = current_winders();
+    let handlers = current_handlers();
+    let (location, call) = current_context();
+
+    let mut state = start;
+    let result = loop {
+        state = match state {
+            State::Return(value, ref k) if k.is_halt() => break Ok(value),
+            state => match step(state) {
+                Ok(next) => next,
+                Err(SErr::Escape(c, value)) if c.run == id => State::Return(value, c.k),
+                Err(e@SErr::Escape(_, _)) => break Err(e),
+                Err(e) => match current_handlers() {
+                    // Errors are raised like `raise` does, if there
+                    // is a handler
+                    Some(_) => raise(e.into_condition(), false, Cont::halt())?,
+                    None => break Err(e.located(current_location()).traced(backtrace()))
                 }
+            }
+        };
+    };
 
-                sexpr = SExpr::List(flatten(list));
-            },
-            SExpr::List(xs) => {
-                let mut iter = xs.into_iter();
-                let op = iter.next()
-                    .ok_or_else(|| SErr::new_unexpected_form(&SExpr::List(vec![])))?;
-                let mut args = Args::new(iter.collect(), &env);
-
-                match op {
-                    // Need to handle control structres like if and begin
-                    // here to be able to use same stack for tail recursive
-                    // functions.
-                    // Other control structres should be written in forms of
-                    // if or begin (and I hope that's all for basic TCO)
-                    SExpr::Atom(Token::Symbol(ref sym)) if sym == "if" => {
-                        let mut arg_iter = args.into_iter();
-                        let test = arg_iter.next()
-                            .ok_or_else(|| SErr::WrongArgCount(2, 0))?;
-                        let consequent = arg_iter.next()
-                            .ok_or_else(|| SErr::WrongArgCount(2, 1))?;
-                        let alterne = arg_iter.next()
-                            .unwrap_or(SExpr::Unspecified);
-
-                        if test.eval(&env)?.to_bool() {
-                            sexpr = consequent;
-                        } else {
-                            sexpr = alterne;
-                        }
-                    },
-                    SExpr::Atom(Token::Symbol(ref sym)) if sym == "begin" => {
-                        let last = args.pop()
-                            .ok_or_else(|| SErr::new_generic("Bodyless `begin`"))?;
-                        args.eval()?; // eval all except the last one
-                        sexpr = last;
-                    },
-                    SExpr::Atom(Token::Symbol(symbol)) => {
-                        let procedure = args.env
-                            .get(&symbol)?
-                            .clone();
-
-                        match procedure {
-                            SExpr::Procedure(proc) => match proc {
-                                ProcedureData::Primitive(x) => return x.apply(args),
-                                ProcedureData::Compound(x) => {
-                                    env = x.build_env(args)?;
-                                    sexpr = *x.body;
-                                }
-                            },
-                            _ => bail!(NotAProcedure => procedure)
-                        };
-                    },
-                    x => {
-                        // Trying to use something other than a symbol as procedure
-                        // Evaluate and see if it's a procedure.
-                        let evaled = eval(&x, &env)?;
-                        if let SExpr::Procedure(procedure) = evaled {
-                            match procedure {
-                                ProcedureData::Primitive(x) => return x.apply(args),
-                                ProcedureData::Compound(x) => {
-                                    env = x.build_env(args)?;
-                                    sexpr = *x.body;
-                                }
-                            };
-                        } else {
-                            bail!(NotAProcedure => x)
-                        }
+    RUNS.with(|runs| runs.borrow_mut().pop());
+    set_context(location, call);
+    match result {
+        Err(SErr::Escape(c, value)) => Err(SErr::Escape(c, value)),
+        Err(e) => {
+            // Leave the `dynamic-wind`s entered by this evaluation.
+            set_handlers(handlers);
+            rewind(&winders)?;
+            Err(e)
+        },
+        ok => ok
+    }
+}
+
+/// Calls the current exception handler with `obj`. The handler is called
+/// with the outer handlers installed. If the handler returns from a
+/// non-continuable exception, a secondary exception is raised.
+pub fn raise(obj: SExpr, continuable: bool, k: ContRef) -> SResult<State> {
+    let current = current_handlers();
+    let handler = match current {
+        Some(ref handler) => Rc::clone(handler),
+        None => bail!(Raised => obj)
+    };
+
+    set_handlers(handler.parent.clone());
+    let k = if continuable {
+        Cont::push(Frame::Handlers(current), k)
+    } else {
+        Cont::push(Frame::Call(handler_returned, vec![obj.clone()], handler.env.clone_ref()), k)
+    };
+
+    Ok(State::Apply(handler.handler.clone(), vec![obj], handler.env.clone_ref(), k))
+}
+
+fn handler_returned(_value: SExpr, mut obj: SExprs, _env: EnvRef, k: ContRef) -> SResult<State> {
+    let irritants = vec![obj.remove(0)];
+    let error = SErr::Error("Exception handler returned from a non-continuable exception".to_string(), irritants);
+    raise(error.into_condition(), false, k)
+}
+
+fn step(state: State) -> SResult<State> {
+    match state {
+        State::Eval(sexpr, env, k) => eval_step(sexpr, env, k),
+        State::Return(value, k) => return_step(value, k),
+        State::Apply(procedure, args, env, k) => apply_step(procedure, args, env, k),
+    }
+}
+
+fn eval_step(sexpr: SExpr, env: EnvRef, k: ContRef) -> SResult<State> {
+    match sexpr {
+        SExpr::Atom(Token::Symbol(x)) => {
+            Ok(State::Return(env.get(&x)?, k))
+        },
+        x@SExpr::Atom(_) | x@SExpr::Vector(_) | x@SExpr::Bytevector(_) | x@SExpr::Record(_) | x@SExpr::RecordType(_) | x@SExpr::HashTable(_)
+            | x@SExpr::Procedure(_) | x@SExpr::Port(_)
+            | x@SExpr::Macro(_) | x@SExpr::Condition(_) | x@SExpr::Values(_) | x@SExpr::Eof | x@SExpr::Unspecified => {
+            Ok(State::Return(x, k))
+        },
+        SExpr::Nil => Err(SErr::new_unexpected_form(&SExpr::Nil)),
+        SExpr::Pair(pair) => {
+            if let Some(span) = pair.span() {
+                set_location(Some(span.clone()));
+            }
+
+            // A dotted tail is treated as the last element of the form.
+            let (mut xs, tail) = SExpr::Pair(pair).list_parts()?;
+            if !tail.is_nil() {
+                xs.push(tail);
+            }
+
+            let op = xs.remove(0);
+            let args = xs;
+
+            match op {
+                // Forms that evaluate their subexpressions are handled here,
+                // so that the subexpressions are evaluated with the same
+                // continuation machinery as everything else.
+                ref x if x.is_symbol("if") => {
+                    let mut arg_iter = args.into_iter();
+                    let test = arg_iter.next()
+                        .ok_or_else(|| SErr::WrongArgCount(2, 0))?;
+                    let consequent = arg_iter.next()
+                        .ok_or_else(|| SErr::WrongArgCount(2, 1))?;
+                    let alterne = arg_iter.next()
+                        .unwrap_or(SExpr::Unspecified);
+
+                    let k = Cont::push(Frame::If(consequent, alterne, env.clone_ref()), k);
+                    Ok(State::Eval(test, env, k))
+                },
+                ref x if x.is_symbol("begin") => {
+                    if args.is_empty() {
+                        bail!(Generic => "Bodyless `begin`")
                     }
+
+                    sequence(args, 0, env, k)
+                },
+                ref x if x.is_symbol("define") => {
+                    let (id, value) = lang::definition(args, &env)?;
+                    let k = Cont::push(Frame::Define(id, env.clone_ref()), k);
+                    Ok(State::Eval(value, env, k))
+                },
+                ref x if x.is_symbol("set!") => {
+                    let mut arg_iter = args.into_iter();
+                    let id = arg_iter.next()
+                        .ok_or_else(|| SErr::new_id_not_found("nothing"))?
+                        .into_symbol()?;
+                    let value = arg_iter.next()
+                        .ok_or_else(|| SErr::new_expr_not_found("nothing"))?;
+
+                    let k = Cont::push(Frame::Set(id, env.clone_ref()), k);
+                    Ok(State::Eval(value, env, k))
+                },
+                SExpr::Atom(Token::Symbol(ref symbol)) => {
+                    let operator = env.get(symbol)?;
+                    combine(operator, op.clone(), args, env, k)
+                },
+                x => {
+                    // Trying to use something other than a symbol as procedure
+                    // Evaluate and see if it's a procedure.
+                    let k = Cont::push(Frame::Operator(x.clone(), args, env.clone_ref()), k);
+                    Ok(State::Eval(x, env, k))
                 }
             }
+        }
+    }
+}
+
+fn return_step(value: SExpr, k: ContRef) -> SResult<State> {
+    let (frame, k) = Cont::pop(k);
+    match frame {
+        Frame::Halt => Ok(State::Return(value, Cont::halt())),
+        Frame::If(consequent, alterne, env) => {
+            if value.to_bool() {
+                Ok(State::Eval(consequent, env, k))
+            } else {
+                Ok(State::Eval(alterne, env, k))
+            }
+        },
+        Frame::Begin(body, i, env) => sequence(body, i, env, k),
+        Frame::Define(id, env) => {
+            let value = CompoundData::name_procedure(value, &id);
+            env.define(id, value);
+            Ok(State::Return(SExpr::Unspecified, k))
+        },
+        Frame::Set(id, env) => {
+            Ok(State::Return(env.set(id, value)?, k))
+        },
+        Frame::Operator(op, args, env) => combine(value, op, args, env, k),
+        Frame::Operands(procedure, mut args, i, env) => {
+            args[i] = value;
+            eval_operands(procedure, args, i + 1, env, k)
+        },
+        Frame::Call(f, exprs, env) => f(value, exprs, env, k),
+        Frame::Handlers(handlers) => {
+            set_handlers(handlers);
+            Ok(State::Return(value, k))
+        },
+        Frame::Unwind => {
+            if let Some(winder) = current_winders() {
+                set_winders(winder.parent.clone());
+                winder.leave()?;
+            }
+            Ok(State::Return(value, k))
+        }
+    }
+}
+
+fn apply_step(procedure: SExpr, args: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    match procedure {
+        SExpr::Procedure(ProcedureData::Primitive(x)) => match x {
+            PrimitiveData::Procedure(f) => {
+                Ok(State::Return(f(Args::new_evaluated(args, &env))?, k))
+            },
+            PrimitiveData::Syntax(f) => {
+                // Syntax is applied to values only through `apply`, make sure
+                // they don't get evaluated again.
+                let args_quoted = args.into_iter()
+                    .map(|x| quote!(x))
+                    .collect();
+                Ok(State::Return(f(Args::new(args_quoted, &env))?, k))
+            },
+            PrimitiveData::SpecialForm(f) => {
+                let args_quoted = args.into_iter()
+                    .map(|x| quote!(x))
+                    .collect();
+                f(Args::new(args_quoted, &env), k)
+            },
+            PrimitiveData::Control(f) => f(Args::new_evaluated(args, &env), k),
+        },
+        SExpr::Procedure(ProcedureData::Compound(x)) => {
+            let env = x.build_env(args)?;
+            enter_call(&x, &k);
+            Ok(State::Eval((*x.body).clone(), env, k))
+        },
+        SExpr::Procedure(ProcedureData::Continuation(x)) => {
+            let value = match args.len() {
+                0 => SExpr::Unspecified,
+                1 => args.into_iter().next().unwrap(),
+                // Like `values` does
+                _ => SExpr::Values(args)
+            };
+
+            rewind(&x.winders)?;
+            set_handlers(x.handlers.clone());
+            if x.run != current_run() && is_running(x.run) {
+                // The continuation belongs to an evaluation further down
+                // the Rust stack, unwind to it.
+                Err(SErr::Escape(x, value))
+            } else {
+                Ok(State::Return(value, x.k))
+            }
+        },
+        SExpr::Procedure(ProcedureData::Parameter(x)) => {
+            if !args.is_empty() {
+                bail!(WrongArgCount => 0 as usize, args.len())
+            }
+
+            Ok(State::Return(x.get(), k))
+        },
+        x => bail!(NotAProcedure => x)
+    }
+}
+
+/// Continues a combination once its operator is known.
+fn combine(operator: SExpr, op: SExpr, args: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    match operator {
+        SExpr::Procedure(ProcedureData::Primitive(PrimitiveData::Syntax(f))) => {
+            Ok(State::Return(f(Args::new(args, &env))?, k))
+        },
+        SExpr::Procedure(ProcedureData::Primitive(PrimitiveData::SpecialForm(f))) => {
+            f(Args::new(args, &env), k)
+        },
+        SExpr::Procedure(_) => {
+            eval_operands(operator, args, 0, env, k)
+        },
+        SExpr::Macro(x) => {
+            // Expand the macro use and evaluate the expansion in place of it.
+            let mut form = vec![op];
+            form.extend(args.into_iter());
+            Ok(State::Eval(x.expand(&SExpr::list(form))?, env, k))
+        },
+        x => bail!(NotAProcedure => x)
+    }
+}
+
+/// Evaluates the operands from the `i`th one on, replacing each of them
+/// with its value.
+fn eval_operands(procedure: SExpr, mut args: SExprs, mut i: usize,
+                 env: EnvRef, k: ContRef) -> SResult<State> {
+    while i < args.len() {
+        let value = match args[i] {
+            // Variables and constants can't capture the continuation,
+            // no need for a frame.
+            SExpr::Atom(Token::Symbol(ref x)) => env.get(x)?,
+            SExpr::Pair(_) | SExpr::Nil => {
+                let arg = mem::replace(&mut args[i], SExpr::Unspecified);
+                let k = Cont::push(Frame::Operands(procedure, args, i, env.clone_ref()), k);
+                return Ok(State::Eval(arg, env, k))
+            },
+            _ => {
+                i += 1;
+                continue
+            }
         };
-    };
+
+        args[i] = value;
+        i += 1;
+    }
+
+    Ok(State::Apply(procedure, args, env, k))
+}
+
+/// Evaluates `args` and applies `procedure` to them, like a combination
+/// whose operator is known already.
+pub fn call(procedure: SExpr, args: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    eval_operands(procedure, args, 0, env, k)
+}
+
+/// Evaluates `body` in order, the last expression in tail position.
+pub fn eval_body(body: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    if body.is_empty() {
+        bail!(Generic => "Expected an expression, found an empty body")
+    }
+
+    sequence(body, 0, env, k)
+}
+
+fn sequence(mut body: SExprs, i: usize, env: EnvRef, k: ContRef) -> SResult<State> {
+    let expr = mem::replace(&mut body[i], SExpr::Unspecified);
+    if i + 1 == body.len() {
+        Ok(State::Eval(expr, env, k))
+    } else {
+        let k = Cont::push(Frame::Begin(body, i + 1, env.clone_ref()), k);
+        Ok(State::Eval(expr, env, k))
+    }
 }
 
 #[derive(Debug)]
@@ -145,7 +776,8 @@ pub enum Extra {
 pub struct Args {
     pub env: EnvRef,
     pub extra: Extra,
-    vec: SExprs
+    vec: SExprs,
+    evaluated: bool
 }
 
 impl Deref for Args {
@@ -164,11 +796,17 @@ impl DerefMut for Args {
 
 impl Args {
     pub fn new_with_extra(vec: SExprs, extra: Extra, env: &EnvRef) -> Args {
-        Args { env: env.clone_ref(), extra, vec }
+        Args { env: env.clone_ref(), extra, vec, evaluated: false }
     }
 
     pub fn new(vec: SExprs, env: &EnvRef) -> Args {
-        Args { env: env.clone_ref(), extra: Extra::Nothing, vec }
+        Args { env: env.clone_ref(), extra: Extra::Nothing, vec, evaluated: false }
+    }
+
+    /// Creates arguments that are values already, `eval` and `evaled`
+    /// won't evaluate them again.
+    pub fn new_evaluated(vec: SExprs, env: &EnvRef) -> Args {
+        Args { env: env.clone_ref(), extra: Extra::Nothing, vec, evaluated: true }
     }
 
     pub fn env(&self) -> EnvRef {
@@ -180,13 +818,22 @@ impl Args {
     }
 
     pub fn eval(&self) -> SResult<SExprs> {
+        if self.evaluated {
+            return Ok(self.vec.clone())
+        }
+
         self.vec.iter()
             .map(|x| eval(&x, &self.env))
             .collect::<SResult<_>>()
     }
 
     pub fn evaled(self) -> SResult<Args> {
-        Ok(Args::new_with_extra(self.eval()?, self.extra, &self.env))
+        if self.evaluated {
+            return Ok(self)
+        }
+
+        let vec = self.eval()?;
+        Ok(Args { env: self.env, extra: self.extra, vec, evaluated: true })
     }
 
     pub fn own_one(self) -> SResult<SExpr> {
@@ -246,14 +893,827 @@ impl Args {
     }
 }
 
-// FIXME: does unneccesarry cloning when called, refactor with into_iter
-pub trait ToArgs {
-    fn to_args(&self, env: &EnvRef) -> Args;
-}
+#[cfg(test)]
+mod testing {
+    use env::{Env, EnvRef};
+    use lexer::{tokenize, Source};
+    use parser::parse;
+    use primitives;
+    use pretty_print::{external, pretty, Labels};
+    use parser::SExpr;
+    use serr::SResult;
+
+    fn new_env() -> EnvRef {
+        let env = Env::with_values(EnvRef::null(), primitives::env()).into_ref();
+        primitives::load_prelude(&env).unwrap();
+        env
+    }
+
+    /// Evaluates the expressions of `code` in `env` until one of them
+    /// fails, returns the value of the last one.
+    fn eval_in(env: &EnvRef, code: &str) -> SResult<SExpr> {
+        let mut value = SExpr::Unspecified;
+        for (sexpr, span) in parse(tokenize(&Source::new("test.scm", code)).unwrap()).unwrap() {
+            value = sexpr.eval_at(&span, env)?;
+        }
+
+        Ok(value)
+    }
+
+    fn eval_with(code: &str) -> SResult<SExpr> {
+        eval_in(&new_env(), code)
+    }
+
+    fn eval_str(code: &str) -> String {
+        eval_with(code).unwrap().to_string()
+    }
+
+    #[test]
+    fn verify_call_cc() {
+        assert_eq!(eval_str("(+ 1 (call/cc (lambda (k) (+ 10 (k 1)))))"), "2");
+        assert_eq!(eval_str("
+            (define (first-negative xs)
+              (call-with-current-continuation
+                (lambda (return)
+                  (map (lambda (x) (if (< x 0) (return x))) xs)
+                  #f)))
+            (first-negative '(1 2 -3 4))"), "-3");
+
+        // Re-entering a continuation
+        assert_eq!(eval_str("
+            (define (count-to-three)
+              (define k #f)
+              (define n 0)
+              (call/cc (lambda (c) (set! k c)))
+              (set! n (+ n 1))
+              (if (< n 3) (k 'again) n))
+            (count-to-three)"), "3");
+    }
+
+    #[test]
+    fn verify_dynamic_wind() {
+        assert_eq!(eval_str("
+            (define trace '())
+            (define (note x) (set! trace (cons x trace)))
+            (define k #f)
+            (define n 0)
+            (define (run)
+              (dynamic-wind
+                (lambda () (note 'before))
+                (lambda () (call/cc (lambda (c) (set! k c))) (note 'during))
+                (lambda () (note 'after)))
+              (set! n (+ n 1))
+              (if (< n 2) (k 'again)))
+            (run)
+            trace"), "(after during before after during before)");
+
+        assert_eq!(eval_str("
+            (define trace '())
+            (define (note x) (set! trace (cons x trace)))
+            (call/cc
+              (lambda (escape)
+                (dynamic-wind
+                  (lambda () (note 'before))
+                  (lambda () (escape 'out) (note 'unreachable))
+                  (lambda () (note 'after)))))
+            trace"), "(after before)");
+    }
+
+    #[test]
+    fn verify_derived_forms() {
+        assert_eq!(eval_str("(list (or #f 5) (and 1 2) (and) (or))"), "(5 2 #t #f)");
+        assert_eq!(eval_str("(cond ((assv 2 '((1 . a) (2 . b))) => cdr) (else 'none))"), "b");
+        assert_eq!(eval_str("(case (* 2 3) ((2 3 5 7) 'prime) ((1 4 6 8 9) 'composite))"), "composite");
+        assert_eq!(eval_str("(case 'x ((a) 1) (else => (lambda (x) x)))"), "x");
+        assert_eq!(eval_str("(let* ((x 1) (y (+ x 1))) (* x y))"), "2");
+        assert_eq!(eval_str("
+            (letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                     (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+              (even? 100))"), "#t");
+    }
+
+    #[test]
+    fn verify_tail_calls_in_conditionals() {
+        assert_eq!(eval_str("
+            (define (f n) (cond ((= n 0) 'done) (else (f (- n 1)))))
+            (f 1000000)"), "done");
+        assert_eq!(eval_str("
+            (define (f n) (case n ((0) 'done) (else (f (- n 1)))))
+            (f 1000000)"), "done");
+        assert_eq!(eval_str("
+            (define (f n) (and #t (if (= n 0) 'done (f (- n 1)))))
+            (f 1000000)"), "done");
+        assert_eq!(eval_str("
+            (define (f n) (or (and (= n 0) 'done) (f (- n 1))))
+            (f 1000000)"), "done");
+    }
+
+    #[test]
+    fn verify_tail_calls_in_let_forms() {
+        assert_eq!(eval_str("
+            (define (f n) (let ((m (- n 1))) (if (< m 0) 'done (f m))))
+            (f 1000000)"), "done");
+        assert_eq!(eval_str("
+            (define (f n) (let* ((a n) (m (- a 1))) (if (< m 0) 'done (f m))))
+            (f 1000000)"), "done");
+        assert_eq!(eval_str("
+            (define (f n) (letrec ((m (- n 1))) (if (< m 0) 'done (f m))))
+            (f 1000000)"), "done");
+        assert_eq!(eval_str("
+            (let loop ((i 0)) (if (= i 1000000) 'done (loop (+ i 1))))"), "done");
+    }
+
+    #[test]
+    fn verify_tail_calls_in_apply() {
+        assert_eq!(eval_str("
+            (define (f n) (if (= n 0) 'done (apply f (list (- n 1)))))
+            (f 1000000)"), "done");
+    }
+
+    #[test]
+    fn verify_exceptions() {
+        assert_eq!(eval_str("(guard (e (#t (list 'caught e))) (raise 'boom))"), "(caught boom)");
+        assert_eq!(eval_str("
+            (guard (e ((string? e) 'string) ((error-object? e) (error-object-irritants e)))
+              (error \"Something bad:\" 1 2))"), "(1 2)");
+        assert_eq!(eval_str("
+            (guard (e ((error-object? e) (error-object-irritants e)))
+              undefined-variable)"), "(undefined-variable)");
+        assert_eq!(eval_str("
+            (guard (e ((file-error? e) 'file-error))
+              (open-input-file \"/nonexistent/file\"))"), "file-error");
+        assert_eq!(eval_str("
+            (with-exception-handler
+              (lambda (c) 42)
+              (lambda () (+ (raise-continuable 'oops) 1)))"), "43");
+
+        // Re-raised to the outer guard when no clause matches
+        assert_eq!(eval_str("
+            (guard (e (#t (list 'outer e)))
+              (guard (e ((number? e) 'number))
+                (raise 'inner)))"), "(outer inner)");
 
+        assert_eq!(eval_str("
+            (define trace '())
+            (guard (e (#t (set! trace (cons 'handler trace))))
+              (dynamic-wind
+                (lambda () (set! trace (cons 'before trace)))
+                (lambda () (raise 'error))
+                (lambda () (set! trace (cons 'after trace)))))
+            trace"), "(handler after before)");
+    }
+
+    #[test]
+    fn verify_multiple_values() {
+        assert_eq!(eval_str("(call-with-values (lambda () (values 1 2)) +)"), "3");
+        assert_eq!(eval_str("(call-with-values (lambda () (values)) list)"), "()");
+        assert_eq!(eval_str("(receive (a . rest) (values 1 2 3) (list a rest))"), "(1 (2 3))");
+        assert_eq!(eval_str("
+            (define x 10)
+            (let-values (((x) (values 1)) ((y) (values x))) (list x y))"), "(1 10)");
+        assert_eq!(eval_str("(let*-values (((x) (values 1)) ((y) (values x))) (list x y))"), "(1 1)");
+        assert_eq!(eval_str("
+            (define-values (a . b) (values 1 2 3))
+            (list a b)"), "(1 (2 3))");
+
+        // Values pass through begin and tail calls
+        assert_eq!(eval_str("
+            (define (f n) (if (= n 0) (begin 'ignored (values 'a 'b)) (f (- n 1))))
+            (call-with-values (lambda () (f 10)) list)"), "(a b)");
+
+        assert_eq!(eval_str("(call-with-values (lambda () (call/cc (lambda (k) (k 1 2)))) list)"), "(1 2)");
+    }
+
+    #[test]
+    fn verify_mutable_pairs() {
+        assert_eq!(eval_str("
+            (define xs (list 1 2 3))
+            (define ys (cdr xs))
+            (set-car! ys 'b)
+            (set-cdr! ys '(c d))
+            (list xs ys)"), "((1 b c d) (b c d))");
+        assert_eq!(eval_str("(let ((x (cons 1 2))) (list (eq? x x) (eq? x (cons 1 2)) (equal? x (cons 1 2))))"), "(#t #f #t)");
+        assert_eq!(eval_str("(let ((x '(a))) (eq? x x))"), "#t");
+
+        // Circular lists
+        assert_eq!(eval_str("
+            (define xs (list 1 2 3))
+            (set-cdr! (cddr xs) xs)
+            (list (list-ref xs 10) (list? xs) (pair? xs))"), "(2 #f #t)");
+        assert_eq!(eval_str("
+            (define a (list 1 2))
+            (define b (list 1 2))
+            (define c (list 1 2 1 2))
+            (define d (list 1 3))
+            (set-cdr! (cdr a) a)
+            (set-cdr! (cdr b) b)
+            (set-cdr! (cdddr c) c)
+            (set-cdr! (cdr d) d)
+            (define v (vector 1 #f))
+            (define w (vector 1 #f))
+            (vector-set! v 1 v)
+            (vector-set! w 1 w)
+            (list (equal? a b) (equal? a c) (equal? a d) (equal? v w) (equal? (list a v) (list b w)))"),
+            "(#t #t #f #t #t)");
+
+        // cons and cdr don't copy the list
+        assert_eq!(eval_str("
+            (define (build n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))
+            (define (count xs n) (if (null? xs) n (count (cdr xs) (+ n 1))))
+            (count (build 200000 '()) 0)"), "200000");
+    }
+
+    #[test]
+    fn verify_vectors() {
+        assert_eq!(eval_str("#(1 \"a\" (b))"), "#(1 \"a\" (b))");
+        assert_eq!(eval_str("
+            (define v (make-vector 3 0))
+            (vector-set! v 1 'x)
+            (list v (vector-ref v 1) (vector-length v) (vector? v) (vector? '(1)))"), "(#(0 x 0) x 3 #t #f)");
+        assert_eq!(eval_str("(vector->list #(1 2 3 4) 1 3)"), "(2 3)");
+        assert_eq!(eval_str("(vector-map + #(1 2 3) #(10 20))"), "#(11 22)");
+        assert_eq!(eval_str("
+            (define sum 0)
+            (vector-for-each (lambda (x) (set! sum (+ sum x))) #(1 2 3))
+            sum"), "6");
+        assert_eq!(eval_str("
+            (define v (vector 1 2 3 4 5))
+            (vector-copy! v 1 v 0 3)
+            (vector-fill! v 'z 4)
+            v"), "#(1 1 2 3 z)");
+        assert_eq!(eval_str("(vector-append #(1) #() #(2 3))"), "#(1 2 3)");
+        assert_eq!(eval_str("(let ((v #(1))) (list (equal? #(1 (2)) #(1 (2))) (eqv? v v) (eqv? #(1) #(1))))"), "(#t #t #f)");
+        assert_eq!(eval_str("(let ((x 2)) `#(1 ,x))"), "#(1 2)");
+    }
+
+    #[test]
+    fn verify_bytevectors() {
+        assert_eq!(eval_str("#u8(1 2 255)"), "#u8(1 2 255)");
+        assert_eq!(eval_str("
+            (define bv (make-bytevector 3 7))
+            (bytevector-u8-set! bv 0 255)
+            (list bv (bytevector-u8-ref bv 0) (bytevector-length bv) (bytevector? bv))"), "(#u8(255 7 7) 255 3 #t)");
+        assert_eq!(eval_str("(bytevector-copy #u8(1 2 3 4) 1 3)"), "#u8(2 3)");
+        assert_eq!(eval_str("(bytevector-append #u8(1) (bytevector 2 3))"), "#u8(1 2 3)");
+        assert_eq!(eval_str("(utf8->string (string->utf8 \"λx\"))"), "\"λx\"");
+        assert_eq!(eval_str("(string->utf8 \"λ\")"), "#u8(206 187)");
+        assert_eq!(eval_str("(equal? #u8(1 2) (bytevector 1 2))"), "#t");
+
+        let path = ::std::env::temp_dir().join("scheme-rs-verify-bytevectors.bin");
+        let _ = ::std::fs::remove_file(&path);
+        assert_eq!(eval_str(&format!("
+            (define path \"{}\")
+            (define out (open-binary-output-file path))
+            (write-u8 1 out)
+            (write-bytevector #u8(2 3 4 5) out 1)
+            (close-port out)
+            (define in (open-binary-input-file path))
+            (define head (read-bytevector 2 in))
+            (define rest (read-all in))
+            (close-port in)
+            (delete-file path)
+            (list head rest)", path.display())), "(#u8(1 3) #u8(4 5))");
+
+        // Binary writes are buffered until the port is flushed
+        assert_eq!(eval_str(&format!("
+            (define path \"{}\")
+            (define out (open-binary-output-file path))
+            (write-u8 7 out)
+            (flush-output-port out)
+            (define in (open-binary-input-file path))
+            (define byte (read-u8 in))
+            (delete-file path)
+            byte", path.display())), "7");
+        assert_eq!(eval_str("(guard (e (#t (error-object-message e))) (write-u8 1 (open-output-string)))"),
+                   "\"Can't apply function `write-u8` to a port type of string output\"");
+    }
+
+    #[test]
+    fn verify_records() {
+        let point = "
+            (define-record-type <point>
+              (make-point x y)
+              point?
+              (x point-x set-point-x!)
+              (y point-y)
+              (label point-label))
+            (define p (make-point 1 2))";
+
+        assert_eq!(eval_str(&format!("{} (set-point-x! p 10) (list (point-x p) (point-y p))", point)), "(10 2)");
+        assert_eq!(eval_str(&format!("{} (list (point? p) (point? 1) (typeof p))", point)), "(#t #f <point>)");
+        assert_eq!(eval_str(&format!("{} p", point)), "#<record <point> x: 1 y: 2 label: <unspecified>>");
+        assert_eq!(eval_str(&format!("{} (list (equal? p p) (equal? p (make-point 1 2)) (eqv? p p))", point)), "(#t #f #t)");
+        assert_eq!(eval_str(&format!("
+            {}
+            (define-record-type <other> (make-other x) other? (x other-x))
+            (guard (e (#t 'wrong-type)) (other-x p))", point)), "wrong-type");
+    }
+
+    #[test]
+    fn verify_hash_tables() {
+        let table = "
+            (define t (make-hash-table equal?))
+            (hash-table-set! t '(1 2) 'a)
+            (hash-table-set! t \"b\" 2)";
+
+        assert_eq!(eval_str(&format!("{} (list (hash-table-ref t (list 1 2)) (hash-table-ref/default t 'c 0))", table)), "(a 0)");
+        assert_eq!(eval_str(&format!("{} (hash-table-ref t 'c (lambda () 'none))", table)), "none");
+        assert_eq!(eval_str(&format!("{} (hash-table-update!/default t \"b\" (lambda (x) (* x 10)) 0) (hash-table-ref t \"b\")", table)), "20");
+        assert_eq!(eval_str(&format!("{} (hash-table-delete! t \"b\") (hash-table->alist t)", table)), "(((1 2) . a))");
+        assert_eq!(eval_str("
+            (define t (make-hash-table eqv?))
+            (hash-table-set! t (list 1) 1)
+            (hash-table-set! t 2 2)
+            (list (hash-table-contains? t (list 1)) (hash-table-size t) (hash-table-fold t (lambda (k v acc) (+ v acc)) 0))"), "(#f 2 3)");
+        assert_eq!(eval_str("(guard (e (#t 'missing)) (hash-table-ref (make-hash-table) 1))"), "missing");
+        assert_eq!(eval_str("
+            (define t (make-hash-table =))
+            (hash-table-set! t 1 'one)
+            (hash-table-set! t 1/2 'half)
+            (list (hash-table-ref/default t 1.0 #f) (hash-table-ref/default t 0.5 #f) (hash-table-size t))"), "(one half 2)");
+        assert_eq!(eval_str("
+            (define t (make-hash-table string=?))
+            (hash-table-set! t \"a\" 1)
+            (list (hash-table-ref/default t (string #\\a) #f) (hash-table-ref/default t 1 #f))"), "(1 #f)");
+        assert_eq!(eval_str("(guard (e (#t 'unsupported)) (make-hash-table (lambda (a b) #t)))"), "unsupported");
+        assert_eq!(eval_str("
+            (define t (make-hash-table eq?))
+            (define u (make-hash-table))
+            (hash-table-set! t car 1)
+            (hash-table-set! t cdr 2)
+            (hash-table-set! t u 3)
+            (hash-table-set! t t 4)
+            (list (hash-table-ref t car) (hash-table-ref t cdr) (hash-table-ref t u) (hash-table-ref t t)
+                  (hash-table-ref/default t (make-hash-table) #f))"), "(1 2 3 4 #f)");
+    }
+
+    #[test]
+    fn verify_datum_comments() {
+        assert_eq!(eval_str("(list 1 #;2 3 #;(4 5))"), "(1 3)");
+        assert_eq!(eval_str("'(1 . #;2 3)"), "(1 . 3)");
+        assert_eq!(eval_str("#;(error \"skipped\") #; #;1 2 'done"), "done");
+    }
+
+    #[test]
+    fn verify_number_syntax() {
+        assert_eq!(eval_str("(list #xFF #b1010 #o17 #d10 #x-1a)"), "(255 10 15 10 -26)");
+        assert_eq!(eval_str("(list #e1.5 #i1/2 #x1.8 #e#x10 #x#i10 1e2)"), "(3/2 0.5 1.5 16 16.0 100.0)");
+        assert_eq!(eval_str("(list (string->number \"ff\" 16) (string->number \"#b101\") (string->number \"abc\"))"), "(255 5 #f)");
+        assert_eq!(eval_str("(list 1e-2147483648 (string->number \"1e-2147483648\") (string->number \"1e-2147483649\"))"), "(0.0 0.0 #f)");
+        assert_eq!(eval_str("(list (number->string 255 16) (number->string -5 2) (number->string 1/3 3) (number->string 2.5 2))"),
+                   "(\"ff\" \"-101\" \"1/10\" \"10.1\")");
+    }
+
+    #[test]
+    fn verify_big_integers() {
+        assert_eq!(eval_str("(define (fact n) (if (= n 0) 1 (* n (fact (- n 1))))) (fact 30)"), "265252859812191058636308480000000");
+        assert_eq!(eval_str("(- (+ 9223372036854775807 1) 1)"), "9223372036854775807");
+        assert_eq!(eval_str("(list (quotient (expt 10 30) 7) (remainder (expt 10 30) 7) (modulo (- (expt 2 70)) 3))"),
+                   "(142857142857142857142857142857 1 2)");
+        assert_eq!(eval_str("(list (gcd (expt 2 80) (expt 6 40)) (lcm 4 6) (expt 2/3 3) (expt 2 -2))"), "(1099511627776 12 8/27 1/4)");
+        assert_eq!(eval_str("(/ (expt 10 30) (expt 4 20))"), "931322574615478515625/1024");
+        assert_eq!(eval_str("(list (< (expt 2 64) (expt 2 65)) (= (expt 2 64) (* (expt 2 32) (expt 2 32))) (integer? (expt 2 64)))"), "(#t #t #t)");
+        assert_eq!(eval_str("(list #x-ffffffffffffffffffff (number->string (expt 2 100) 16))"),
+                   "(-1208925819614629174706175 \"10000000000000000000000000\")");
+    }
+
+    #[test]
+    fn verify_numeric_tower() {
+        assert_eq!(eval_str("(list (exact 2.5) (exact 0.1) (inexact 1/4) (exact->inexact 3) (exact 4.0))"),
+                   "(5/2 3602879701896397/36028797018963968 0.25 3.0 4)");
+        assert_eq!(eval_str("(list (sqrt 16) (sqrt 9/4) (sqrt 2.25) (sqrt (expt 10 40)) (expt 4 1/2) (expt 8/27 2/3) (expt 2.0 3))"),
+                   "(4 3/2 1.5 100000000000000000000 2 4/9 8.0)");
+        assert_eq!(eval_str("(call-with-values (lambda () (exact-integer-sqrt 17)) list)"), "(4 1)");
+        assert_eq!(eval_str("(guard (e (#t 'inexact)) (exact-integer-sqrt 4.0))"), "inexact");
+        assert_eq!(eval_str("(call-with-values (lambda () (floor/ -7 2)) list)"), "(-4 1)");
+        assert_eq!(eval_str("(call-with-values (lambda () (truncate/ -7 2)) list)"), "(-3 -1)");
+        assert_eq!(eval_str("(list (floor 7/2) (ceiling 7/2) (truncate -7/2) (round 7/2) (round 5/2) (round 2.5) (floor -3.5))"),
+                   "(3 4 -3 4 2 2.0 -4.0)");
+        assert_eq!(eval_str("(list (exact-integer? 5) (exact-integer? 5.0) (integer? 5.0) (rational? 1.5) (nan? (/ 0.0 0.0)) (infinite? (/ 1.0 0)) (finite? 1/2))"),
+                   "(#t #f #t #t #t #t #t)");
+        assert_eq!(eval_str("(list (rationalize 1/3 1/100) (rationalize 0.3 1/10) (square 1/2) (max 1 2.0) (remainder 7.0 2) (odd? -3))"),
+                   "(1/3 0.3333333333333333 1/4 2.0 1.0 #t)");
+        assert_eq!(eval_str("(list (max 1.0 2) (min 3 1/2) (min 2 1.5 1))"), "(2.0 1/2 1.0)");
+        assert_eq!(eval_str("(list (= 1 1.0) (< 1/3 0.3333333333333333) (numerator 0.5) (denominator 0.5))"), "(#t #f 1.0 2.0)");
+    }
+
+    #[test]
+    fn verify_complex_numbers() {
+        assert_eq!(eval_str("(list 1+2i -i 1.5-0.5i 3+0i #x10+fi (string->number \"1e2+1e-1i\"))"),
+                   "(1.0+2.0i 0.0-1.0i 1.5-0.5i 3 16.0+15.0i 100.0+0.1i)");
+        assert_eq!(eval_str("(list (* 1+2i 3-i) (+ 1+i 1-i) (/ 1+i 2) (- 5 +2i))"), "(5.0+5.0i 2.0 0.5+0.5i 5.0-2.0i)");
+        assert_eq!(eval_str("(list (real-part 3+4i) (imag-part 3+4i) (magnitude 3+4i) (magnitude -5) (angle 1) (imag-part 2.5))"),
+                   "(3.0 4.0 5.0 5 0 0)");
+        assert_eq!(eval_str("(list (make-rectangular 1 2) (make-rectangular 1 0) (make-polar 2 0) (= (make-polar 1 0.0) 1.0))"),
+                   "(1.0+2.0i 1 2 #t)");
+
+        // The reader and make-rectangular agree on exactness
+        assert_eq!(eval_str("(list 3+0i (make-rectangular 3 0) 1/2+0.0i (make-rectangular 1/2 0.0) 2@0 (make-polar 2 0) 1+2i (make-rectangular 1 2))"),
+                   "(3 3 0.5 0.5 2 2 1.0+2.0i 1.0+2.0i)");
+        assert_eq!(eval_str("(list (sqrt -4) (magnitude (expt +i 2)) (exp 0+0.0i) (log -1))"),
+                   "(0.0+2.0i 1.0 1.0 0.0+3.141592653589793i)");
+        assert_eq!(eval_str("(list (log +i -1) (log -1 +i) (log 8 2))"), "(0.5 2.0 3.0)");
+        assert_eq!(eval_str("(list (complex? 1+i) (real? 1+i) (real? 1.5) (number? -i) (exact? 1+i) (= 1+i 1+i))"),
+                   "(#t #f #t #t #f #t)");
+        assert_eq!(eval_str("(number->string 3-4.5i 2)"), "\"11.-100.1i\"");
+    }
+
+    #[test]
+    fn verify_external_representations() {
+        fn eval_external(code: &str, display: bool, labels: Labels) -> String {
+            external(&eval_with(code).unwrap(), display, labels)
+        }
+
+        assert_eq!(eval_str("(list \"a\\\"b\\nc\\\\\" #\\space #\\a #\\x7 'x '|two words| (string->symbol \"12\"))"),
+                   "(\"a\\\"b\\nc\\\\\" #\\space #\\a #\\alarm x |two words| |12|)");
+        assert_eq!(eval_external("(list \"a b\" #\\c '(\"d\"))", true, Labels::Cycles), "(a b c (d))");
+
+        // Only cycles get labels with write
+        assert_eq!(eval_str("(define x (list 1 2 3)) (set-cdr! (cddr x) x) x"), "#0=(1 2 3 . #0#)");
+        assert_eq!(eval_str("(define v (vector 1 (list 2))) (vector-set! v 0 v) (set-car! (vector-ref v 1) v) v"), "#0=#(#0# (#0#))");
+        assert_eq!(eval_str("(let ((x (list 1 2))) (list x x))"), "((1 2) (1 2))");
+        assert_eq!(eval_external("(let ((x (list 1 2))) (list x x (cdr x)))", false, Labels::Shared), "(#0=(1 . #1=(2)) #0# #1#)");
+        assert_eq!(eval_external("(let ((x (list 1 2))) (list x x))", false, Labels::None), "((1 2) (1 2))");
+
+        // The reader accepts the labels
+        assert_eq!(eval_str("(define x '#0=(a b . #0#)) (list (car x) (caddr x) (eq? x (cddr x)))"), "(a a #t)");
+        assert_eq!(eval_str("'(#1=(x) #1# #2=#(#2#))"), "((x) (x) #0=#(#0#))");
+        assert_eq!(eval_str("(let ((x '(#0=(1) #0#))) (eq? (car x) (cadr x)))"), "#t");
+        assert_eq!(eval_str("'#0=(a #1=(b) #1#)"), "(a (b) (b))");
+        assert_eq!(eval_str("(define x '#0=(#1=(b) #1# #0#)) (list (eq? x (caddr x)) (eq? (car x) (cadr x)))"), "(#t #t)");
+    }
+
+    #[test]
+    fn verify_string_ports() {
+        assert_eq!(eval_str(r#"
+          (define p (open-input-string "(a b) 42 \"s\"\nnext line"))
+          (list (read p) (read p) (read p) (read-line p) (read-char p) (read-line p))
+        "#), r#"((a b) 42 "s" "" #\n "ext line")"#);
+        assert_eq!(eval_str(r##"
+          (define p (open-input-string "abc(d)12\"s\"#t"))
+          (list (read p) (read p) (read p) (read p) (read p) (eof-object? (read p)))
+        "##), r#"(abc (d) 12 "s" #t #t)"#);
+        assert_eq!(eval_str(r#"(read-all (open-input-string "all of it"))"#), r#""all of it""#);
+
+        assert_eq!(eval_str(r#"
+          (define o (open-output-string))
+          (write "a" o)
+          (display " b" o)
+          (write-string " c" o)
+          (newline o)
+          (get-output-string o)
+        "#), r#""\"a\" b c\n""#);
+        assert_eq!(eval_str(r#"(call-with-output-string (lambda (port) (write '(1 #\x) port)))"#), r#""(1 #\\x)""#);
+        assert_eq!(eval_str(r#"(list (input-port? (open-input-string "")) (output-port? (open-output-string)))"#), "(#t #t)");
 
-impl ToArgs for [SExpr] {
-    fn to_args(&self, env: &EnvRef) -> Args {
-        Args::new(self.to_vec(), &env)
+        // The current output port is restored even if the thunk escapes
+        assert_eq!(eval_str(r#"(with-output-to-string (lambda () (display 1) (write "2") 'ignored))"#), r#""1\"2\"""#);
+        assert_eq!(eval_str(r#"
+          (guard (e (#t e))
+            (with-output-to-string (lambda () (raise 'oops))))
+          (with-output-to-string (lambda () (display "x")))
+        "#), r#""x""#);
+    }
+
+    #[test]
+    fn verify_input_procedures() {
+        assert_eq!(eval_str(r#"
+          (define p (open-input-string "añb\nline\nlast"))
+          (list (peek-char p) (peek-char p) (read-char p) (read-char p) (char-ready? p)
+                (read-string 2 p) (read-line p) (read-string 10 p) (read-string 1 p))
+        "#), r#"(#\a #\a #\a #\ñ #t "b\n" "line" "last" #<eof>)"#);
+
+        // Every input procedure returns the eof object at the end
+        assert_eq!(eval_str(r#"
+          (define p (open-input-string ""))
+          (map eof-object? (list (read-char p) (peek-char p) (read-line p) (read-string 3 p) (read p)))
+        "#), "(#t #t #t #t #t)");
+        assert_eq!(eval_str(r#"(read-string 0 (open-input-string ""))"#), r#""""#);
+        assert_eq!(eval_str(r#"(guard (e (#t (error-object-message e))) (peek-u8 (open-input-string "x")))"#),
+                   r#""Can't apply function `peek-u8` to a port type of string input""#);
+        assert_eq!(eval_str("(list (eof-object? (eof-object)) (eqv? (eof-object) (eof-object)) (eof-object? '()))"), "(#t #t #f)");
+        assert_eq!(eval_str(r#"
+          (define p (open-input-string "(a) b ; comment\n"))
+          (let loop ((acc '()))
+            (let ((x (read p)))
+              (if (eof-object? x) acc (loop (append acc (list x))))))
+        "#), "((a) b)");
+        assert_eq!(eval_str(r#"
+          (define p (open-input-string "abc\nline2 x"))
+          (list (read p) (read-line p) (read p) (peek-char p) (read-line p))
+        "#), r#"(abc "" line2 #\space " x")"#);
+        assert_eq!(eval_with(r#"(read (open-input-string "(a b"))"#).unwrap_err().to_string(),
+                   "test.scm:1:1: Expected ), found end of input\n    (read (open-input-string \"(a b\"))\n    ^");
+
+        let path = ::std::env::temp_dir().join("scheme-rs-verify-input-procedures.bin");
+        let _ = ::std::fs::remove_file(&path);
+        assert_eq!(eval_str(&format!(r#"
+          (define path "{}")
+          (define out (open-binary-output-file path))
+          (write-bytevector (bytevector 1 2 3) out)
+          (close-port out)
+          (define in (open-binary-input-file path))
+          (define result
+            (list (peek-u8 in) (read-u8 in) (u8-ready? in) (read-bytevector 5 in)
+                  (read-bytevector 5 in) (read-u8 in) (peek-u8 in)))
+          (delete-file path)
+          result
+        "#, path.display())), "(1 1 #t #u8(2 3) #<eof> #<eof> #<eof>)");
+    }
+
+    #[test]
+    fn verify_custom_ports() {
+        // read! gives at most 3 characters at a time, upcased
+        assert_eq!(eval_str(r#"
+          (define (upcase-port s)
+            (define pos 0)
+            (make-custom-textual-input-port "upcase"
+              (lambda (str start count)
+                (let ((n (min count 3 (- (string-length s) pos))))
+                  (string-replace-range! str start (+ start n) (string-upcase (substring s pos (+ pos n))))
+                  (set! pos (+ pos n))
+                  n))
+              #f #f #f))
+          (define p (upcase-port "hello\n(a b)world!"))
+          (list (input-port? p) (textual-port? p) (peek-char p) (read-char p) (read-line p)
+                (read p) (read-string 10 p) (read-char p))
+        "#), r#"(#t #t #\H #\H "ELLO" (A B) "WORLD!" #<eof>)"#);
+
+        // Strings are indexed by bytes, like the string procedures do
+        assert_eq!(eval_str(r#"
+          (define source "héllo λ")
+          (define done #f)
+          (define p
+            (make-custom-textual-input-port "utf-8"
+              (lambda (str start count)
+                (if done
+                    0
+                    (begin
+                      (set! done #t)
+                      (string-replace-range! str start (+ start (string-length source)) source)
+                      (string-length source))))
+              #f #f #f))
+          (list (read-char p) (read-char p) (peek-char p) (read-line p) (read-char p))
+        "#), r#"(#\h #\é #\l "llo λ" #<eof>)"#);
+
+        // write! may take less than it is given, close is called by close-port
+        assert_eq!(eval_str(r#"
+          (define a (open-output-string))
+          (define b (open-output-string))
+          (define closed #f)
+          (define tee
+            (make-custom-textual-output-port "tee"
+              (lambda (str start count)
+                (let ((s (substring str start (+ start (min count 3)))))
+                  (write-string s a)
+                  (write-string s b)
+                  (string-length s)))
+              #f #f (lambda () (set! closed #t))))
+          (define output? (output-port? tee))
+          (write '(1 "two" #\3) tee)
+          (display " λ-ok" tee)
+          (close-port tee)
+          (list output? (get-output-string a) (get-output-string b) closed)
+        "#), r#"(#t "(1 \"two\" #\\3) λ-ok" "(1 \"two\" #\\3) λ-ok" #t)"#);
+
+        assert_eq!(eval_str(r#"
+          (define data (bytevector 1 2 3 4 5))
+          (define pos 0)
+          (define in
+            (make-custom-binary-input-port "bytes"
+              (lambda (bv start count)
+                (let ((n (min count 2 (- (bytevector-length data) pos))))
+                  (bytevector-copy! bv start data pos (+ pos n))
+                  (set! pos (+ pos n))
+                  n))
+              #f #f #f))
+          (define written (bytevector))
+          (define out
+            (make-custom-binary-output-port "collect"
+              (lambda (bv start count)
+                (set! written (bytevector-append written (bytevector-copy bv start (+ start count))))
+                count)
+              #f #f #f))
+          (write-u8 (read-u8 in) out)
+          (write-bytevector (read-bytevector 3 in) out)
+          (list (binary-port? in) (peek-u8 in) (read-bytevector 5 in) (read-u8 in) written)
+        "#), "(#t 5 #u8(5) #<eof> #u8(1 2 3 4))");
+
+        assert_eq!(eval_str(r#"
+          (guard (e (#t 'error)) (make-custom-textual-input-port "bad" 'read! #f #f #f))
+        "#), "error");
+        assert_eq!(eval_str(r#"
+          (define p (make-custom-textual-input-port "bad" (lambda (s start count) (+ count 1)) #f #f #f))
+          (guard (e (#t 'error)) (read-char p))
+        "#), "error");
+    }
+
+    #[test]
+    fn verify_parameters() {
+        assert_eq!(eval_str(r#"
+          (define p (make-parameter 10))
+          (list (p) (parameterize ((p 20)) (p)) (p))
+        "#), "(10 20 10)");
+
+        // The converter is applied to the initial and the parameterized
+        // values, not when the old value is put back
+        assert_eq!(eval_str(r#"
+          (define calls 0)
+          (define p (make-parameter 1 (lambda (x) (set! calls (+ calls 1)) (* x 10))))
+          (list (p) (parameterize ((p 2)) (p)) (p) calls)
+        "#), "(10 20 10 2)");
+        assert_eq!(eval_str(r#"
+          (define p (make-parameter 0 (lambda (x) (if (number? x) x (error "not a number" x)))))
+          (guard (e ((error-object? e) (list (error-object-message e) (p))))
+            (parameterize ((p 'x)) (p)))
+        "#), r#"("not a number" 0)"#);
+
+        // Converters run like any other procedure call
+        assert_eq!(eval_str(r#"
+          (define p (make-parameter 0 (lambda (x) (if (number? x) x (raise-continuable 'not-a-number)))))
+          (with-exception-handler (lambda (e) 42)
+            (lambda () (parameterize ((p 'x)) (p))))
+        "#), "42");
+        assert_eq!(eval_str(r#"
+          (define saved #f)
+          (define p (make-parameter 0 (lambda (x) (call/cc (lambda (k) (set! saved k) x)))))
+          (let ((results '()))
+            (let ((v (parameterize ((p 1)) (p))))
+              (set! results (cons v results))
+              (if (< (length results) 3) (saved (* v 10)))
+              results))
+        "#), "(100 10 1)");
+
+        // Bindings are made after all the values are evaluated
+        assert_eq!(eval_str(r#"
+          (define p (make-parameter 1))
+          (define q (make-parameter 2))
+          (parameterize ((p (q)) (q (p)))
+            (list (p) (q) (parameterize ((p 3)) (list (p) (q)))))
+        "#), "(2 1 (3 1))");
+
+        // Restored on errors, escapes and re-entries
+        assert_eq!(eval_str(r#"
+          (define p (make-parameter 'outer))
+          (define inside (guard (e (#t (p))) (parameterize ((p 'inner)) (raise 'oops))))
+          (list inside (p))
+        "#), "(outer outer)");
+        assert_eq!(eval_str(r#"
+          (define p (make-parameter 'outer))
+          (define seen '())
+          (define k #f)
+          (let ((n 0))
+            (parameterize ((p 'inner))
+              (call/cc (lambda (c) (set! k c)))
+              (set! seen (append seen (list (p)))))
+            (set! seen (append seen (list (p))))
+            (set! n (+ n 1))
+            (if (< n 2) (k #f)))
+          seen
+        "#), "(inner outer inner outer)");
+        assert_eq!(eval_str(r#"
+          (define p (make-parameter 'outer))
+          (with-exception-handler
+            (lambda (e) (p))
+            (lambda () (parameterize ((p 'inner)) (raise-continuable 'c))))
+        "#), "inner");
+
+        let env = new_env();
+        eval_in(&env, "(define p (make-parameter 1))").unwrap();
+        assert!(eval_in(&env, "(parameterize ((p 2)) (car 5))").is_err());
+        assert_eq!(eval_in(&env, "(p)").unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn verify_current_ports() {
+        assert_eq!(eval_str(r#"
+          (define out (open-output-string))
+          (parameterize ((current-output-port out))
+            (display "a")
+            (write "b")
+            (newline))
+          (get-output-string out)
+        "#), r#""a\"b\"\n""#);
+        assert_eq!(eval_str(r#"
+          (define in (open-input-string "(1 2) rest"))
+          (parameterize ((current-input-port in))
+            (list (read) (read-line)))
+        "#), r#"((1 2) " rest")"#);
+        assert_eq!(eval_str("(map output-port? (list (current-output-port) (current-error-port)))"), "(#t #t)");
+        assert_eq!(eval_str("(input-port? (current-input-port))"), "#t");
+
+        // Only ports of the right direction are accepted
+        assert_eq!(eval_str("(guard (e (#t (error-object-irritants e))) (parameterize ((current-output-port 5)) 1))"), "(5)");
+        assert_eq!(eval_str(r#"
+          (guard (e (#t 'not-an-input-port))
+            (parameterize ((current-input-port (open-output-string))) 1))
+        "#), "not-an-input-port");
+
+        // The previous port is back after an error or a jump out
+        assert_eq!(eval_str(r#"
+          (define out (open-output-string))
+          (guard (e (#t #f))
+            (parameterize ((current-output-port out))
+              (car '())))
+          (call/cc (lambda (k)
+            (parameterize ((current-output-port out))
+              (k #f))))
+          (eq? (current-output-port) out)
+        "#), "#f");
+        assert_eq!(eval_str(r#"
+          (define out (open-output-string))
+          (let ((k #f) (n 0))
+            (parameterize ((current-output-port out))
+              (call/cc (lambda (c) (set! k c)))
+              (display n))
+            (set! n (+ n 1))
+            (if (< n 3) (k #f))
+            (get-output-string out))
+        "#), r#""012""#);
+
+        let path = ::std::env::temp_dir().join("scheme-rs-verify-current-ports.txt");
+        let _ = ::std::fs::remove_file(&path);
+        assert_eq!(eval_str(&format!(r#"
+          (define path "{}")
+          (with-output-to-file path (lambda () (write '(a "b")) (display " c")))
+          (define result (with-input-from-file path (lambda () (list (read) (read-line)))))
+          (delete-file path)
+          result
+        "#, path.display())), r#"((a "b") " c")"#);
+    }
+
+    #[test]
+    fn verify_pretty_print() {
+        fn eval_pretty(code: &str, width: usize) -> String {
+            pretty(&eval_with(code).unwrap(), width)
+        }
+
+        assert_eq!(eval_pretty("'(define (f x) (+ x 1))", 79), "(define (f x) (+ x 1))");
+        assert_eq!(eval_pretty("''(a `(b ,c ,@d) (quote e f) (quote . g))", 79), "'(a `(b ,c ,@d) (quote e f) (quote . g))");
+        assert_eq!(eval_pretty("'(define xs '(aaa bbb ccc ddd))", 18), "(define xs\n  '(aaa bbb ccc\n    ddd))");
+        assert_eq!(eval_pretty("'(define (fact n) (if (= n 0) 1 (* n (fact (- n 1)))))", 30),
+                   "(define (fact n)\n  (if (= n 0)\n      1\n      (* n (fact (- n 1)))))");
+        assert_eq!(eval_pretty("'(let ((a 1) (b 2)) (display a) (+ a b))", 20),
+                   "(let ((a 1) (b 2))\n  (display a)\n  (+ a b))");
+        assert_eq!(eval_pretty("'(let loop ((i 0)) (loop (+ i 1)))", 20),
+                   "(let loop ((i 0))\n  (loop (+ i 1)))");
+        assert_eq!(eval_pretty("'(let () (display 111) (display 222))", 15),
+                   "(let ()\n  (display 111)\n  (display 222))");
+        assert_eq!(eval_pretty("'(lambda (x y) (display x) y)", 20),
+                   "(lambda (x y)\n  (display x)\n  y)");
+        assert_eq!(eval_pretty("'(cond ((null? x) 0) ((pair? x) 1) (else 2))", 25),
+                   "(cond ((null? x) 0)\n      ((pair? x) 1)\n      (else 2))");
+        assert_eq!(eval_pretty("'((a . b) #(1 2 3 4 5 6 7 8 9 10))", 16),
+                   "((a . b)\n #(1 2 3 4 5 6\n   7 8 9 10))");
+
+        // Cycles are labeled like write does
+        assert_eq!(eval_pretty("(define x (list 'aaa 'bbb)) (set-cdr! (cdr x) x) x", 10),
+                   "#0=(aaa\n    bbb\n    . #0#)");
+    }
+
+    #[test]
+    fn verify_error_locations_and_backtraces() {
+        fn eval_err(code: &str) -> String {
+            eval_with(code).unwrap_err().to_string()
+        }
+
+        assert_eq!(eval_err("(define (f x)\n  (car x))\n(f 1)"),
+                   "test.scm:2:3: Expected a pair, found this: 1\n      (car x))\n      ^\n\
+                    Backtrace (innermost first):\n  in f, called at test.scm:3:1");
+
+        // g calls f in tail position, so it is not in the backtrace
+        assert_eq!(eval_err("(define (f x) (car x))\n(define (g x) (f x))\n(+ 1 (g 1))"),
+                   "test.scm:1:15: Expected a pair, found this: 1\n    (define (f x) (car x))\n                  ^\n\
+                    Backtrace (innermost first):\n  in f, called at test.scm:2:15");
+
+        // Atoms are located where they're read from
+        assert_eq!(eval_err("(define x 1)\n  undefined-variable"),
+                   "test.scm:2:3: Unbound variable: undefined-variable\n      undefined-variable\n      ^");
+    }
+
+    #[test]
+    fn verify_parse_errors() {
+        let error = |code| parse(tokenize(&Source::new("test.scm", code)).unwrap()).unwrap_err().to_string();
+
+        assert_eq!(error("#u8(1\n  256)"), "test.scm:2:3: Expected a byte, found 256\n      256)\n      ^");
+        assert_eq!(error("'(. 2)"), "test.scm:1:3: Expected an expression, found .\n    '(. 2)\n      ^");
+        assert_eq!(eval_str(r##"
+          (map (lambda (x) (guard (e ((read-error? e) 'read-error)) (read (open-input-string x))))
+               '("#u8(256)" "(. 2)"))
+        "##), "(read-error read-error)");
+    }
+
+    #[test]
+    fn verify_parsed_pairs_are_located() {
+        let source = Source::new("test.scm", "(a\n  (b c) . d)");
+        let (expr, _) = parse(tokenize(&source).unwrap()).unwrap().remove(0);
+
+        let mut spans = vec![];
+        let mut current = expr;
+        while let SExpr::Pair(pair) = current {
+            spans.push(pair.span().map(|x| (x.line, x.column)));
+            current = pair.cdr();
+        }
+        assert_eq!(spans, vec![Some((1, 1)), Some((2, 3))]);
     }
 }
@@ -1,8 +1,522 @@
-use parser::SExpr;
-use serr::{SResult};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 
-pub fn expand(sexpr: SExpr) -> SResult<SExpr> {
-    // TODO: after implementing hygienic macros, expand them here
-    // TODO: (begin) -> #<unspecified>
-    Ok(sexpr)
+use lexer::Token;
+use parser::{SExpr, SExprs};
+use env::{EnvRef, WeakEnvRef};
+use serr::{SErr, SResult};
+use utils::new_rc_ref_cell;
+
+/// Symbols introduced by a macro template are renamed into aliases of the
+/// form `name<ALIAS_MARK>macro-id.stamp`. An alias that is not bound by the
+/// expansion itself is looked up as `name` in the environment where the
+/// macro was defined, which is what keeps the expansion hygienic.
+const ALIAS_MARK: char = '\u{1}';
+
+/// How many transformers are created between two cleanups of `MACRO_ENVS`.
+const MACRO_ENVS_CLEANUP: usize = 64;
+
+thread_local! {
+    /// Definition environments of the `syntax-rules` transformers, by
+    /// `MacroData::id`. The environments are not kept alive by this, the
+    /// expansions are always evaluated inside of them.
+    static MACRO_ENVS: RefCell<HashMap<usize, WeakEnvRef>> = RefCell::new(HashMap::new());
+    static NEXT_MACRO_ID: Cell<usize> = Cell::new(0);
+    /// Every expansion gets a fresh stamp so that identifiers introduced
+    /// by two different expansions never clash.
+    static STAMP: Cell<usize> = Cell::new(0);
+}
+
+/// A `syntax-rules` transformer. Macro uses are expanded by the evaluator
+/// when it finds a keyword bound to a transformer, since `let-syntax` and
+/// internal `define-syntax` bindings are only known at run time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacroData {
+    id: usize,
+    ellipsis: Option<String>,
+    literals: Vec<String>,
+    rules: Vec<(SExpr, SExpr)>,
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    One(SExpr),
+    Many(Vec<Binding>)
+}
+
+type Bindings = HashMap<String, Binding>;
+
+impl MacroData {
+    /// Creates a transformer from the arguments of a `syntax-rules` form:
+    /// `([ellipsis] (literal ...) (pattern template) ...)`
+    pub fn new(spec: SExprs, env: &EnvRef) -> SResult<MacroData> {
+        let mut iter = spec.into_iter().peekable();
+        let ellipsis = match iter.peek() {
+            Some(SExpr::Atom(Token::Symbol(_))) => Some(iter.next().unwrap().into_symbol()?),
+            _ => None
+        };
+
+        let literals = iter.next()
+            .ok_or_else(|| SErr::new_generic("syntax-rules: expected a literal list"))?
+            .into_list()?
+            .into_iter()
+            .map(|x| x.into_symbol())
+            .collect::<SResult<_>>()?;
+
+        let rules = iter
+            .map(|rule| {
+                let mut parts = rule.into_list()?.into_iter();
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(pattern), Some(template), None) => Ok((pattern, template)),
+                    (x, y, _) => bail!(UnexpectedForm => SExpr::list(x.into_iter().chain(y).collect()))
+                }
+            })
+            .collect::<SResult<_>>()?;
+
+        let id = NEXT_MACRO_ID.with(|x| {
+            x.set(x.get() + 1);
+            x.get()
+        });
+        MACRO_ENVS.with(|envs| {
+            let mut envs = envs.borrow_mut();
+            // Forget the environments that are gone now and then
+            if id % MACRO_ENVS_CLEANUP == 0 {
+                envs.retain(|_, env| env.upgrade().is_some());
+            }
+            envs.insert(id, env.downgrade());
+        });
+
+        Ok(MacroData { id, ellipsis, literals, rules })
+    }
+
+    /// Expands a macro use. `form` is the whole use, including the keyword.
+    pub fn expand(&self, form: &SExpr) -> SResult<SExpr> {
+        let stamp = STAMP.with(|s| {
+            s.set(s.get() + 1);
+            s.get()
+        });
+
+        for (pattern, template) in &self.rules {
+            let mut binds = Bindings::new();
+            if self.match_form(pattern, form, &mut binds) {
+                return self.expand_template(template, &binds, stamp, false)
+            }
+        }
+
+        bail!("No matching syntax rule for: {}", form)
+    }
+
+    //
+    // Matching
+    //
+    fn match_form(&self, pattern: &SExpr, form: &SExpr, binds: &mut Bindings) -> bool {
+        // The keyword position of the pattern is ignored.
+        match (split_list(pattern), split_list(form)) {
+            (Some((ps, pt)), Some((xs, t))) if !ps.is_empty() && !xs.is_empty() =>
+                self.match_list(&ps[1..], pt.as_ref(), &xs[1..], t.as_ref(), binds),
+            _ => false
+        }
+    }
+
+    fn match_pattern(&self, pattern: &SExpr, form: &SExpr, binds: &mut Bindings) -> bool {
+        match pattern {
+            SExpr::Atom(Token::Symbol(p)) => {
+                if base_name(p) == "_" {
+                    true
+                } else if self.literals.iter().any(|x| **x == **p) {
+                    match form {
+                        SExpr::Atom(Token::Symbol(x)) => base_name(x) == base_name(p),
+                        _ => false
+                    }
+                } else {
+                    binds.insert(p.to_string(), Binding::One(form.clone()));
+                    true
+                }
+            },
+            SExpr::Nil | SExpr::Pair(_) => match (split_list(pattern), split_list(form)) {
+                (Some((ps, pt)), Some((xs, t))) =>
+                    self.match_list(&ps, pt.as_ref(), &xs, t.as_ref(), binds),
+                _ => false
+            },
+            SExpr::Vector(ps) => match form {
+                SExpr::Vector(xs) => self.match_list(&ps.borrow(), None, &xs.borrow(), None, binds),
+                _ => false
+            },
+            x => x == form
+        }
+    }
+
+    /// Matches the elements `xs` (followed by the tail `t`, `None` meaning
+    /// the empty list) against the element patterns `ps` (followed by the
+    /// tail pattern `pt`).
+    fn match_list(&self, ps: &[SExpr], pt: Option<&SExpr>,
+                  xs: &[SExpr], t: Option<&SExpr>, binds: &mut Bindings) -> bool {
+        let ellipsis_pos = ps.iter().position(|p| self.is_ellipsis(p));
+
+        match ellipsis_pos {
+            Some(0) => false,
+            Some(pos) => {
+                let before = &ps[..pos - 1];
+                let repeated = &ps[pos - 1];
+                let after = &ps[pos + 1..];
+                if xs.len() < before.len() + after.len() {
+                    return false
+                }
+
+                let repeat_count = xs.len() - before.len() - after.len();
+                let matches_before = before.iter().zip(xs)
+                    .all(|(p, x)| self.match_pattern(p, x, binds));
+                if !matches_before {
+                    return false
+                }
+
+                let mut matched = vec![];
+                for x in &xs[before.len()..before.len() + repeat_count] {
+                    let mut inner = Bindings::new();
+                    if !self.match_pattern(repeated, x, &mut inner) {
+                        return false
+                    }
+                    matched.push(inner);
+                }
+
+                let mut vars = vec![];
+                self.pattern_vars(repeated, &mut vars);
+                for var in vars {
+                    let seq = matched.iter_mut()
+                        .map(|m| m.remove(&var).unwrap())
+                        .collect();
+                    binds.insert(var, Binding::Many(seq));
+                }
+
+                let matches_after = after.iter().zip(&xs[before.len() + repeat_count..])
+                    .all(|(p, x)| self.match_pattern(p, x, binds));
+
+                matches_after && self.match_tail(pt, &[], t, binds)
+            },
+            None => {
+                if xs.len() < ps.len() || (pt.is_none() && xs.len() != ps.len()) {
+                    return false
+                }
+
+                let matches = ps.iter().zip(xs)
+                    .all(|(p, x)| self.match_pattern(p, x, binds));
+
+                matches && self.match_tail(pt, &xs[ps.len()..], t, binds)
+            }
+        }
+    }
+
+    /// Matches what is left of a form after the element patterns are
+    /// consumed, `rest` followed by the tail `t`, against a tail pattern.
+    fn match_tail(&self, pt: Option<&SExpr>, rest: &[SExpr],
+                  t: Option<&SExpr>, binds: &mut Bindings) -> bool {
+        let remaining = match (rest.is_empty(), t) {
+            (true, None) => SExpr::Nil,
+            (true, Some(t)) => t.clone(),
+            (false, None) => SExpr::list(rest.to_vec()),
+            (false, Some(t)) => SExpr::dottedlist(rest.to_vec(), t.clone()),
+        };
+
+        match pt {
+            Some(pt) => self.match_pattern(pt, &remaining, binds),
+            None => remaining.is_nil()
+        }
+    }
+
+    fn pattern_vars(&self, pattern: &SExpr, vars: &mut Vec<String>) {
+        match pattern {
+            SExpr::Atom(Token::Symbol(p)) => {
+                if base_name(p) != "_" && !self.literals.iter().any(|x| **x == **p) && !self.is_ellipsis(pattern) {
+                    vars.push(p.to_string());
+                }
+            },
+            SExpr::Pair(_) => if let Some((ps, pt)) = split_list(pattern) {
+                ps.iter().for_each(|p| self.pattern_vars(p, vars));
+                pt.iter().for_each(|p| self.pattern_vars(p, vars));
+            },
+            SExpr::Vector(ps) => ps.borrow().iter().for_each(|p| self.pattern_vars(p, vars)),
+            _ => ()
+        }
+    }
+
+    //
+    // Expansion
+    //
+    fn expand_template(&self, template: &SExpr, binds: &Bindings, stamp: usize, escaped: bool) -> SResult<SExpr> {
+        match template {
+            SExpr::Atom(Token::Symbol(x)) => match binds.get(&**x) {
+                Some(Binding::One(form)) => Ok(form.clone()),
+                Some(Binding::Many(_)) => bail!("Pattern variable used without an ellipsis: {}", base_name(x)),
+                None if self.is_ellipsis(template) && !escaped => bail!("Misplaced ellipsis in template"),
+                None => Ok(SExpr::Atom(Token::Symbol(self.alias(x, stamp).into())))
+            },
+            SExpr::Pair(_) => {
+                let (ts, tail) = template.list_parts()?;
+                if !escaped && tail.is_nil() && ts.len() == 2 && self.is_ellipsis(&ts[0]) {
+                    // (... template) escapes the ellipsis
+                    return self.expand_template(&ts[1], binds, stamp, true)
+                }
+
+                let xs = self.expand_elements(&ts, binds, stamp, escaped)?;
+                Ok(SExpr::dottedlist(xs, self.expand_template(&tail, binds, stamp, escaped)?))
+            },
+            SExpr::Vector(ts) => {
+                let xs = self.expand_elements(&ts.borrow(), binds, stamp, escaped)?;
+                Ok(SExpr::Vector(new_rc_ref_cell(xs)))
+            },
+            x => Ok(x.clone())
+        }
+    }
+
+    fn expand_elements(&self, ts: &[SExpr], binds: &Bindings, stamp: usize, escaped: bool) -> SResult<SExprs> {
+        let mut result = vec![];
+        let mut i = 0;
+        while i < ts.len() {
+            let mut depth = 0;
+            while !escaped && ts.get(i + depth + 1).map_or(false, |t| self.is_ellipsis(t)) {
+                depth += 1;
+            }
+
+            if depth == 0 {
+                result.push(self.expand_template(&ts[i], binds, stamp, escaped)?);
+            } else {
+                result.append(&mut self.expand_repeated(&ts[i], binds, stamp, depth)?);
+            }
+
+            i += depth + 1;
+        }
+
+        Ok(result)
+    }
+
+    /// Expands a subtemplate followed by `depth` ellipses.
+    fn expand_repeated(&self, template: &SExpr, binds: &Bindings, stamp: usize, depth: usize) -> SResult<SExprs> {
+        if depth == 0 {
+            return Ok(vec![self.expand_template(template, binds, stamp, false)?])
+        }
+
+        let mut vars = vec![];
+        self.pattern_vars(template, &mut vars);
+        let seqs = vars.iter()
+            .filter_map(|var| match binds.get(var) {
+                Some(Binding::Many(seq)) => Some((var, seq)),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+
+        if seqs.is_empty() {
+            bail!("No pattern variable to repeat in template: {}", template)
+        }
+
+        let len = seqs[0].1.len();
+        if seqs.iter().any(|(_, seq)| seq.len() != len) {
+            bail!("Pattern variables of unequal length are repeated together in: {}", template)
+        }
+
+        let mut result = vec![];
+        for i in 0..len {
+            let mut inner = binds.clone();
+            for (var, seq) in &seqs {
+                inner.insert((*var).clone(), seq[i].clone());
+            }
+            result.append(&mut self.expand_repeated(template, &inner, stamp, depth - 1)?);
+        }
+
+        Ok(result)
+    }
+
+    //
+    // Helpers
+    //
+    fn is_ellipsis(&self, x: &SExpr) -> bool {
+        match self.ellipsis {
+            Some(ref e) => x.as_symbol().map_or(false, |s| s == e),
+            None => x.is_ellipsis()
+        }
+    }
+
+    fn alias(&self, name: &str, stamp: usize) -> String {
+        format!("{}{}{}.{}", name, ALIAS_MARK, self.id, stamp)
+    }
+}
+
+/// Splits a list into its elements and its tail, `None` meaning the empty
+/// list. Returns `None` if `x` is not a list at all.
+fn split_list(x: &SExpr) -> Option<(SExprs, Option<SExpr>)> {
+    match x {
+        SExpr::Nil | SExpr::Pair(_) => {
+            let (xs, tail) = x.list_parts().ok()?;
+            Some((xs, if tail.is_nil() { None } else { Some(tail) }))
+        },
+        _ => None
+    }
+}
+
+/// Returns the name that the user wrote for a (possibly renamed) symbol.
+pub fn base_name(name: &str) -> &str {
+    match name.find(ALIAS_MARK) {
+        Some(pos) => &name[..pos],
+        None => name
+    }
+}
+
+/// Strips one level of renaming from an alias and returns the renamed
+/// symbol together with the environment the introducing macro was defined in.
+pub fn resolve_alias(name: &str) -> Option<(&str, EnvRef)> {
+    let pos = name.rfind(ALIAS_MARK)?;
+    let id = name[pos + 1..].split('.')
+        .next()?
+        .parse::<usize>()
+        .ok()?;
+
+    MACRO_ENVS.with(|envs| {
+        envs.borrow()
+            .get(&id)
+            .and_then(|env| env.upgrade())
+            .map(|env| (&name[..pos], env))
+    })
+}
+
+/// Converts renamed symbols back into plain ones, used when syntax becomes
+/// data through `quote` or `quasiquote`.
+pub fn strip_syntax(sexpr: SExpr) -> SExpr {
+    match sexpr {
+        SExpr::Atom(Token::Symbol(ref x)) if x.contains(ALIAS_MARK) => {
+            SExpr::Atom(Token::Symbol(base_name(x).into()))
+        },
+        // Lists without renamed symbols are kept as they are, so that
+        // quoted data keeps its identity.
+        ref x@SExpr::Pair(_) if has_alias(x) => {
+            let (xs, y) = x.list_parts().unwrap();
+            SExpr::dottedlist(xs.into_iter().map(strip_syntax).collect(), strip_syntax(y))
+        },
+        ref x@SExpr::Vector(_) if has_alias(x) => {
+            let xs = x.as_vector().unwrap().borrow().clone();
+            SExpr::Vector(new_rc_ref_cell(xs.into_iter().map(strip_syntax).collect()))
+        },
+        x => x
+    }
+}
+
+fn has_alias(sexpr: &SExpr) -> bool {
+    has_alias_in(sexpr, &mut HashSet::new())
+}
+
+/// `seen` keeps the lists and vectors that are already looked into, data
+/// read with datum labels can contain itself.
+fn has_alias_in(sexpr: &SExpr, seen: &mut HashSet<usize>) -> bool {
+    match sexpr {
+        SExpr::Atom(Token::Symbol(x)) => x.contains(ALIAS_MARK),
+        SExpr::Pair(x) if !seen.insert(x.id()) => false,
+        SExpr::Pair(_) => match sexpr.list_parts() {
+            Ok((xs, y)) => xs.iter().any(|x| has_alias_in(x, seen)) || has_alias_in(&y, seen),
+            // Circular data is never produced by a macro expansion.
+            Err(_) => false
+        },
+        SExpr::Vector(xs) if !seen.insert(&*xs.borrow() as *const _ as usize) => false,
+        SExpr::Vector(xs) => xs.borrow().iter().any(|x| has_alias_in(x, seen)),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use env::{Env, EnvRef};
+    use lexer::{tokenize, Source};
+    use parser::{SExpr, parse};
+    use primitives;
+    use super::{MacroData, resolve_alias};
+
+    fn eval_str(code: &str) -> String {
+        let env = Env::with_values(EnvRef::null(), primitives::env()).into_ref();
+        primitives::load_prelude(&env).unwrap();
+        parse(tokenize(&Source::new("test", code)).unwrap()).unwrap()
+            .into_iter()
+            .map(|(sexpr, span)| sexpr.eval_at(&span, &env).unwrap())
+            .last()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn verify_hygiene() {
+        assert_eq!(eval_str("
+            (define-syntax swap!
+              (syntax-rules ()
+                ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))
+            (define tmp 1)
+            (define other 2)
+            (swap! tmp other)
+            (list tmp other)"), "(2 1)");
+
+        assert_eq!(eval_str("
+            (define-syntax my-or
+              (syntax-rules ()
+                ((_) #f)
+                ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))
+            (define t 5)
+            (my-or #f t)"), "5");
+    }
+
+    #[test]
+    fn verify_ellipsis() {
+        assert_eq!(eval_str("
+            (define-syntax flatten
+              (syntax-rules ()
+                ((_ (a b ...) ...) '(a ... b ... ...))))
+            (flatten (1 2 3) (4 5 6))"), "(1 4 2 3 5 6)");
+
+        assert_eq!(eval_str("
+            (define-syntax be-like-begin
+              (syntax-rules ()
+                ((_ name)
+                 (define-syntax name
+                   (syntax-rules ()
+                     ((name expr (... ...)) (begin expr (... ...))))))))
+            (be-like-begin sequence)
+            (sequence 1 2 3)"), "3");
+    }
+
+    #[test]
+    fn verify_vector_patterns() {
+        assert_eq!(eval_str("
+            (define-syntax last-first
+              (syntax-rules ()
+                ((_ #(a ... z)) '(z a ...))
+                ((_ x) 'no-match)))
+            (list (last-first #(1 2 3)) (last-first #(1)) (last-first #()) (last-first (1 2)))"),
+                   "((3 1 2) (1) no-match no-match)");
+
+        assert_eq!(eval_str("
+            (define-syntax rows
+              (syntax-rules ()
+                ((_ #(#(a ...) ...)) '((a ...) ...))))
+            (rows #(#(1 2) #(3)))"), "((1 2) (3))");
+    }
+
+    #[test]
+    fn verify_literals_and_local_macros() {
+        assert_eq!(eval_str("
+            (define-syntax my-if
+              (syntax-rules (then else)
+                ((_ c then a else b) (if c a b))))
+            (my-if #f then 1 else 2)"), "2");
+
+        assert_eq!(eval_str("
+            (define x 1)
+            (let-syntax ((get-x (syntax-rules () ((_) x))))
+              (let ((x 2))
+                (get-x)))"), "1");
+    }
+
+    #[test]
+    fn verify_macro_envs_are_released() {
+        let env = Env::new(EnvRef::null()).into_ref();
+        let transformer = MacroData::new(vec![SExpr::Nil], &env).unwrap();
+        let alias = transformer.alias("x", 0);
+        assert!(resolve_alias(&alias).is_some());
+
+        drop(env);
+        assert!(resolve_alias(&alias).is_none());
+    }
 }
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::rc::Rc;
+use std::cmp::Ordering;
+
+use lexer::Token;
+use parser::SExpr;
+use primitives::equivalence::eqv;
+use procedure::ProcedureData;
+use utils::{new_rc_ref_cell, RcRefCell};
+
+/// How many objects inside a list or a vector are hashed for `equal?`
+/// tables. Only bounds the work, the keys are still compared fully.
+const HASH_BUDGET: usize = 32;
+
+/// The equivalence that a hash table compares its keys with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Equivalence {
+    Eqv,
+    Equal,
+    /// `=`, which `string=?` is the same procedure as. Numbers are equal
+    /// if they have the same value, the other keys are compared like
+    /// `equal?` does.
+    Numeric,
+}
+
+/// A mutable hash table, shared like the other compound objects.
+#[derive(Debug, Clone)]
+pub struct HashTableData(RcRefCell<HashTable>);
+
+#[derive(Debug, Clone)]
+pub struct HashTable {
+    equivalence: Equivalence,
+    buckets: HashMap<u64, Vec<(SExpr, SExpr)>, BuildHasherDefault<DefaultHasher>>,
+    len: usize,
+}
+
+impl PartialEq for HashTableData {
+    fn eq(&self, other: &HashTableData) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl HashTableData {
+    pub fn new(equivalence: Equivalence) -> HashTableData {
+        HashTableData(new_rc_ref_cell(HashTable {
+            equivalence,
+            buckets: HashMap::default(),
+            len: 0
+        }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len
+    }
+
+    /// A number that identifies the table as long as it is alive.
+    pub fn id(&self) -> usize {
+        &*self.0 as *const _ as usize
+    }
+
+    pub fn get(&self, key: &SExpr) -> Option<SExpr> {
+        let table = self.0.borrow();
+        table.buckets.get(&table.hash(key))?
+            .iter()
+            .find(|(k, _)| table.same(k, key))
+            .map(|(_, v)| v.clone())
+    }
+
+    pub fn insert(&self, key: SExpr, value: SExpr) {
+        let table = &mut *self.0.borrow_mut();
+        let hash = table.hash(&key);
+        let equivalence = table.equivalence;
+        let bucket = table.buckets.entry(hash).or_insert_with(Vec::new);
+
+        match bucket.iter_mut().find(|(k, _)| same(equivalence, k, &key)) {
+            Some(entry) => entry.1 = value,
+            None => {
+                bucket.push((key, value));
+                table.len += 1;
+            }
+        }
+    }
+
+    /// Removes the key, returns false if there was no such key.
+    pub fn remove(&self, key: &SExpr) -> bool {
+        let table = &mut *self.0.borrow_mut();
+        let hash = table.hash(key);
+        let equivalence = table.equivalence;
+
+        let removed = match table.buckets.get_mut(&hash) {
+            Some(bucket) => {
+                let len = bucket.len();
+                bucket.retain(|(k, _)| !same(equivalence, k, key));
+                len != bucket.len()
+            },
+            None => false
+        };
+
+        if removed {
+            table.len -= 1;
+            if table.buckets[&hash].is_empty() {
+                table.buckets.remove(&hash);
+            }
+        }
+
+        removed
+    }
+
+    pub fn clear(&self) {
+        let table = &mut *self.0.borrow_mut();
+        table.buckets.clear();
+        table.len = 0;
+    }
+
+    pub fn entries(&self) -> Vec<(SExpr, SExpr)> {
+        self.0.borrow()
+            .buckets
+            .values()
+            .flat_map(|bucket| bucket.iter().cloned())
+            .collect()
+    }
+
+    /// Creates a new table with the same contents.
+    pub fn copy(&self) -> HashTableData {
+        HashTableData(new_rc_ref_cell(self.0.borrow().clone()))
+    }
+}
+
+impl HashTable {
+    fn hash(&self, key: &SExpr) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let mut budget = HASH_BUDGET;
+        match key.clone().into_float() {
+            // 1, 1.0 and 2/2 are the same number
+            Ok(x) if self.equivalence == Equivalence::Numeric => (if x == 0.0 { 0 } else { x.to_bits() }).hash(&mut hasher),
+            _ => hash_sexpr(key, self.equivalence != Equivalence::Eqv, &mut budget, &mut hasher)
+        }
+        hasher.finish()
+    }
+
+    fn same(&self, x: &SExpr, y: &SExpr) -> bool {
+        same(self.equivalence, x, y)
+    }
+}
+
+fn same(equivalence: Equivalence, x: &SExpr, y: &SExpr) -> bool {
+    match equivalence {
+        Equivalence::Eqv => eqv(x, y),
+        Equivalence::Equal => x == y,
+        Equivalence::Numeric if x.is_numeric() && y.is_numeric() => x.partial_cmp(y) == Some(Ordering::Equal),
+        Equivalence::Numeric => x == y,
+    }
+}
+
+/// Hashes `x` so that the keys that are the same for the given
+/// equivalence get the same hash. With `deep`, the contents of lists and
+/// vectors are hashed instead of their identities.
+fn hash_sexpr<H: Hasher>(x: &SExpr, deep: bool, budget: &mut usize, state: &mut H) {
+    if *budget == 0 {
+        return
+    }
+    *budget -= 1;
+
+    match x {
+        SExpr::Atom(token) => match token {
+            Token::Symbol(x) => x.hash(state),
+            Token::Integer(x) => x.hash(state),
+            Token::BigInt(x) => x.hash(state),
+            Token::Fraction(x) => (&x.n, &x.d).hash(state),
+            // 0.0 and -0.0 are the same number
+            Token::Float(x) => (if *x == 0.0 { 0 } else { x.to_bits() }).hash(state),
+            Token::Complex(x) => (x.re.to_bits(), x.im.to_bits()).hash(state),
+            Token::Boolean(x) => x.hash(state),
+            Token::Chr(x) => x.hash(state),
+            Token::Str(x) => x.borrow().hash(state),
+            _ => 0.hash(state),
+        },
+        SExpr::Nil => 1.hash(state),
+        SExpr::Pair(x) if deep => {
+            hash_sexpr(&x.car(), deep, budget, state);
+            hash_sexpr(&x.cdr(), deep, budget, state);
+        },
+        SExpr::Pair(x) => x.id().hash(state),
+        SExpr::Vector(xs) if deep => for x in xs.borrow().iter() {
+            if *budget == 0 {
+                break
+            }
+            hash_sexpr(x, deep, budget, state);
+        },
+        SExpr::Vector(xs) => (&*xs.borrow() as *const _ as usize).hash(state),
+        SExpr::Bytevector(xs) if deep => xs.borrow().hash(state),
+        SExpr::Bytevector(xs) => (&*xs.borrow() as *const _ as usize).hash(state),
+        x => match identity(x) {
+            Some(id) => id.hash(state),
+            // The rest are compared by their contents, like compound
+            // procedures, so they're hashed to the same value and told
+            // apart by the equivalence.
+            None => 2.hash(state)
+        }
+    }
+}
+
+/// Objects that are the same only if they are the same object, identified
+/// by their addresses.
+fn identity(x: &SExpr) -> Option<usize> {
+    match x {
+        SExpr::Record(x) => Some(&*x.values as *const _ as usize),
+        SExpr::RecordType(x) => Some(x.id()),
+        SExpr::HashTable(x) => Some(x.id()),
+        SExpr::Condition(x) => Some(&*x.0 as *const _ as usize),
+        SExpr::Procedure(ProcedureData::Primitive(x)) => Some(x.id()),
+        SExpr::Procedure(ProcedureData::Continuation(x)) => Some(&*x.k as *const _ as usize),
+        SExpr::Procedure(ProcedureData::Parameter(x)) => Some(&*x.value as *const _ as usize),
+        _ => None
+    }
+}
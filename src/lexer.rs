@@ -1,10 +1,17 @@
 use std::iter::Peekable;
 use std::cmp::Ordering;
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
 use utils::{new_rc_ref_cell, RcRefCell};
+use serr::{SErr, SResult};
 
 use utils::GentleIterator;
 use utils::AndOr;
 use utils::fraction::Fraction;
+use utils::bigint::BigInt;
+use utils::complex::Complex;
+use utils::radix;
 
 // TODO: string.parse::<Token>();
 
@@ -12,10 +19,25 @@ use utils::fraction::Fraction;
 pub enum Token {
     LParen,
     RParen,
-    Symbol(String),
+    /// `#(`, starts a vector literal.
+    VectorOpener,
+    /// `#u8(`, starts a bytevector literal.
+    BytevectorOpener,
+    /// `#;`, the datum after it is skipped.
+    DatumComment,
+    /// `#n=`, labels the datum after it.
+    DatumLabel(usize),
+    /// `#n#`, refers to the datum labeled with `#n=`.
+    DatumReference(usize),
+    /// Shared, since symbols are copied every time a form is evaluated.
+    Symbol(Rc<str>),
     Integer(i64),
+    /// Integers that don't fit in an `i64`.
+    BigInt(BigInt),
     Fraction(Fraction),
     Float(f64),
+    /// Complex numbers with a non-zero imaginary part.
+    Complex(Complex),
     Boolean(bool),
     Chr(char),
     Str(RcRefCell<String>),
@@ -33,13 +55,17 @@ impl PartialOrd for Token {
         match (self, other) {
             (Integer(x), Integer(y)) => x.partial_cmp(y),
             (Float(x), Float(y)) => x.partial_cmp(y),
-            (Fraction(x), Fraction(y)) => x.partial_cmp(y),
-            (Integer(x), Float(y)) => (*x as f64).partial_cmp(y),
-            (Float(x), Integer(y)) => x.partial_cmp(&(*y as f64)),
-            (Integer(x), Fraction(y)) => (*x as f64).partial_cmp(&(*y).into()),
-            (Fraction(x), Integer(y)) => f64::from(*x).partial_cmp(&(*y as f64)),
-            (Float(x), Fraction(y)) => x.partial_cmp(&(*y).into()),
-            (Fraction(x), Float(y)) => f64::from(*x).partial_cmp(y),
+            // Complex numbers are only equal or not
+            (Complex(x), Complex(y)) if x == y => Some(Ordering::Equal),
+            (Complex(_), _) | (_, Complex(_)) => None,
+            // Floats are compared by their exact values, so that
+            // comparisons stay transitive
+            (Float(x), y) => y.as_rational().and_then(|y| match ::utils::fraction::Fraction::from_f64(*x) {
+                Some(x) => x.partial_cmp(&y),
+                None => x.partial_cmp(&0.0)
+            }),
+            (x, Float(_)) => other.partial_cmp(x).map(Ordering::reverse),
+            (x, y) if x.is_exact() && y.is_exact() => x.as_rational().partial_cmp(&y.as_rational()),
 
             (Str(x), Str(y)) => x.partial_cmp(y),
             (Chr(x), Chr(y)) => x.partial_cmp(y),
@@ -52,6 +78,49 @@ impl PartialOrd for Token {
 }
 
 impl Token {
+    /// Makes an integer token, `i64` if the number fits in one.
+    pub fn integer(x: BigInt) -> Token {
+        match x.to_i64() {
+            Some(x) => Token::Integer(x),
+            None => Token::BigInt(x)
+        }
+    }
+
+    /// Makes an integer token if the fraction is a whole number.
+    pub fn rational(x: Fraction) -> Token {
+        if x.is_int() {
+            Token::integer(x.n)
+        } else {
+            Token::Fraction(x)
+        }
+    }
+
+    /// Makes a complex token, a float if the imaginary part is zero.
+    pub fn complex(x: Complex) -> Token {
+        if x.im == 0.0 {
+            Token::Float(x.re)
+        } else {
+            Token::Complex(x)
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        match self {
+            Token::Integer(_) | Token::BigInt(_) | Token::Fraction(_) => true,
+            _ => false
+        }
+    }
+
+    /// Returns exact numbers as a fraction.
+    pub fn as_rational(&self) -> Option<Fraction> {
+        match self {
+            Token::Integer(x) => Some(Fraction::from(*x)),
+            Token::BigInt(x) => Some(Fraction::from(x.clone())),
+            Token::Fraction(x) => Some(x.clone()),
+            _ => None
+        }
+    }
+
     fn get(chr: char) -> Token {
         match chr {
             '(' | '['  => Token::LParen,
@@ -66,59 +135,162 @@ impl Token {
     }
 }
 
+/// A piece of code that is being read, a file or a line of the REPL.
+/// Kept around so that errors can show the line they happened on.
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: &str, text: &str) -> Rc<Source> {
+        Rc::new(Source { name: name.to_string(), text: text.to_string() })
+    }
+}
+
+/// Where a token starts in its source. Lines and columns start from 1.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub source: Rc<Source>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// The line of the source that the span points to.
+    pub fn source_line(&self) -> Option<&str> {
+        self.source.text.lines().nth(self.line - 1)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source.name, self.line, self.column)
+    }
+}
+
+/// Keeps track of the position of the last char that is taken from
+/// `inner`. The position is shared because the iterator itself is
+/// hidden behind a `Peekable`.
+struct Positioned<I: Iterator<Item=char>> {
+    inner: I,
+    next: (usize, usize),
+    last: Rc<Cell<(usize, usize)>>
+}
+
+impl<I: Iterator<Item=char>> Iterator for Positioned<I> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let chr = self.inner.next()?;
+        self.last.set(self.next);
+        self.next = if chr == '\n' {
+            (self.next.0 + 1, 1)
+        } else {
+            (self.next.0, self.next.1 + 1)
+        };
+
+        Some(chr)
+    }
+}
+
 pub struct TokenIterator<I: Iterator<Item=char>> {
-    inner: Peekable<I>
+    inner: Peekable<Positioned<I>>,
+    position: Rc<Cell<(usize, usize)>>,
+    source: Rc<Source>,
+    fold_case: bool
 }
 
 impl<I: Iterator<Item=char>> TokenIterator<I> {
-    pub fn new(inner: I) -> Self {
+    pub fn new(inner: I, source: Rc<Source>) -> Self {
+        let position = Rc::new(Cell::new((1, 1)));
         TokenIterator {
-            inner: inner.peekable()
+            inner: Positioned { inner, next: (1, 1), last: position.clone() }.peekable(),
+            position,
+            source,
+            fold_case: false
         }
     }
+
+    /// Whether the lexer has taken a char from `inner` to look ahead but
+    /// hasn't used it in a token yet. This takes another char if there
+    /// isn't one waiting, so it is meant for an `inner` that has ended.
+    pub fn has_lookahead(&mut self) -> bool {
+        self.inner.peek().is_some()
+    }
 }
 
 impl<I: Iterator<Item=char>> Iterator for TokenIterator<I> {
-    type Item = Token;
-
-    fn next(&mut self) -> Option<Token> {
-        tokenize_single(&mut self.inner)
+    type Item = SResult<(Token, Span)>;
+
+    fn next(&mut self) -> Option<SResult<(Token, Span)>> {
+        loop {
+            // Skipping peeks the first char of the token, so the last
+            // position is where the token starts.
+            skip_atmosphere(&mut self.inner);
+            let (line, column) = self.position.get();
+            let span = Span { source: self.source.clone(), line, column };
+
+            match tokenize_single(&mut self.inner, &span, self.fold_case) {
+                Ok(Some(Lexeme::Token(token))) => return Some(Ok((token, span))),
+                Ok(Some(Lexeme::Comment)) => continue,
+                Ok(Some(Lexeme::FoldCase(x))) => self.fold_case = x,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e))
+            }
+        }
     }
 }
 
-pub fn tokenize_single<I>(iter: &mut Peekable<I>) -> Option<Token>
+/// What the lexer finds next in the input.
+enum Lexeme {
+    Token(Token),
+    /// A `#| ... |#` comment, skipped like whitespace.
+    Comment,
+    /// `#!fold-case` or `#!no-fold-case`
+    FoldCase(bool),
+}
+
+/// Reads the next lexeme, `span` is where it starts and only used for
+/// errors. Returns `None` at the end of the input.
+fn tokenize_single<I>(iter: &mut Peekable<I>, span: &Span, fold_case: bool) -> SResult<Option<Lexeme>>
 where I: Iterator<Item = char> {
-    while parse_whitespace(iter) || parse_comment(iter) {
-        continue
-    }
+    skip_atmosphere(iter);
 
-    parse_lparen(iter)
+    let lexeme = parse_lparen(iter)
         .or_else(|| parse_quote(iter))
         .or_else(|| parse_unquote(iter))
         .or_else(|| parse_quasiquote(iter))
         .or_else(|| parse_rparen(iter))
-        .or_else(|| parse_string(iter))
-        .or_else(|| parse_hash(iter))
-        .or_else(|| parse_symbol(iter))
-}
-
-pub fn tokenize<I>(iter: &mut Peekable<I>) -> Vec<Token>
-where I: Iterator<Item = char> {
-    let mut tokens: Vec<Token> = vec![];
+        .map(Ok)
+        .or_else(|| parse_string(iter, span))
+        .or_else(|| parse_symbol(iter, span, fold_case))
+        .map(|token| token.map(Lexeme::Token))
+        .or_else(|| parse_hash(iter, span, fold_case));
 
-    while let Some(x) = tokenize_single(iter) {
-        tokens.push(x)
-    }
+    lexeme.transpose()
+}
 
-    tokens
+pub fn tokenize(source: &Rc<Source>) -> SResult<Vec<(Token, Span)>> {
+    TokenIterator::new(source.text.chars(), source.clone())
+        .collect()
 }
 
 //
 // Parsers
 //
+/// Skips the whitespace and the line comments.
+fn skip_atmosphere<I>(iter: &mut Peekable<I>)
+where I: Iterator<Item = char> {
+    while parse_whitespace(iter) || parse_comment(iter) {
+        continue
+    }
+}
+
 fn parse_whitespace<I>(iter: &mut Peekable<I>) -> bool
 where I: Iterator<Item = char> {
-    if check_chr(iter, ' ') || check_chr(iter, '\n') || check_chr(iter, '\t') {
+    if check(iter, |c| c.is_whitespace()) {
         iter.next();
         true
     } else {
@@ -136,6 +308,29 @@ where I: Iterator<Item = char> {
     }
 }
 
+/// Skips a `#| ... |#` comment, the `#|` is already consumed. These
+/// comments can be nested.
+fn parse_block_comment<I>(iter: &mut Peekable<I>, span: &Span) -> SResult<Lexeme>
+where I: Iterator<Item = char> {
+    let mut depth = 1;
+    while depth > 0 {
+        match iter.next() {
+            Some('|') if check_chr(iter, '#') => {
+                iter.next();
+                depth -= 1;
+            },
+            Some('#') if check_chr(iter, '|') => {
+                iter.next();
+                depth += 1;
+            },
+            Some(_) => (),
+            None => return read_error(span, "|#", None)
+        }
+    }
+
+    Ok(Lexeme::Comment)
+}
+
 fn parse_quote<I>(iter: &mut Peekable<I>) -> Option<Token>
 where I: Iterator<Item = char> {
     parse_single(iter, '\'')
@@ -164,67 +359,215 @@ where I: Iterator<Item = char> {
         .or_else(|| parse_single(iter, ']'))
 }
 
-fn parse_string<I>(iter: &mut Peekable<I>) -> Option<Token>
+fn parse_string<I>(iter: &mut Peekable<I>, span: &Span) -> Option<SResult<Token>>
 where I: Iterator<Item = char> {
-    // FIXME: check escape chars
     if !check_chr(iter, '"') {
         return None
     }
 
     iter.next(); // Consume the opening "
-    let value = iter
-        .take_until(|c| *c != '"')
-        .collect();
-    iter.next(); // Consume the closing "
-    Some(Token::Str(new_rc_ref_cell(value)))
+    Some(parse_delimited(iter, span, '"')
+         .map(|value| Token::Str(new_rc_ref_cell(value))))
+}
+
+/// Reads the rest of a string or a `|symbol|` until the closing
+/// `delimiter`, handling the escapes.
+fn parse_delimited<I>(iter: &mut Peekable<I>, span: &Span, delimiter: char) -> SResult<String>
+where I: Iterator<Item = char> {
+    let mut value = String::new();
+    loop {
+        match iter.next() {
+            Some('\\') => if let Some(c) = parse_escape(iter, span)? {
+                value.push(c);
+            },
+            Some(c) if c == delimiter => return Ok(value),
+            Some(c) => value.push(c),
+            None => return read_error(span, &delimiter.to_string(), None)
+        }
+    }
 }
 
-fn parse_hash<I>(iter: &mut Peekable<I>) -> Option<Token>
+/// Reads an escape sequence, the `\` is already consumed. Returns `None`
+/// for a line continuation.
+fn parse_escape<I>(iter: &mut Peekable<I>, span: &Span) -> SResult<Option<char>>
+where I: Iterator<Item = char> {
+    let chr = match iter.next() {
+        Some('a') => '\x07',
+        Some('b') => '\x08',
+        Some('t') => '\t',
+        Some('n') => '\n',
+        Some('r') => '\r',
+        Some('x') => {
+            let hex: String = iter.take_until(|c| c.is_ascii_hexdigit()).collect();
+            match iter.next() {
+                Some(';') => (),
+                Some(c) => return read_error(span, ";", Some(&c.to_string())),
+                None => return read_error(span, ";", None)
+            }
+
+            match hex_char(&hex) {
+                Some(c) => c,
+                None => return read_error(span, "a hex scalar value", Some(&format!("\\x{};", hex)))
+            }
+        },
+        // \<whitespace><newline><whitespace> is a line continuation
+        Some(c) if c.is_whitespace() => {
+            let mut newline = c == '\n';
+            while !newline && check(iter, |c| c.is_whitespace()) {
+                newline = iter.next() == Some('\n');
+            }
+            if !newline {
+                return read_error(span, "a newline after \\", None)
+            }
+
+            iter.take_until(|c| *c != '\n' && c.is_whitespace());
+            return Ok(None)
+        },
+        Some(c@'"') | Some(c@'\\') | Some(c@'|') => c,
+        Some(c) => return read_error(span, "an escape sequence", Some(&format!("\\{}", c))),
+        None => return read_error(span, "an escape sequence", None)
+    };
+
+    Ok(Some(chr))
+}
+
+fn parse_hash<I>(iter: &mut Peekable<I>, span: &Span, fold_case: bool) -> Option<SResult<Lexeme>>
 where I: Iterator<Item = char> {
     if !check_chr(iter, '#') {
         return None
     }
 
     iter.next(); // Consume #
-    match iter.next() {
-        Some('t') => Some(Token::Boolean(true)),  // #t means true
-        Some('f') => Some(Token::Boolean(false)), // #f means false
+    let token = match iter.peek() {
+        Some('(') => {
+            iter.next();
+            Token::VectorOpener
+        },
+        Some(';') => {
+            iter.next();
+            Token::DatumComment
+        },
+        Some('|') => {
+            iter.next();
+            return Some(parse_block_comment(iter, span))
+        },
         Some('\\') => {
-            // #\a represents char 'a'
-            // #\b represents char 'b'
-            // ...
-            let value = iter.next()
-                .expect("Expected a char, got nothing.");
-            Some(Token::Chr(value))
+            iter.next();
+            return Some(parse_char(iter, span, fold_case).map(Lexeme::Token))
         },
-        Some('(') => {
-            // Return Token::VectorOpener ?
-            panic!("Not yet implemented.")
-        }
-        Some(c) => {
-            panic!("Expected #t, #f, #(...) or #\\<char> got: #{}", c)
+        Some('!') => {
+            iter.next();
+            let directive: String = iter.take_until(|c| !is_delimiter(*c)).collect();
+            return match directive.as_str() {
+                "fold-case" => Some(Ok(Lexeme::FoldCase(true))),
+                "no-fold-case" => Some(Ok(Lexeme::FoldCase(false))),
+                x => Some(read_error(span, "#!fold-case or #!no-fold-case", Some(&format!("#!{}", x))))
+            }
         },
-        None => {
-            panic!("Expected something , got nothing: ....")
-        }
+        Some(c) if c.is_ascii_digit() => {
+            let digits: String = iter.take_until(|c| c.is_ascii_digit()).collect();
+            let n = match digits.parse() {
+                Ok(n) => n,
+                Err(_) => return Some(read_error(span, "a datum label", Some(&format!("#{}", digits))))
+            };
+
+            match iter.next() {
+                Some('=') => Token::DatumLabel(n),
+                Some('#') => Token::DatumReference(n),
+                Some(c) => return Some(read_error(span, "#<n>= or #<n>#", Some(&format!("#{}{}", digits, c)))),
+                None => return Some(read_error(span, "#<n>= or #<n>#", None))
+            }
+        },
+        Some(_) => {
+            let word: String = iter.take_until(|c| !is_delimiter(*c)).collect();
+            match word.as_str() {
+                "t" | "true" => Token::Boolean(true),
+                "f" | "false" => Token::Boolean(false),
+                "u8" if check_chr(iter, '(') => {
+                    iter.next();
+                    Token::BytevectorOpener
+                },
+                "u8" => return Some(read_error(span, "#u8(", Some("#u8"))),
+                // Numbers with radix or exactness prefixes
+                x if x.starts_with(|c: char| "xXbBoOdDeEiI".contains(c)) => match parse_number(&format!("#{}", x)) {
+                    Some(number) => number,
+                    None => return Some(read_error(span, "a number", Some(&format!("#{}", x))))
+                },
+                x => return Some(read_error(span, "#t, #f, #(, #u8( or #\\<char>", Some(&format!("#{}", x))))
+            }
+        },
+        None => return Some(read_error(span, "#t, #f, #(, #u8( or #\\<char>", None))
+    };
+
+    Some(Ok(Lexeme::Token(token)))
+}
+
+/// Reads a character, the `#\` is already consumed: `#\a`, `#\space`
+/// or `#\x3bb`.
+fn parse_char<I>(iter: &mut Peekable<I>, span: &Span, fold_case: bool) -> SResult<Token>
+where I: Iterator<Item = char> {
+    let first = match iter.next() {
+        Some(c) => c,
+        None => return read_error(span, "a character after #\\", None)
+    };
+
+    let rest: String = iter.take_until(|c| !is_delimiter(*c)).collect();
+    if rest.is_empty() {
+        return Ok(Token::Chr(first))
+    }
+
+    let mut name = format!("{}{}", first, rest);
+    if fold_case {
+        name = name.to_lowercase();
     }
+
+    let chr = match name.as_str() {
+        "alarm" => '\x07',
+        "backspace" => '\x08',
+        "delete" => '\x7f',
+        "escape" => '\x1b',
+        "newline" => '\n',
+        "null" => '\0',
+        "return" => '\r',
+        "space" => ' ',
+        "tab" => '\t',
+        x if x.starts_with('x') => match hex_char(&x[1..]) {
+            Some(c) => c,
+            None => return read_error(span, "a character name", Some(&format!("#\\{}", x)))
+        },
+        x => return read_error(span, "a character name", Some(&format!("#\\{}", x)))
+    };
+
+    Ok(Token::Chr(chr))
 }
 
-fn parse_symbol<I>(iter: &mut Peekable<I>) -> Option<Token>
+fn parse_symbol<I>(iter: &mut Peekable<I>, span: &Span, fold_case: bool) -> Option<SResult<Token>>
 where I: Iterator<Item = char> {
-    // Check if iter is empty or not
-    if !check(iter, |_| true) {
+    // Check if iter is empty or not, # is handled by parse_hash
+    if !check(iter, |c| c != '#') {
         return None
     }
 
+    // |symbol| may have any char in it
+    if check_chr(iter, '|') {
+        iter.next();
+        return Some(parse_delimited(iter, span, '|').map(|x| Token::Symbol(x.into())))
+    }
+
     let value: String = iter
-        .take_until(|c| *c != ' ' && *c != ')' && *c != ']' && *c != '\n')
+        .take_until(|c| !is_delimiter(*c))
         .collect();
 
-    parse_number(&value)
+    let token = parse_number(&value)
         .or_else(|| if value == "..." { Some(Token::Ellipsis) } else { None })
         .or_else(|| if value == "." { Some(Token::Dot) } else { None })
-        .or_else(|| Some(Token::Symbol(value)))
+        .unwrap_or_else(|| if fold_case {
+            Token::Symbol(value.to_lowercase().into())
+        } else {
+            Token::Symbol(value.into())
+        });
+
+    Some(Ok(token))
 }
 
 /// Parse a single char and return the corresponding Token
@@ -239,18 +582,28 @@ where I: Iterator<Item = char> {
 }
 
 pub fn parse_number(value: &str) -> Option<Token> {
-    value.parse::<i64>().map(Token::Integer)
-        .or_else(|_| value.parse::<f64>().map(Token::Float))
-        .or_else(|_| value.parse::<Fraction>().map(|f| {
-            if f.is_int() { Token::Integer(f.n)}
-            else { Token::Fraction(f) }
-        }))
-        .ok()
+    radix::parse_number(value, 10)
 }
 
 //
 // Helper functions
 //
+/// Whitespace and the chars that can't be in a symbol end a token.
+fn is_delimiter(chr: char) -> bool {
+    chr.is_whitespace() || "()[]\";|".contains(chr)
+}
+
+/// Converts a hex scalar value like `3bb` to a char.
+fn hex_char(hex: &str) -> Option<char> {
+    u32::from_str_radix(hex, 16).ok()
+        .and_then(::std::char::from_u32)
+}
+
+/// Creates a read error, `found` is `None` at the end of the input.
+fn read_error<T>(span: &Span, expected: &str, found: Option<&str>) -> SResult<T> {
+    Err(SErr::ReadError(Some(span.clone()), expected.to_string(), found.unwrap_or("end of input").to_string()))
+}
+
 fn check<F,I>(iter: &mut Peekable<I>, fun: F) -> bool
 where F: Fn(char) -> bool,
       I: Iterator<Item = char> {
@@ -274,5 +627,38 @@ mod testing {
         assert!(super::parse_whitespace(&mut " ".chars().peekable()));
         assert!(super::parse_whitespace(&mut "\n".chars().peekable()));
     }
+
+    #[test]
+    fn verify_lexical_syntax() {
+        let tokens = |code| super::tokenize(&super::Source::new("test", code))
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        assert_eq!(tokens("#true #false #t #\\space #\\x3bb #\\( #\\a"), "#t #f #t #\\space #\\λ #\\( #\\a");
+        assert_eq!(tokens("\"a\\tb\\x41;\\\"\\\n    c\""), "\"a\\tbA\\\"c\"");
+        assert_eq!(tokens("a #| x #| y |# z |# b ; c\n d"), "a b d");
+        assert_eq!(tokens("|two words| #!fold-case ABC #\\SPACE #!no-fold-case ABC"), "|two words| abc #\\space ABC");
+        assert_eq!(tokens("#0=(a . #0#)"), "#0= ( a . #0# )");
+        assert_eq!(tokens("(a #;(b c) d)"), "( a #; ( b c ) d )");
+    }
+
+    #[test]
+    fn verify_read_errors() {
+        let error = |code| super::tokenize(&super::Source::new("test", code))
+            .unwrap_err()
+            .to_string();
+
+        assert_eq!(error("(a #q)"), "test:1:4: Expected #t, #f, #(, #u8( or #\\<char>, found #q\n    (a #q)\n       ^");
+        assert_eq!(error("#u9(1)"), "test:1:1: Expected #t, #f, #(, #u8( or #\\<char>, found #u9\n    #u9(1)\n    ^");
+        assert_eq!(error("#\\spac"), "test:1:1: Expected a character name, found #\\spac\n    #\\spac\n    ^");
+        assert_eq!(error("\"a\\qb\""), "test:1:1: Expected an escape sequence, found \\q\n    \"a\\qb\"\n    ^");
+        assert_eq!(error("#| #| |# "), "test:1:1: Expected |#, found end of input\n    #| #| |# \n    ^");
+        assert_eq!(error("\n  #\\"), "test:2:3: Expected a character after #\\, found end of input\n      #\\\n      ^");
+        assert_eq!(error("#"), "test:1:1: Expected #t, #f, #(, #u8( or #\\<char>, found end of input\n    #\n    ^");
+        assert_eq!(error("\"abc"), "test:1:1: Expected \", found end of input\n    \"abc\n    ^");
+    }
 }
 
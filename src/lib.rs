@@ -6,6 +6,9 @@ pub mod utils;
 pub mod env;
 pub mod lexer;
 pub mod parser;
+pub mod pair;
+pub mod record;
+pub mod hash_table;
 pub mod expander;
 pub mod port;
 pub mod procedure;
@@ -6,6 +6,9 @@ mod utils;
 mod env;
 mod lexer;
 mod parser;
+mod pair;
+mod record;
+mod hash_table;
 mod expander;
 mod port;
 mod procedure;
@@ -18,7 +21,7 @@ use std::env::args;
 use std::fs::read_to_string;
 
 use env::{Env, EnvRef};
-use lexer::tokenize;
+use lexer::{tokenize, Source};
 use parser::parse;
 
 fn main() {
@@ -36,10 +39,10 @@ fn main() {
         let scm = read_to_string(path).expect("Can't read file.");
 
         // TODO: run main function? (define (main args) ...)
-        match parse(tokenize(&mut scm.chars().peekable())) {
+        match tokenize(&Source::new(path, &scm)).and_then(parse) {
             Ok(sexprs) => {
-                for sexpr in sexprs {
-                    match sexpr.eval(&env) {
+                for (sexpr, span) in sexprs {
+                    match sexpr.eval_at(&span, &env) {
                         Ok(_) => (),
                         Err(e) => eprintln!("{}", e)
                     }
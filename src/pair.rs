@@ -0,0 +1,277 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use parser::{SExpr, SExprs};
+use lexer::Span;
+use serr::{SErr, SResult};
+
+/// A cons cell. Pairs are shared, so a change made by `set-car!` or
+/// `set-cdr!` is seen through every reference to the pair.
+#[derive(Clone)]
+pub struct PairData(Rc<Pair>);
+
+struct Pair {
+    cell: RefCell<(SExpr, SExpr)>,
+    /// Where the pair is read from, if it is read by the parser. That is
+    /// where the list starts for the first pair of a list and where the
+    /// element starts for the others. Atoms can't keep their location, so
+    /// errors about them are reported at the list around them, or where
+    /// they're read from if they're not in a list, see `evaluator::eval_at`.
+    span: Option<Span>,
+}
+
+impl PairData {
+    pub fn new(car: SExpr, cdr: SExpr) -> PairData {
+        PairData(Rc::new(Pair { cell: RefCell::new((car, cdr)), span: None }))
+    }
+
+    pub fn with_span(car: SExpr, cdr: SExpr, span: Span) -> PairData {
+        PairData(Rc::new(Pair { cell: RefCell::new((car, cdr)), span: Some(span) }))
+    }
+
+    pub fn car(&self) -> SExpr {
+        self.0.cell.borrow().0.clone()
+    }
+
+    pub fn cdr(&self) -> SExpr {
+        self.0.cell.borrow().1.clone()
+    }
+
+    pub fn set_car(&self, x: SExpr) {
+        self.0.cell.borrow_mut().0 = x;
+    }
+
+    pub fn set_cdr(&self, x: SExpr) {
+        self.0.cell.borrow_mut().1 = x;
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        self.0.span.as_ref()
+    }
+
+    /// Checks if both are the same pair, this is what `eq?` means for pairs.
+    pub fn ptr_eq(&self, other: &PairData) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// A number that identifies the pair as long as it is alive.
+    pub fn id(&self) -> usize {
+        &*self.0 as *const _ as usize
+    }
+}
+
+impl PartialEq for PairData {
+    /// Compares the contents, like `equal?`.
+    fn eq(&self, other: &PairData) -> bool {
+        equal(&SExpr::Pair(self.clone()), &SExpr::Pair(other.clone()))
+    }
+}
+
+/// Compares pairs and vectors by their contents, like `equal?`. A pair of
+/// pairs or vectors that is compared again is assumed to be equal, so
+/// circular structures are compared without going around forever.
+pub fn equal(x: &SExpr, y: &SExpr) -> bool {
+    equal_seen(x, y, &mut HashSet::new())
+}
+
+fn equal_seen(x: &SExpr, y: &SExpr, seen: &mut HashSet<(usize, usize)>) -> bool {
+    match (x, y) {
+        (SExpr::Pair(x), SExpr::Pair(y)) => {
+            let mut x = x.clone();
+            let mut y = y.clone();
+            loop {
+                if x.ptr_eq(&y) || !seen.insert((x.id(), y.id())) {
+                    return true
+                }
+
+                if !equal_seen(&x.car(), &y.car(), seen) {
+                    return false
+                }
+
+                match (x.cdr(), y.cdr()) {
+                    (SExpr::Pair(a), SExpr::Pair(b)) => {
+                        x = a;
+                        y = b;
+                    },
+                    (a, b) => return equal_seen(&a, &b, seen)
+                }
+            }
+        },
+        (SExpr::Vector(x), SExpr::Vector(y)) => {
+            if Rc::ptr_eq(x, y) || !seen.insert((&**x as *const _ as usize, &**y as *const _ as usize)) {
+                return true
+            }
+
+            let xs = x.borrow().clone();
+            let ys = y.borrow().clone();
+            xs.len() == ys.len() && xs.iter().zip(ys.iter()).all(|(a, b)| equal_seen(a, b, seen))
+        },
+        (x, y) => x == y
+    }
+}
+
+impl fmt::Debug for PairData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", SExpr::Pair(self.clone()))
+    }
+}
+
+impl Drop for PairData {
+    // Long lists would overflow the stack if they were dropped recursively.
+    fn drop(&mut self) {
+        if Rc::strong_count(&self.0) != 1 {
+            return
+        }
+
+        let mut next = mem::replace(&mut self.0.cell.borrow_mut().1, SExpr::Nil);
+        while let SExpr::Pair(pair) = next {
+            if Rc::strong_count(&pair.0) != 1 {
+                break
+            }
+
+            next = mem::replace(&mut pair.0.cell.borrow_mut().1, SExpr::Nil);
+        }
+    }
+}
+
+/// Walks the pairs of a list, yielding the elements. The tail of an
+/// improper list is available after the iteration ends.
+pub struct ListIter {
+    current: SExpr,
+    slow: SExpr,
+    steps: usize,
+    circular: bool,
+}
+
+impl ListIter {
+    pub fn new(list: &SExpr) -> ListIter {
+        ListIter { current: list.clone(), slow: list.clone(), steps: 0, circular: false }
+    }
+
+    /// What is left after the elements, `()` for proper lists.
+    pub fn tail(&self) -> &SExpr {
+        &self.current
+    }
+
+    pub fn is_circular(&self) -> bool {
+        self.circular
+    }
+}
+
+impl Iterator for ListIter {
+    type Item = SExpr;
+
+    fn next(&mut self) -> Option<SExpr> {
+        let pair = match self.current {
+            SExpr::Pair(ref pair) => pair.clone(),
+            _ => return None
+        };
+
+        // The slow pointer moves once every two steps, if the fast one
+        // catches it the list is circular.
+        self.steps += 1;
+        if self.steps % 2 == 0 {
+            self.slow = self.slow.cdr().unwrap_or(SExpr::Nil);
+            if let (SExpr::Pair(ref a), SExpr::Pair(ref b)) = (&self.slow, &self.current) {
+                if a.ptr_eq(b) && self.steps > 2 {
+                    self.circular = true;
+                    self.current = SExpr::Nil;
+                    return None
+                }
+            }
+        }
+
+        self.current = pair.cdr();
+        Some(pair.car())
+    }
+}
+
+impl SExpr {
+    pub fn cons(car: SExpr, cdr: SExpr) -> SExpr {
+        SExpr::Pair(PairData::new(car, cdr))
+    }
+
+    /// Creates a proper list.
+    pub fn list(xs: SExprs) -> SExpr {
+        SExpr::dottedlist(xs, SExpr::Nil)
+    }
+
+    /// Creates a list that ends with `tail` instead of `()`.
+    pub fn dottedlist(xs: SExprs, tail: SExpr) -> SExpr {
+        xs.into_iter()
+            .rev()
+            .fold(tail, |acc, x| SExpr::cons(x, acc))
+    }
+
+    /// Creates a list like `dottedlist` does from the elements and where
+    /// they are read from. The first pair remembers where the list starts,
+    /// the others remember where their element starts.
+    pub fn located_list(xs: Vec<(SExpr, Span)>, tail: SExpr, span: Span) -> SExpr {
+        let mut iter = xs.into_iter();
+        let first = match iter.next() {
+            Some((first, _)) => first,
+            None => return tail
+        };
+
+        let rest = iter.rev()
+            .fold(tail, |acc, (x, at)| SExpr::Pair(PairData::with_span(x, acc, at)));
+        SExpr::Pair(PairData::with_span(first, rest, span))
+    }
+
+    pub fn iter(&self) -> ListIter {
+        ListIter::new(self)
+    }
+
+    pub fn car(&self) -> SResult<SExpr> {
+        match self {
+            SExpr::Pair(x) => Ok(x.car()),
+            x => bail!(TypeMismatch => "pair", x)
+        }
+    }
+
+    pub fn cdr(&self) -> SResult<SExpr> {
+        match self {
+            SExpr::Pair(x) => Ok(x.cdr()),
+            x => bail!(TypeMismatch => "pair", x)
+        }
+    }
+
+    /// Returns the elements of a (possibly improper) list and what comes
+    /// after them. Fails on circular lists.
+    pub fn list_parts(&self) -> SResult<(SExprs, SExpr)> {
+        // The evaluator splits every form it evaluates, so this walks the
+        // pairs itself instead of using `ListIter`. Cycles are found with
+        // Brent's algorithm: the pair at each power of two steps is kept
+        // and a circular list comes back to it.
+        let mut xs = Vec::with_capacity(4);
+        let mut current = self.clone();
+        let mut kept: Option<PairData> = None;
+        let mut steps = 0;
+        let mut limit = 1;
+        loop {
+            let next = match current {
+                SExpr::Pair(ref pair) => {
+                    if kept.as_ref().map_or(false, |x| x.ptr_eq(pair)) {
+                        return Err(SErr::new_generic("Unexpected circular list"))
+                    }
+
+                    steps += 1;
+                    if steps == limit {
+                        kept = Some(pair.clone());
+                        steps = 0;
+                        limit *= 2;
+                    }
+
+                    let cell = pair.0.cell.borrow();
+                    xs.push(cell.0.clone());
+                    cell.1.clone()
+                },
+                _ => return Ok((xs, current))
+            };
+            current = next;
+        }
+    }
+}
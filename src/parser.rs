@@ -2,29 +2,71 @@ use std::iter::Peekable;
 use std::ops::Not;
 use std::cmp::Ordering;
 use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
 
 use utils::fraction::Fraction;
-use utils::RcRefCell;
-use lexer::Token;
+use utils::bigint::BigInt;
+use utils::complex::Complex;
+use utils::{RcRefCell, new_rc_ref_cell};
+use lexer::{Token, Span};
 use procedure::ProcedureData;
 use evaluator;
 use env::EnvRef;
 use port::PortData;
-use expander::expand;
-use serr::{SErr, SResult};
+use expander::{base_name, MacroData};
+use pair::{self, PairData};
+use record::{RecordData, RecordTypeData};
+use hash_table::HashTableData;
+use serr::{SErr, SResult, ConditionData};
 
 pub type SExprs = Vec<SExpr>;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum SExpr {
     Atom(Token),
-    List(SExprs),
-    DottedList(Vec<SExpr>, Box<SExpr>),
+    /// The empty list.
+    Nil,
+    Pair(PairData),
+    Vector(RcRefCell<SExprs>),
+    Bytevector(RcRefCell<Vec<u8>>),
+    Record(RecordData),
+    RecordType(RecordTypeData),
+    HashTable(HashTableData),
     Procedure(ProcedureData),
     Port(PortData),
+    Macro(MacroData),
+    Condition(ConditionData),
+    /// Multiple values, returned by `values` with zero or more than one
+    /// argument.
+    Values(SExprs),
+    /// The end of file object, the input procedures return it at the end
+    /// of the input.
+    Eof,
     Unspecified,
 }
 
+impl PartialEq for SExpr {
+    fn eq(&self, other: &SExpr) -> bool {
+        use self::SExpr::*;
+        match (self, other) {
+            (Atom(x), Atom(y)) => x == y,
+            (Nil, Nil) | (Eof, Eof) | (Unspecified, Unspecified) => true,
+            // These may be circular, see `pair::equal`
+            (Pair(_), Pair(_)) | (Vector(_), Vector(_)) => pair::equal(self, other),
+            (Bytevector(x), Bytevector(y)) => x == y,
+            (Record(x), Record(y)) => x == y,
+            (RecordType(x), RecordType(y)) => x == y,
+            (HashTable(x), HashTable(y)) => x == y,
+            (Procedure(x), Procedure(y)) => x == y,
+            (Port(x), Port(y)) => x == y,
+            (Macro(x), Macro(y)) => x == y,
+            (Condition(x), Condition(y)) => x == y,
+            (Values(x), Values(y)) => x == y,
+            (_, _) => false
+        }
+    }
+}
+
 impl PartialOrd for SExpr {
     fn partial_cmp(&self, other: &SExpr) -> Option<Ordering> {
         use self::SExpr::*;
@@ -76,7 +118,19 @@ impl From<f64> for SExpr {
 
 impl From<Fraction> for SExpr {
     fn from(i: Fraction) -> Self {
-        sfrac!(i)
+        SExpr::Atom(Token::rational(i))
+    }
+}
+
+impl From<Complex> for SExpr {
+    fn from(i: Complex) -> Self {
+        SExpr::Atom(Token::complex(i))
+    }
+}
+
+impl From<BigInt> for SExpr {
+    fn from(i: BigInt) -> Self {
+        SExpr::Atom(Token::integer(i))
     }
 }
 
@@ -106,10 +160,6 @@ impl From<String> for SExpr {
 
 #[allow(dead_code)]
 impl SExpr {
-    pub fn dottedlist(x: SExprs, y: SExpr) -> SExpr {
-        SExpr::DottedList(x, Box::new(y))
-    }
-
     pub fn to_bool(&self) -> bool {
         // Anything other than #f is treated as true.
         match self {
@@ -121,7 +171,8 @@ impl SExpr {
     // Checks
     pub fn is_symbol(&self, symbol: &str) -> bool {
         match self {
-            SExpr::Atom(Token::Symbol(x)) => x == symbol,
+            // Same as `base_name(x) == symbol` without searching all of `x`
+            SExpr::Atom(Token::Symbol(x)) => x.starts_with(symbol) && (x.len() == symbol.len() || base_name(&x[symbol.len()..]).is_empty()),
             _ => false
         }
     }
@@ -149,26 +200,34 @@ impl SExpr {
 
     pub fn is_integer(&self) -> bool {
         match self {
-            SExpr::Atom(Token::Integer(_)) => true,
+            SExpr::Atom(Token::Integer(_)) | SExpr::Atom(Token::BigInt(_)) => true,
+            _ => false
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        match self {
+            SExpr::Nil => true,
             _ => false
         }
     }
 
     pub fn is_pair(&self) -> bool {
         match self {
-            SExpr::List(xs) if !xs.is_empty() => true,
-            SExpr::DottedList(_, _) => true,
+            SExpr::Pair(_) => true,
             _ => false
         }
     }
 
     pub fn is_proper_list(&self) -> bool {
+        let mut iter = self.iter();
+        iter.by_ref().count();
+        !iter.is_circular() && iter.tail().is_nil()
+    }
+
+    pub fn is_vector(&self) -> bool {
         match self {
-            SExpr::List(_) => true,
-            SExpr::DottedList(_, y) => match &**y {
-                SExpr::List(xs) if xs.is_empty() => true,
-                _ => false
-            }
+            SExpr::Vector(_) => true,
             _ => false
         }
     }
@@ -196,8 +255,17 @@ impl SExpr {
 
     pub fn is_numeric(&self) -> bool {
         match self {
-            SExpr::Atom(Token::Integer(_)) | SExpr::Atom(Token::Fraction(_))
-                | SExpr::Atom(Token::Float(_)) => true,
+            SExpr::Atom(Token::Integer(_)) | SExpr::Atom(Token::BigInt(_))
+                | SExpr::Atom(Token::Fraction(_)) | SExpr::Atom(Token::Float(_))
+                | SExpr::Atom(Token::Complex(_)) => true,
+            _ => false
+        }
+    }
+
+    /// Checks if the number has an imaginary part.
+    pub fn is_complex(&self) -> bool {
+        match self {
+            SExpr::Atom(Token::Complex(_)) => true,
             _ => false
         }
     }
@@ -217,7 +285,7 @@ impl SExpr {
         }
     }
 
-    pub fn as_symbol(&self) -> SResult<&String> {
+    pub fn as_symbol(&self) -> SResult<&str> {
         match self {
             SExpr::Atom(Token::Symbol(x)) => Ok(x),
             x => bail!(TypeMismatch => "symbol", x)
@@ -231,6 +299,20 @@ impl SExpr {
         }
     }
 
+    pub fn as_vector(&self) -> SResult<RcRefCell<SExprs>> {
+        match self {
+            SExpr::Vector(x) => Ok(Rc::clone(x)),
+            x => bail!(TypeMismatch => "vector", x)
+        }
+    }
+
+    pub fn as_bytevector(&self) -> SResult<RcRefCell<Vec<u8>>> {
+        match self {
+            SExpr::Bytevector(x) => Ok(Rc::clone(x)),
+            x => bail!(TypeMismatch => "bytevector", x)
+        }
+    }
+
     pub fn as_int(&self) -> SResult<i64> {
         match self {
             SExpr::Atom(Token::Integer(x)) => Ok(*x),
@@ -245,18 +327,47 @@ impl SExpr {
         }
     }
 
+    pub fn as_macro(&self) -> SResult<&MacroData> {
+        match self {
+            SExpr::Macro(x) => Ok(x),
+            x => bail!(TypeMismatch => "macro", x)
+        }
+    }
+
     // Transforms
     pub fn into_symbol(self) -> SResult<String> {
         match self {
-            SExpr::Atom(Token::Symbol(x)) => Ok(x),
+            SExpr::Atom(Token::Symbol(x)) => Ok(x.to_string()),
             x => bail!(TypeMismatch => "symbol", x)
         }
     }
 
+    /// Returns the values of a `values` call, a single value otherwise.
+    pub fn into_values(self) -> SExprs {
+        match self {
+            SExpr::Values(xs) => xs,
+            x => vec![x]
+        }
+    }
+
     pub fn into_list(self) -> SResult<SExprs> {
+        match self.list_parts() {
+            Ok((xs, SExpr::Nil)) => Ok(xs),
+            _ => bail!(TypeMismatch => "list", self)
+        }
+    }
+
+    pub fn into_record_type(self) -> SResult<RecordTypeData> {
+        match self {
+            SExpr::RecordType(x) => Ok(x),
+            x => bail!(TypeMismatch => "record type", x)
+        }
+    }
+
+    pub fn into_hash_table(self) -> SResult<HashTableData> {
         match self {
-            SExpr::List(xs) => Ok(xs),
-            x => bail!(TypeMismatch => "list", x)
+            SExpr::HashTable(x) => Ok(x),
+            x => bail!(TypeMismatch => "hash table", x)
         }
     }
 
@@ -292,33 +403,60 @@ impl SExpr {
         }
     }
 
+    pub fn into_u8(self) -> SResult<u8> {
+        match self {
+            SExpr::Atom(Token::Integer(x)) if x >= 0 && x <= 255 => Ok(x as u8),
+            x => bail!(TypeMismatch => "byte", x)
+        }
+    }
+
     pub fn into_float(self) -> SResult<f64> {
         match self {
             SExpr::Atom(Token::Float(x)) => Ok(x),
             SExpr::Atom(Token::Integer(x)) => Ok(x as f64),
+            SExpr::Atom(Token::BigInt(x)) => Ok(x.to_f64()),
             SExpr::Atom(Token::Fraction(x)) => Ok(x.into()),
             x => bail!(TypeMismatch => "float", x)
         }
     }
+
+    pub fn into_complex(self) -> SResult<Complex> {
+        match self {
+            SExpr::Atom(Token::Complex(x)) => Ok(x),
+            x => Ok(Complex::from(x.into_float()?))
+        }
+    }
     // Transform operations
     pub fn list_own_one_rest(self) -> SResult<(SExpr, SExprs)> {
-        match self {
-            SExpr::List(xs) => {
-                let mut iter = xs.into_iter();
-                let head = iter.next()
-                    .ok_or_else(|| SErr::FoundNothing)?;
-                let tail = iter.collect();
+        let xs = self.into_list()?;
+        let mut iter = xs.into_iter();
+        let head = iter.next()
+            .ok_or_else(|| SErr::FoundNothing)?;
+        let tail = iter.collect();
 
-                Ok((head, tail))
-            }
-            x => bail!(TypeMismatch => "list", x)
+        Ok((head, tail))
+    }
+
+    pub fn list_own_two(self) -> SResult<(SExpr, SExpr)> {
+        let xs = self.into_list()?;
+        if xs.len() != 2 {
+            bail!(WrongArgCount => 2 as usize, xs.len())
         }
+
+        let mut iter = xs.into_iter();
+        let x1 = iter.next().unwrap();
+        let x2 = iter.next().unwrap();
+        Ok((x1, x2))
     }
 
     pub fn eval(&self, env: &EnvRef) -> SResult<SExpr> {
         evaluator::eval(self, env)
     }
 
+    pub fn eval_at(&self, span: &Span, env: &EnvRef) -> SResult<SExpr> {
+        evaluator::eval_at(self, span, env)
+    }
+
     pub fn eval_ref<F,T>(&self, env: &EnvRef, f: F) -> SResult<T>
     where F: FnMut(&SExpr)->SResult<T> {
         evaluator::eval_ref(self, env, f)
@@ -330,81 +468,207 @@ impl SExpr {
     }
 }
 
-pub fn parse(tokens: Vec<Token>) -> SResult<SExprs> {
-    let mut iter = tokens.into_iter().peekable();
-    let mut exprs: SExprs = vec![];
+/// Parses the data in `tokens` and tells where each of them starts. Atoms
+/// don't know where they're read from, so this is what locates errors
+/// about an atom that is not in a list, see `evaluator::eval_at`.
+pub fn parse(tokens: Vec<(Token, Span)>) -> SResult<Vec<(SExpr, Span)>> {
+    let mut iter = tokens.into_iter().map(Ok).peekable();
+    let mut exprs = vec![];
 
-    while let Some(_) = iter.peek() {
-        exprs.push(expand(parse_single(&mut iter)?)?);
+    loop {
+        skip_datum_comments(&mut iter, &mut HashMap::new())?;
+        let span = match iter.peek() {
+            Some(Ok((_, span))) => span.clone(),
+            _ => break
+        };
+
+        exprs.push((parse_single(&mut iter)?, span));
     }
 
     Ok(exprs)
 }
 
+/// Datums labeled with `#n=` in the datum that is being read.
+type DatumLabels = HashMap<usize, SExpr>;
+
 pub fn parse_single<I>(iter: &mut Peekable<I>) -> SResult<SExpr>
-where I: Iterator<Item=Token> {
-    match iter.peek() {
-        Some(&Token::RParen) => bail!(UnexpectedToken => Token::RParen),
-        Some(&Token::LParen) => {
-            iter.next(); // Consume LParen
-
-            // Check if empty list
-            if iter.peek() == Some(&Token::RParen) {
-                iter.next(); // Consume RParen
-                return Ok(slist![]);
-            }
+where I: Iterator<Item=SResult<(Token, Span)>> {
+    // Labels are only visible in the outermost datum they are in
+    parse_datum(iter, &mut HashMap::new())
+}
 
-            let mut head: SExprs = vec![];
-            while iter.peek() != Some(&Token::RParen) &&
-                    iter.peek() != Some(&Token::Dot) {
-                head.push(parse_single(iter)?);
+fn parse_datum<I>(iter: &mut Peekable<I>, labels: &mut DatumLabels) -> SResult<SExpr>
+where I: Iterator<Item=SResult<(Token, Span)>> {
+    skip_datum_comments(iter, labels)?;
+    let (token, span) = match iter.next() {
+        Some(x) => x?,
+        None => serr!(FoundNothing)
+    };
+
+    match token {
+        Token::RParen => bail!(ReadError => span, "an expression", ")"),
+        Token::LParen => {
+            let mut head: Vec<(SExpr, Span)> = vec![];
+            while !next_is(iter, &Token::RParen, &span, labels)? && !next_is(iter, &Token::Dot, &span, labels)? {
+                let at = next_span(iter, &span);
+                head.push((parse_datum(iter, labels)?, at));
             }
 
-            match iter.next() {
-                Some(Token::Dot) => {
-                    let tail = parse_single(iter)?;
-                    if iter.peek() != Some(&Token::RParen) {
-                        let unexpected = iter.peek().unwrap().clone();
-                        bail!(NotExpectedToken => unexpected, Token::RParen)
-                    } else {
-                        iter.next(); // Consume RParen
-
-                        // If the tail is a proper list, then the result should
-                        // also be a proper list.
-                        if let SExpr::List(mut xs) = tail {
-                            head.append(&mut xs);
-                            Ok(SExpr::List(head))
-                        } else {
-                            Ok(SExpr::DottedList(head, Box::new(tail)))
-                        }
+            match iter.next().transpose()? {
+                Some((Token::Dot, dot)) if head.is_empty() => bail!(ReadError => dot, "an expression", "."),
+                Some((Token::Dot, dot)) => {
+                    let tail = parse_after(iter, &dot, labels)?;
+                    match iter.next().transpose()? {
+                        Some((Token::RParen, _)) => Ok(SExpr::located_list(head, tail, span)),
+                        Some((unexpected, at)) => bail!(ReadError => at, ")", unexpected.to_string()),
+                        None => bail!(ReadError => span, ")", "end of input")
                     }
                 },
-                Some(Token::RParen) => {
-                    Ok(SExpr::List(head))
-                },
-                x => bail!(UnexpectedToken => x.unwrap()),
+                // The empty list is `()`, there is no pair to locate
+                _ => Ok(SExpr::located_list(head, SExpr::Nil, span)),
             }
         },
-        Some(&Token::Quote) => {
-            iter.next();
-            Ok(quote!(parse_single(iter)?))
+        Token::VectorOpener => {
+            let mut xs: SExprs = vec![];
+            while !next_is(iter, &Token::RParen, &span, labels)? {
+                xs.push(parse_datum(iter, labels)?);
+            }
+
+            iter.next(); // Consume RParen
+            Ok(SExpr::Vector(new_rc_ref_cell(xs)))
         },
-        Some(&Token::UnQuote) => {
-            iter.next();
-            Ok(unquote!(parse_single(iter)?))
+        Token::BytevectorOpener => {
+            let mut u8s = vec![];
+            while !next_is(iter, &Token::RParen, &span, labels)? {
+                let at = next_span(iter, &span);
+                let x = parse_datum(iter, labels)?;
+                match x.clone().into_u8() {
+                    Ok(byte) => u8s.push(byte),
+                    Err(_) => bail!(ReadError => at, "a byte", x.to_string())
+                }
+            }
+
+            iter.next(); // Consume RParen
+            Ok(SExpr::Bytevector(new_rc_ref_cell(u8s)))
         },
-        Some(&Token::QuasiQuote) => {
-            iter.next();
-            Ok(quasiquote!(parse_single(iter)?))
+        Token::DatumLabel(n) => {
+            // References in the datum point to the placeholder until the
+            // datum is read, then they are replaced with the datum
+            let placeholder = PairData::new(SExpr::Nil, SExpr::Nil);
+            labels.insert(n, SExpr::Pair(placeholder.clone()));
+            let datum = parse_after(iter, &span, labels)?;
+            if let SExpr::Pair(ref x) = datum {
+                if x.ptr_eq(&placeholder) {
+                    bail!(ReadError => span, "a datum", format!("#{}#", n))
+                }
+            }
+
+            replace_placeholder(&datum, &placeholder, &datum, &mut HashSet::new());
+            labels.insert(n, datum.clone());
+            Ok(datum)
         },
-        Some(&Token::UnQuoteSplicing) => {
-            iter.next();
-            Ok(unquote_splicing!(parse_single(iter)?))
+        Token::DatumReference(n) => match labels.get(&n) {
+            Some(x) => Ok(x.clone()),
+            None => bail!(ReadError => span, "a defined datum label", format!("#{}#", n))
         },
-        Some(_) => {
-            let y = iter.next().unwrap();
-            Ok(SExpr::Atom(y))
+        Token::Quote => Ok(quote!(parse_after(iter, &span, labels)?)),
+        Token::UnQuote => Ok(unquote!(parse_after(iter, &span, labels)?)),
+        Token::QuasiQuote => Ok(quasiquote!(parse_after(iter, &span, labels)?)),
+        Token::UnQuoteSplicing => Ok(unquote_splicing!(parse_after(iter, &span, labels)?)),
+        Token::Dot => bail!(ReadError => span, "an expression", "."),
+        x => Ok(SExpr::Atom(x))
+    }
+}
+
+/// Replaces the references to `placeholder` in `x` with `value`.
+fn replace_placeholder(x: &SExpr, placeholder: &PairData, value: &SExpr, seen: &mut HashSet<usize>) {
+    let is_placeholder = |x: &SExpr| match x {
+        SExpr::Pair(x) => x.ptr_eq(placeholder),
+        _ => false
+    };
+
+    match x {
+        SExpr::Pair(_) => {
+            let mut current = x.clone();
+            while let SExpr::Pair(pair) = current.clone() {
+                if !seen.insert(pair.id()) {
+                    return
+                }
+
+                let car = pair.car();
+                if is_placeholder(&car) {
+                    pair.set_car(value.clone());
+                } else {
+                    replace_placeholder(&car, placeholder, value, seen);
+                }
+
+                current = pair.cdr();
+                if is_placeholder(&current) {
+                    pair.set_cdr(value.clone());
+                    return
+                }
+            }
+
+            // The tail of an improper list
+            replace_placeholder(&current, placeholder, value, seen);
         },
-        None => serr!(FoundNothing)
+        SExpr::Vector(xs) => {
+            if !seen.insert(&*xs.borrow() as *const _ as usize) {
+                return
+            }
+
+            let items = xs.borrow().clone();
+            for (i, item) in items.iter().enumerate() {
+                if is_placeholder(item) {
+                    xs.borrow_mut()[i] = value.clone();
+                } else {
+                    replace_placeholder(item, placeholder, value, seen);
+                }
+            }
+        },
+        _ => ()
+    }
+}
+
+/// Parses the expression that has to come after the token at `span`.
+fn parse_after<I>(iter: &mut Peekable<I>, span: &Span, labels: &mut DatumLabels) -> SResult<SExpr>
+where I: Iterator<Item=SResult<(Token, Span)>> {
+    skip_datum_comments(iter, labels)?;
+    if iter.peek().is_none() {
+        bail!(ReadError => span.clone(), "an expression", "end of input")
+    }
+
+    parse_datum(iter, labels)
+}
+
+/// Where the next token starts, or `default` if there is none.
+fn next_span<I>(iter: &mut Peekable<I>, default: &Span) -> Span
+where I: Iterator<Item=SResult<(Token, Span)>> {
+    match iter.peek() {
+        Some(Ok((_, span))) => span.clone(),
+        _ => default.clone()
+    }
+}
+
+/// Checks if the next token is `token`, the input can't end before the
+/// list that starts at `open` is closed.
+fn next_is<I>(iter: &mut Peekable<I>, token: &Token, open: &Span, labels: &mut DatumLabels) -> SResult<bool>
+where I: Iterator<Item=SResult<(Token, Span)>> {
+    skip_datum_comments(iter, labels)?;
+    match iter.peek() {
+        Some(Ok((x, _))) => Ok(x == token),
+        Some(Err(_)) => Err(iter.next().unwrap().unwrap_err()),
+        None => bail!(ReadError => open.clone(), ")", "end of input")
     }
 }
+
+/// Skips the `#;` comments and the expressions after them.
+fn skip_datum_comments<I>(iter: &mut Peekable<I>, labels: &mut DatumLabels) -> SResult<()>
+where I: Iterator<Item=SResult<(Token, Span)>> {
+    while let Some(Ok((Token::DatumComment, _))) = iter.peek() {
+        let (_, span) = iter.next().unwrap()?;
+        parse_after(iter, &span, labels)?;
+    }
+
+    Ok(())
+}
@@ -2,10 +2,21 @@ use std::io::prelude::*;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
-use std::io::{BufReader, BufWriter, Stdin, Stdout};
-
+use std::char;
+use std::io::{BufReader, BufWriter, Cursor, Stdin, Stdout, Stderr};
+use std::cell::{RefCell, RefMut};
+use std::rc::{Rc, Weak};
+
+use lexer::Token;
+use parser::SExpr;
+use env::EnvRef;
+use evaluator;
+use procedure::{ProcedureData, ParameterData};
+use primitives::io as io_primitives;
+use primitives::vector::index;
+use primitives::bytevector::new_bytevector;
 use serr::{SErr, SResult};
-use utils::chars::Chars;
+use utils::chars::{Chars, first_char};
 use utils::{new_rc_ref_cell, RcRefCell};
 
 #[derive(Debug, Clone)]
@@ -16,6 +27,16 @@ pub enum PortData {
     BinaryFileOutput(String, RcRefCell<BufWriter<File>>),
     StdInput(RcRefCell<Stdin>),
     StdOutput(RcRefCell<Stdout>),
+    StdError(RcRefCell<Stderr>),
+    /// Reads the characters of a string.
+    StringInput(RcRefCell<Cursor<Vec<u8>>>),
+    /// Collects the characters written to it, see `output_string`.
+    StringOutput(RcRefCell<Vec<u8>>),
+    /// Ports whose data comes from or goes to Scheme procedures.
+    CustomTextualInput(RcRefCell<CustomPort>),
+    CustomTextualOutput(RcRefCell<CustomPort>),
+    CustomBinaryInput(RcRefCell<CustomPort>),
+    CustomBinaryOutput(RcRefCell<CustomPort>),
     Closed
 }
 
@@ -36,6 +57,21 @@ impl PartialEq for PortData {
             (PortData::StdOutput(r), PortData::StdOutput(rr)) => {
                     &*r as *const _ == &*rr as *const _
             },
+            (PortData::StdError(r), PortData::StdError(rr)) => {
+                    &*r as *const _ == &*rr as *const _
+            },
+            (PortData::StringInput(r), PortData::StringInput(rr)) => {
+                    &*r as *const _ == &*rr as *const _
+            },
+            (PortData::StringOutput(r), PortData::StringOutput(rr)) => {
+                    &*r as *const _ == &*rr as *const _
+            },
+            (PortData::CustomTextualInput(r), PortData::CustomTextualInput(rr))
+                | (PortData::CustomTextualOutput(r), PortData::CustomTextualOutput(rr))
+                | (PortData::CustomBinaryInput(r), PortData::CustomBinaryInput(rr))
+                | (PortData::CustomBinaryOutput(r), PortData::CustomBinaryOutput(rr)) => {
+                    &*r as *const _ == &*rr as *const _
+            },
             _ => false
         }
     }
@@ -84,7 +120,30 @@ impl PortData {
             .write(true)
             .open(path)?;
 
-        Ok(PortData::BinaryFileOutput(path.to_string(), new_rc_ref_cell(BufWriter::new(file))))
+        let writer = new_rc_ref_cell(BufWriter::new(file));
+        BINARY_OUTPUTS.with(|outputs| {
+            let mut outputs = outputs.borrow_mut();
+            outputs.retain(|x| x.upgrade().is_some());
+            outputs.push(Rc::downgrade(&writer));
+        });
+
+        Ok(PortData::BinaryFileOutput(path.to_string(), writer))
+    }
+
+    pub fn new_string_input(string: &str) -> PortData {
+        PortData::StringInput(new_rc_ref_cell(Cursor::new(string.as_bytes().to_vec())))
+    }
+
+    pub fn new_string_output() -> PortData {
+        PortData::StringOutput(new_rc_ref_cell(vec![]))
+    }
+
+    /// The characters that are written to a string output port so far.
+    pub fn output_string(&self) -> SResult<String> {
+        match self {
+            PortData::StringOutput(bytes) => Ok(String::from_utf8_lossy(&bytes.borrow()).into_owned()),
+            x => bail!(TypeMismatch => "string output port", SExpr::Port(x.clone()))
+        }
     }
 
     //
@@ -94,8 +153,9 @@ impl PortData {
         match self {
             PortData::TextualFileInput(_, br) => port_read_str_fn!(br, read_line),
             PortData::StdInput(br) => port_read_str_fn!(br, read_line),
-            // FIXME: fix this and the functions below
-            _x => bail!(WrongPort => "read-line", "TODO:PORT_NAME_HERE")
+            PortData::StringInput(br) => port_read_str_fn!(br, read_line),
+            PortData::CustomTextualInput(port) => custom(port)?.read_line(),
+            x => bail!(WrongPort => "read-line", x.type_name())
         }
     }
 
@@ -103,38 +163,112 @@ impl PortData {
         match self {
             PortData::TextualFileInput(_, br) => port_read_str_fn!(br, read_to_string),
             PortData::StdInput(br) => port_read_str_fn!(br, read_to_string),
-            _x => bail!(WrongPort => "read-all-str", "TODO:PORT_NAME_HERE")
+            PortData::StringInput(br) => port_read_str_fn!(br, read_to_string),
+            PortData::CustomTextualInput(port) => {
+                let bytes = custom(port)?.read_all()?;
+                Ok((bytes.len(), String::from_utf8_lossy(&bytes).into_owned()))
+            },
+            x => bail!(WrongPort => "read-all-str", x.type_name())
         }
     }
 
-    pub fn read_char(&mut self) -> SResult<(usize, char)> {
-        // FIXME: this only reads 1 u8 and casts it to char
-        macro_rules! port_read_chr(
-            ($br: ident) => {{
-                let br = &mut *$br.borrow_mut();
-                let mut chr = [0; 1];
-                br.read_exact(&mut chr)?;
-                Ok((1, chr[0] as char))
-            }};
-        );
+    /// Reads a character, `None` at the end of the input.
+    pub fn read_char(&mut self) -> SResult<Option<char>> {
+        self.with_chars(|chars| Ok(chars.next()))
+    }
 
+    /// Reads at most `k` characters, `None` if the end of the input is
+    /// reached before any.
+    pub fn read_string(&mut self, k: usize) -> SResult<Option<String>> {
+        let string = self.with_chars(|chars| Ok(chars.take(k).collect::<String>()))?;
+        if string.is_empty() && k > 0 {
+            Ok(None)
+        } else {
+            Ok(Some(string))
+        }
+    }
+
+    /// Returns the next character without consuming it.
+    pub fn peek_char(&mut self) -> SResult<Option<char>> {
         match self {
-            PortData::TextualFileInput(_, br) => port_read_chr!(br),
-            PortData::StdInput(br) => port_read_chr!(br),
-            _x => bail!(WrongPort => "read-char", "TODO:PORT_NAME_HERE")
+            PortData::TextualFileInput(_, br) => {
+                let br = &mut *br.borrow_mut();
+                let chr = Chars::new(br).next();
+                if let Some(c) = chr {
+                    br.seek_relative(-(c.len_utf8() as i64))?;
+                }
+                Ok(chr)
+            },
+            PortData::StringInput(br) => {
+                let br = &mut *br.borrow_mut();
+                let position = br.position();
+                let chr = Chars::new(br).next();
+                br.set_position(position);
+                Ok(chr)
+            },
+            PortData::StdInput(br) => {
+                let stdin = br.borrow();
+                let mut lock = stdin.lock();
+                let buf = lock.fill_buf()?;
+                // A character that is split at the end of the buffer can't
+                // be put back, it is seen as a replacement character.
+                Ok(buf.first().map(|_| first_char(buf).unwrap_or(char::REPLACEMENT_CHARACTER)))
+            },
+            PortData::CustomTextualInput(port) => Ok(first_char(custom(port)?.fill()?)),
+            x => bail!(WrongPort => "peek-char", x.type_name())
         }
     }
 
-    pub fn read_u8(&mut self) -> SResult<(usize, u8)> {
+    /// Whether a character can be read without waiting for input.
+    pub fn char_ready(&self) -> SResult<bool> {
+        match self {
+            PortData::TextualFileInput(_, _) | PortData::StringInput(_) => Ok(true),
+            PortData::CustomTextualInput(_) => Ok(true),
+            // There is no way to know that without blocking
+            PortData::StdInput(_) => Ok(false),
+            x => bail!(WrongPort => "char-ready?", x.type_name())
+        }
+    }
+
+    /// Reads a byte, `None` at the end of the input.
+    pub fn read_u8(&mut self) -> SResult<Option<u8>> {
         match self {
             PortData::BinaryFileInput(_, br) => {
                 let br = &mut *br.borrow_mut();
-                let mut u8s = [0; 1];
-                br.read_exact(&mut u8s)?;
+                let byte = br.fill_buf()?.first().cloned();
+                if byte.is_some() {
+                    br.consume(1);
+                }
 
-                Ok((1, u8s[0]))
+                Ok(byte)
             },
-            _x => bail!(WrongPort => "read-u8", "TODO:PORT_NAME_HERE")
+            PortData::CustomBinaryInput(port) => {
+                let mut port = custom(port)?;
+                let byte = port.fill()?.first().cloned();
+                if byte.is_some() {
+                    port.consume(1);
+                }
+
+                Ok(byte)
+            },
+            x => bail!(WrongPort => "read-u8", x.type_name())
+        }
+    }
+
+    /// Returns the next byte without consuming it.
+    pub fn peek_u8(&mut self) -> SResult<Option<u8>> {
+        match self {
+            PortData::BinaryFileInput(_, br) => Ok(br.borrow_mut().fill_buf()?.first().cloned()),
+            PortData::CustomBinaryInput(port) => Ok(custom(port)?.fill()?.first().cloned()),
+            x => bail!(WrongPort => "peek-u8", x.type_name())
+        }
+    }
+
+    /// Whether a byte can be read without waiting for input.
+    pub fn u8_ready(&self) -> SResult<bool> {
+        match self {
+            PortData::BinaryFileInput(_, _) | PortData::CustomBinaryInput(_) => Ok(true),
+            x => bail!(WrongPort => "u8-ready?", x.type_name())
         }
     }
 
@@ -147,7 +281,30 @@ impl PortData {
 
                 Ok((size, u8s))
             },
-            _x => bail!(WrongPort => "read-all-u8", "TODO:PORT_NAME_HERE")
+            PortData::CustomBinaryInput(port) => {
+                let u8s = custom(port)?.read_all()?;
+                Ok((u8s.len(), u8s))
+            },
+            x => bail!(WrongPort => "read-all-u8", x.type_name())
+        }
+    }
+
+    /// Reads at most `k` bytes, less than `k` only if the end of the file
+    /// is reached.
+    pub fn read_bytevector(&mut self, k: usize) -> SResult<(usize, Vec<u8>)> {
+        match self {
+            PortData::BinaryFileInput(_, br) => {
+                let br = &mut *br.borrow_mut();
+                let mut u8s = vec![];
+                let size = br.by_ref().take(k as u64).read_to_end(&mut u8s)?;
+
+                Ok((size, u8s))
+            },
+            PortData::CustomBinaryInput(port) => {
+                let u8s = custom(port)?.read_bytes(k)?;
+                Ok((u8s.len(), u8s))
+            },
+            x => bail!(WrongPort => "read-bytevector", x.type_name())
         }
     }
 
@@ -164,7 +321,16 @@ impl PortData {
         match self {
             PortData::TextualFileInput(_, br) => with_chars!(br),
             PortData::StdInput(br) => with_chars!(br),
-            _x => bail!(WrongPort => "chars", "TODO:PORT_NAME_HERE")
+            PortData::StringInput(br) => with_chars!(br),
+            PortData::CustomTextualInput(port) => {
+                let mut chars = CustomChars { port: custom(port)?, error: None };
+                let result = f(&mut chars);
+                match chars.error {
+                    Some(e) => Err(e),
+                    None => result
+                }
+            },
+            x => bail!(WrongPort => "chars", x.type_name())
         }
     }
     //
@@ -182,20 +348,82 @@ impl PortData {
         match self {
             PortData::TextualFileOutput(_,br) => write_string!(br),
             PortData::StdOutput(br) => write_string!(br),
-            _x => bail!(WrongPort => "write-string", "TODO:PORT_NAME_HERE")
+            PortData::StdError(br) => write_string!(br),
+            PortData::StringOutput(br) => write_string!(br),
+            PortData::CustomTextualOutput(port) => custom(port)?.write_string(string)?,
+            x => bail!(WrongPort => "write-string", x.type_name())
+        };
+
+        Ok(())
+    }
+
+    pub fn write_u8s(&mut self, u8s: &[u8]) -> SResult<()> {
+        match self {
+            PortData::BinaryFileOutput(_, bw) => {
+                let bw = &mut *bw.borrow_mut();
+                bw.write_all(u8s)?;
+            },
+            PortData::CustomBinaryOutput(port) => custom(port)?.write_u8s(u8s)?,
+            x => bail!(WrongPort => "write-u8", x.type_name())
+        };
+
+        Ok(())
+    }
+
+    /// Writes out what is buffered, the writes to binary files are only
+    /// buffered until the port is flushed or closed.
+    pub fn flush(&mut self) -> SResult<()> {
+        match self {
+            PortData::BinaryFileOutput(_, bw) => bw.borrow_mut().flush()?,
+            x if x.is_output() => (),
+            x => bail!(WrongPort => "flush-output-port", x.type_name())
         };
 
         Ok(())
     }
 
+    /// Flushes binary files and calls the `close` procedure of a custom
+    /// port, the other ports are closed when they are dropped.
+    pub fn close(&mut self) -> SResult<()> {
+        match self {
+            PortData::BinaryFileOutput(_, bw) => Ok(bw.borrow_mut().flush()?),
+            PortData::CustomTextualInput(port) | PortData::CustomTextualOutput(port)
+                | PortData::CustomBinaryInput(port) | PortData::CustomBinaryOutput(port) => custom(port)?.close(),
+            _ => Ok(())
+        }
+    }
+
     //
     // Checks
     //
+    /// Describes the kind of the port, used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            PortData::TextualFileInput(_, _) => "textual input file",
+            PortData::TextualFileOutput(_, _) => "textual output file",
+            PortData::BinaryFileInput(_, _) => "binary input file",
+            PortData::BinaryFileOutput(_, _) => "binary output file",
+            PortData::StdInput(_) => "standard input",
+            PortData::StdOutput(_) => "standard output",
+            PortData::StdError(_) => "standard error",
+            PortData::StringInput(_) => "string input",
+            PortData::StringOutput(_) => "string output",
+            PortData::CustomTextualInput(_) => "custom textual input",
+            PortData::CustomTextualOutput(_) => "custom textual output",
+            PortData::CustomBinaryInput(_) => "custom binary input",
+            PortData::CustomBinaryOutput(_) => "custom binary output",
+            PortData::Closed => "closed port",
+        }
+    }
+
     pub fn is_input(&self) -> bool {
         match self {
             PortData::TextualFileInput(_, _) => true,
             PortData::BinaryFileInput(_, _) => true,
             PortData::StdInput(_) => true,
+            PortData::StringInput(_) => true,
+            PortData::CustomTextualInput(_) => true,
+            PortData::CustomBinaryInput(_) => true,
             _ => false
         }
     }
@@ -205,6 +433,10 @@ impl PortData {
             PortData::TextualFileOutput(_, _) => true,
             PortData::BinaryFileOutput(_, _) => true,
             PortData::StdOutput(_) => true,
+            PortData::StdError(_) => true,
+            PortData::StringOutput(_) => true,
+            PortData::CustomTextualOutput(_) => true,
+            PortData::CustomBinaryOutput(_) => true,
             _ => false
         }
     }
@@ -214,7 +446,12 @@ impl PortData {
             PortData::TextualFileInput(_, _) => true,
             PortData::TextualFileOutput(_, _) => true,
             PortData::StdOutput(_) => true,
+            PortData::StdError(_) => true,
             PortData::StdInput(_) => true,
+            PortData::StringInput(_) => true,
+            PortData::StringOutput(_) => true,
+            PortData::CustomTextualInput(_) => true,
+            PortData::CustomTextualOutput(_) => true,
             _ => false
         }
     }
@@ -223,19 +460,238 @@ impl PortData {
         match self {
             PortData::BinaryFileInput(_, _) => true,
             PortData::BinaryFileOutput(_, _) => true,
+            PortData::CustomBinaryInput(_) => true,
+            PortData::CustomBinaryOutput(_) => true,
             _ => false
         }
     }
 }
 
+/// How many characters or bytes the `read!` procedure of a custom port is
+/// asked for at once.
+const CUSTOM_PORT_CHUNK: usize = 1024;
+
+/// The procedures behind a custom port, like the custom ports of R6RS.
+/// `read!` and `write!` are called as `(read! target start count)` and
+/// `(write! source start count)` with a string or a bytevector, and
+/// return how many items they have read or written. Strings are indexed
+/// by bytes, like the string procedures do.
+#[derive(Debug)]
+pub struct CustomPort {
+    pub id: String,
+    /// `read!` for input ports, `write!` for output ports.
+    procedure: SExpr,
+    close: Option<SExpr>,
+    env: EnvRef,
+    binary: bool,
+    /// Input that is read but not consumed yet, UTF-8 for textual ports.
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl CustomPort {
+    pub fn new(id: String, procedure: SExpr, close: Option<SExpr>, binary: bool, env: &EnvRef) -> CustomPort {
+        CustomPort { id, procedure, close, env: env.clone_ref(), binary, buffer: vec![], position: 0 }
+    }
+
+    /// The input that isn't consumed yet, `read!` is called if there is
+    /// none. Empty at the end of the input.
+    fn fill(&mut self) -> SResult<&[u8]> {
+        if self.position == self.buffer.len() {
+            self.buffer = self.read_chunk()?;
+            self.position = 0;
+        }
+
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.position += n;
+    }
+
+    fn read_chunk(&self) -> SResult<Vec<u8>> {
+        let target = if self.binary {
+            new_bytevector(vec![0; CUSTOM_PORT_CHUNK])
+        } else {
+            sstr!(" ".repeat(CUSTOM_PORT_CHUNK))
+        };
+
+        let args = vec![target.clone(), sint!(0), sint!(CUSTOM_PORT_CHUNK as i64)];
+        let count = index(evaluator::apply(&self.procedure, args, &self.env)?)?;
+        if count > CUSTOM_PORT_CHUNK {
+            bail!("The read! procedure of {} returned {}, more than the {} it is asked for",
+                  self.id, count, CUSTOM_PORT_CHUNK)
+        }
+
+        Ok(match target {
+            SExpr::Bytevector(u8s) => u8s.borrow()[..count].to_vec(),
+            SExpr::Atom(Token::Str(string)) => match string.borrow().get(..count) {
+                Some(read) => read.as_bytes().to_vec(),
+                None => bail!("The read! procedure of {} returned {}, which splits a character", self.id, count)
+            },
+            _ => unreachable!()
+        })
+    }
+
+    fn read_char(&mut self) -> SResult<Option<char>> {
+        let chr = first_char(self.fill()?);
+        if let Some(c) = chr {
+            self.consume(c.len_utf8());
+        }
+
+        Ok(chr)
+    }
+
+    fn read_line(&mut self) -> SResult<(usize, String)> {
+        let mut line = String::new();
+        while let Some(c) = self.read_char()? {
+            line.push(c);
+            if c == '\n' {
+                break
+            }
+        }
+
+        Ok((line.len(), line))
+    }
+
+    /// Reads at most `k` bytes.
+    fn read_bytes(&mut self, k: usize) -> SResult<Vec<u8>> {
+        let mut u8s = vec![];
+        while u8s.len() < k {
+            let n = {
+                let available = self.fill()?;
+                if available.is_empty() {
+                    break
+                }
+
+                let n = available.len().min(k - u8s.len());
+                u8s.extend_from_slice(&available[..n]);
+                n
+            };
+            self.consume(n);
+        }
+
+        Ok(u8s)
+    }
+
+    fn read_all(&mut self) -> SResult<Vec<u8>> {
+        self.read_bytes(usize::max_value())
+    }
+
+    /// Hands `source`, a string or a bytevector of `len` items, to `write!`
+    /// until all of it is written.
+    fn write(&mut self, source: SExpr, len: usize) -> SResult<()> {
+        let mut start = 0;
+        while start < len {
+            let args = vec![source.clone(), sint!(start as i64), sint!((len - start) as i64)];
+            let count = index(evaluator::apply(&self.procedure, args, &self.env)?)?;
+            if count == 0 || count > len - start {
+                bail!("The write! procedure of {} returned {}, expected 1 to {}", self.id, count, len - start)
+            }
+            start += count;
+        }
+
+        Ok(())
+    }
+
+    fn write_string(&mut self, string: &str) -> SResult<()> {
+        self.write(sstr!(string), string.len())
+    }
+
+    fn write_u8s(&mut self, u8s: &[u8]) -> SResult<()> {
+        self.write(new_bytevector(u8s.to_vec()), u8s.len())
+    }
+
+    /// Calls the `close` procedure once.
+    fn close(&mut self) -> SResult<()> {
+        match self.close.take() {
+            Some(close) => evaluator::apply(&close, vec![], &self.env).map(|_| ()),
+            None => Ok(())
+        }
+    }
+}
+
+/// A custom port is borrowed while its procedures run, they can't use it.
+fn custom(port: &RcRefCell<CustomPort>) -> SResult<RefMut<'_, CustomPort>> {
+    port.try_borrow_mut()
+        .map_err(|_| SErr::Generic("A custom port is used by its own procedures".to_string()))
+}
 
-pub fn current_input_port() -> PortData {
-    // TODO: current_input should be changable
-    PortData::StdInput(new_rc_ref_cell(io::stdin()))
+/// The characters of a custom port. An error of `read!` ends them, and
+/// is reported after they are used.
+struct CustomChars<'a> {
+    port: RefMut<'a, CustomPort>,
+    error: Option<SErr>,
 }
 
-pub fn current_output_port() -> PortData {
-    // TODO: current_output should be changable
-    PortData::StdOutput(new_rc_ref_cell(io::stdout()))
+impl<'a> Iterator for CustomChars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self.port.read_char() {
+            Ok(chr) => chr,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
 }
 
+thread_local! {
+    /// The ports that input and output procedures use when they aren't
+    /// given one, `parameterize` can change them.
+    static CURRENT_INPUT: ParameterData = ParameterData::new(
+        SExpr::Port(PortData::StdInput(new_rc_ref_cell(io::stdin()))),
+        Some(ProcedureData::new_primitive(io_primitives::input_port)));
+    static CURRENT_OUTPUT: ParameterData = ParameterData::new(
+        SExpr::Port(PortData::StdOutput(new_rc_ref_cell(io::stdout()))),
+        Some(ProcedureData::new_primitive(io_primitives::output_port)));
+    static CURRENT_ERROR: ParameterData = ParameterData::new(
+        SExpr::Port(PortData::StdError(new_rc_ref_cell(io::stderr()))),
+        Some(ProcedureData::new_primitive(io_primitives::output_port)));
+    /// The binary output files that are open, `flush_all` writes out what
+    /// they have buffered.
+    static BINARY_OUTPUTS: RefCell<Vec<Weak<RefCell<BufWriter<File>>>>> = RefCell::new(vec![]);
+}
+
+/// Flushes the binary output files, `exit` calls this since the ports
+/// aren't dropped then.
+pub fn flush_all() -> SResult<()> {
+    BINARY_OUTPUTS.with(|outputs| {
+        for writer in outputs.borrow().iter().filter_map(|x| x.upgrade()) {
+            writer.borrow_mut().flush()?;
+        }
+
+        Ok(())
+    })
+}
+
+pub fn current_input_parameter() -> ParameterData {
+    CURRENT_INPUT.with(|x| x.clone())
+}
+
+pub fn current_output_parameter() -> ParameterData {
+    CURRENT_OUTPUT.with(|x| x.clone())
+}
+
+pub fn current_error_parameter() -> ParameterData {
+    CURRENT_ERROR.with(|x| x.clone())
+}
+
+pub fn current_input_port() -> SResult<PortData> {
+    port_value(current_input_parameter())
+}
+
+pub fn current_output_port() -> SResult<PortData> {
+    port_value(current_output_parameter())
+}
+
+/// The converters of the port parameters make sure they are always bound
+/// to ports.
+fn port_value(parameter: ParameterData) -> SResult<PortData> {
+    match parameter.get() {
+        SExpr::Port(port) => Ok(port),
+        x => bail!(TypeMismatch => "port", x)
+    }
+}
@@ -1,8 +1,12 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::mem;
 
 use env::EnvRef;
-use lexer::Token;
+use lexer::{Token, parse_number};
 use parser::SExpr;
+use utils::radix::{format_float, format_complex};
+use expander::base_name;
 use procedure::ProcedureData;
 use procedure::CompoundData;
 use procedure::PrimitiveData;
@@ -16,19 +20,26 @@ impl fmt::Display for Token {
         let s = match self {
             Token::LParen          => "(".to_string(),
             Token::RParen          => ")".to_string(),
+            Token::VectorOpener    => "#(".to_string(),
+            Token::BytevectorOpener => "#u8(".to_string(),
+            Token::DatumComment    => "#;".to_string(),
+            Token::DatumLabel(n)   => format!("#{}=", n),
+            Token::DatumReference(n) => format!("#{}#", n),
             Token::Dot             => ".".to_string(),
             Token::Ellipsis        => "...".to_string(),
             Token::Quote           => "'".to_string(),
             Token::UnQuote         => ",".to_string(),
             Token::QuasiQuote      => "`".to_string(),
             Token::UnQuoteSplicing => ",@".to_string(),
-            Token::Symbol(x)  => x.to_string(),
+            Token::Symbol(x)  => format_symbol(base_name(x)),
             Token::Integer(x) => format!("{}", x),
-            Token::Float(x)   => format!("{}", x),
+            Token::Float(x)   => format_float(*x),
+            Token::Complex(x) => format_complex(*x),
+            Token::BigInt(x)  => format!("{}", x),
             Token::Fraction(x) => format!("{}/{}", x.n, x.d),
             Token::Boolean(x) => format_bool(x).to_string(),
-            Token::Chr(x)     => format!("#\\{}", x),
-            Token::Str(x)     => format!("\"{}\"", x.borrow()),
+            Token::Chr(x)     => format_char(*x),
+            Token::Str(x)     => format!("\"{}\"", escape(&x.borrow(), '"')),
         };
 
         fmt.write_str(&s);
@@ -37,18 +48,456 @@ impl fmt::Display for Token {
     }
 }
 
-#[allow(unused_must_use)]
 impl fmt::Display for SExpr {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&external(self, false, Labels::Cycles))
+    }
+}
+
+/// Which objects get datum labels, `#0=` and `#0#`, when they are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Labels {
+    /// None, like `write-simple` does. Circular objects are written
+    /// forever.
+    None,
+    /// Only the ones that are part of a cycle, like `write` does.
+    Cycles,
+    /// Every object that is seen more than once, like `write-shared` does.
+    Shared,
+}
+
+/// The external representation of `x`. With `display`, strings and
+/// chars are written as they are instead of the way they are read.
+pub fn external(x: &SExpr, display: bool, labels: Labels) -> String {
+    let mut printer = Printer {
+        display,
+        labels: HashMap::new(),
+        next_label: 0,
+        out: String::new(),
+    };
+
+    if labels != Labels::None {
+        find_labels(x, labels == Labels::Shared, &mut HashSet::new(), &mut HashSet::new(), &mut printer.labels);
+    }
+
+    printer.print(x);
+    printer.out
+}
+
+/// Lays `x` out in lines of at most `width` chars where possible, the way
+/// Scheme code is conventionally indented. Cycles get labels like they do
+/// with `write`.
+pub fn pretty(x: &SExpr, width: usize) -> String {
+    let mut printer = Printer {
+        display: false,
+        labels: HashMap::new(),
+        next_label: 0,
+        out: String::new(),
+    };
+
+    find_labels(x, false, &mut HashSet::new(), &mut HashSet::new(), &mut printer.labels);
+    let doc = printer.doc(x);
+    let mut out = String::new();
+    layout(&doc, width, &mut out);
+    out
+}
+
+/// A written object, lists are kept apart so that they can be broken
+/// into lines.
+enum Doc {
+    Text(String),
+    /// The opening, like `(`, `#(`, `#0=(` or `'(`, and the items. The tail
+    /// of a dotted list comes after a `.` item.
+    List(String, Vec<Doc>),
+}
+
+impl Doc {
+    fn flat_len(&self) -> usize {
+        match self {
+            Doc::Text(x) => x.chars().count(),
+            Doc::List(open, items) => {
+                let spaces = items.len().saturating_sub(1);
+                open.chars().count() + items.iter().map(Doc::flat_len).sum::<usize>() + spaces + 1
+            }
+        }
+    }
+
+    fn is_dot(&self) -> bool {
+        match self {
+            Doc::Text(x) => x == ".",
+            _ => false
+        }
+    }
+
+    fn flat(&self, out: &mut String) {
         match self {
-            SExpr::Atom(x) => fmt.write_str(&format!("{}", x)),
-            SExpr::Procedure(x) => fmt.write_str(&format!("{}", x)),
-            SExpr::Unspecified => fmt.write_str("<unspecified>"),
-            SExpr::Port(_port) => fmt.write_str("#<a port>"),
-            SExpr::DottedList(xs, sexpr) => fmt.write_str(&format!("({} . {})", str_list(xs), sexpr)),
-            SExpr::List(xs) => fmt.write_str(&format!("({})", str_list(xs))),
+            Doc::Text(x) => out.push_str(x),
+            Doc::List(open, items) => {
+                out.push_str(open);
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    item.flat(out);
+                }
+                out.push(')');
+            }
+        }
+    }
+}
+
+/// How many items after the head of these forms stay on the first line,
+/// the rest is their body and is indented by two.
+fn body_form(name: &str, items: &[Doc]) -> Option<usize> {
+    match name {
+        "define" | "define-syntax" | "define-values" | "define-record-type"
+            | "lambda" | "λ" | "let*" | "letrec" | "letrec*" | "let-values"
+            | "let*-values" | "let-syntax" | "letrec-syntax" | "parameterize"
+            | "syntax-rules" | "when" | "unless" | "case" | "guard" => Some(1),
+        "do" | "receive" => Some(2),
+        // Named let
+        "let" => match items.get(1) {
+            Some(Doc::Text(x)) if is_symbol_text(x) => Some(2),
+            _ => Some(1)
+        },
+        _ => None
+    }
+}
+
+/// Checks if an atom is written as a symbol, and not as `()`, a number,
+/// a string or a `#` syntax.
+fn is_symbol_text(x: &str) -> bool {
+    !x.is_empty() && !x.starts_with(|c| c == '(' || c == '"' || c == '#') && parse_number(x).is_none()
+}
+
+fn current_column(out: &str) -> usize {
+    out[out.rfind('\n').map_or(0, |i| i + 1)..].chars().count()
+}
+
+fn is_body_form(head: &Doc, items: &[Doc]) -> bool {
+    match head {
+        Doc::Text(head) => body_form(head, items).is_some(),
+        _ => false
+    }
+}
+
+fn layout(doc: &Doc, width: usize, out: &mut String) {
+    let column = current_column(out);
+    let (open, items) = match doc {
+        Doc::List(open, items) if !items.is_empty() && column + doc.flat_len() > width => (open, items),
+        _ => return doc.flat(out)
+    };
+
+    out.push_str(open);
+    let inner = column + open.chars().count();
+
+    // Lists of atoms, like data and vectors, are filled line by line
+    if items.iter().all(|x| match x { Doc::Text(_) => true, _ => false }) && !is_body_form(&items[0], items) {
+        for (i, item) in items.iter().enumerate() {
+            // The dot of a dotted tail stays on the line of the tail
+            let dotted = i > 0 && items[i - 1].is_dot();
+            let len = if item.is_dot() { 2 + items[i + 1].flat_len() } else { item.flat_len() };
+            if i > 0 && !dotted && current_column(out) + 1 + len + 1 > width {
+                out.push('\n');
+                out.push_str(&" ".repeat(inner));
+            } else if i > 0 {
+                out.push(' ');
+            }
+            item.flat(out);
+        }
+        out.push(')');
+        return
+    }
+
+    // Items on the first line and the indentation of the rest:
+    // (define (f x)     (f a          ((f x)
+    //   body)              b)          a)
+    let (first_line, indent) = match items[0] {
+        Doc::Text(ref head) => match body_form(head, items) {
+            Some(n) => (n + 1, column + 2),
+            None if items.len() > 1 => (2, inner + head.chars().count() + 1),
+            None => (1, inner)
+        },
+        Doc::List(_, _) => (1, inner)
+    };
+
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 && (i < first_line || items[i - 1].is_dot()) {
+            out.push(' ');
+        } else if i > 0 {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent));
+        }
+        layout(item, width, out);
+    }
+    out.push(')');
+}
+
+/// Objects that can be shared or be a part of a cycle, identified by
+/// their addresses.
+fn identity(x: &SExpr) -> Option<usize> {
+    match x {
+        SExpr::Pair(x) => Some(x.id()),
+        SExpr::Vector(xs) => Some(&*xs.borrow() as *const _ as usize),
+        SExpr::Record(x) => Some(&*x.values.borrow() as *const _ as usize),
+        _ => None
+    }
+}
+
+/// Finds the objects that need labels. `seen` are the objects that are
+/// visited before, `active` are the ones that are being visited.
+fn find_labels(x: &SExpr, shared: bool, seen: &mut HashSet<usize>, active: &mut HashSet<usize>, labels: &mut HashMap<usize, Option<usize>>) {
+    let id = match identity(x) {
+        Some(id) => id,
+        None => return
+    };
+
+    if active.contains(&id) || (shared && seen.contains(&id)) {
+        labels.insert(id, None);
+        return
+    }
+    if !seen.insert(id) {
+        return
+    }
+
+    match x {
+        // The rest of a list is walked in a loop instead of recursion, so
+        // that long lists don't overflow the stack
+        SExpr::Pair(_) => {
+            let mut spine = vec![id];
+            active.insert(id);
+            let mut current = x.clone();
+            while let SExpr::Pair(pair) = current {
+                find_labels(&pair.car(), shared, seen, active, labels);
+                current = pair.cdr();
+                match identity(&current) {
+                    Some(next) if current.is_pair() && !active.contains(&next) && !seen.contains(&next) => {
+                        seen.insert(next);
+                        active.insert(next);
+                        spine.push(next);
+                    },
+                    _ => {
+                        find_labels(&current, shared, seen, active, labels);
+                        break
+                    }
+                }
+            }
+
+            for id in spine {
+                active.remove(&id);
+            }
+        },
+        SExpr::Vector(xs) => {
+            active.insert(id);
+            for x in xs.borrow().iter() {
+                find_labels(x, shared, seen, active, labels);
+            }
+            active.remove(&id);
+        },
+        SExpr::Record(record) => {
+            active.insert(id);
+            for x in record.values.borrow().iter() {
+                find_labels(x, shared, seen, active, labels);
+            }
+            active.remove(&id);
+        },
+        _ => ()
+    }
+}
+
+struct Printer {
+    display: bool,
+    /// Objects that need labels and the labels they got, if they are
+    /// written already.
+    labels: HashMap<usize, Option<usize>>,
+    next_label: usize,
+    out: String,
+}
+
+impl Printer {
+    /// Writes the label of `x` if it has one. Returns true if `x` is
+    /// written before, so only a reference to it is needed.
+    fn label(&mut self, x: &SExpr) -> bool {
+        let id = match identity(x) {
+            Some(id) => id,
+            None => return false
+        };
+
+        match self.labels.get(&id).cloned() {
+            Some(Some(n)) => {
+                self.out.push_str(&format!("#{}#", n));
+                true
+            },
+            Some(None) => {
+                let n = self.next_label;
+                self.next_label += 1;
+                self.labels.insert(id, Some(n));
+                self.out.push_str(&format!("#{}=", n));
+                false
+            },
+            None => false
+        }
+    }
+
+    fn has_label(&self, x: &SExpr) -> bool {
+        identity(x).map_or(false, |id| self.labels.contains_key(&id))
+    }
+
+    /// Builds the `Doc` of `x`, labels are given in the order they are
+    /// written.
+    fn doc(&mut self, x: &SExpr) -> Doc {
+        let saved = mem::replace(&mut self.out, String::new());
+        let items = match x {
+            SExpr::Pair(_) | SExpr::Vector(_) if !self.label(x) => {
+                if let Some((abbreviation, datum)) = self.abbreviation(x) {
+                    let prefix = mem::replace(&mut self.out, saved) + abbreviation;
+                    return match self.doc(&datum) {
+                        Doc::Text(text) => Doc::Text(prefix + &text),
+                        Doc::List(open, items) => Doc::List(prefix + &open, items)
+                    }
+                }
+
+                let mut items = vec![];
+                if let SExpr::Vector(xs) = x {
+                    self.out.push_str("#(");
+                    for x in xs.borrow().clone().iter() {
+                        items.push(self.doc(x));
+                    }
+                } else {
+                    self.out.push('(');
+                    let mut rest = x.clone();
+                    while let SExpr::Pair(pair) = rest {
+                        items.push(self.doc(&pair.car()));
+                        rest = pair.cdr();
+                        match rest {
+                            SExpr::Nil => break,
+                            SExpr::Pair(_) if !self.has_label(&rest) => (),
+                            ref tail => {
+                                items.push(Doc::Text(".".to_string()));
+                                items.push(self.doc(tail));
+                                break
+                            }
+                        }
+                    }
+                }
+                Some(items)
+            },
+            SExpr::Pair(_) | SExpr::Vector(_) => None,
+            x => {
+                self.print(x);
+                None
+            }
+        };
+
+        let text = mem::replace(&mut self.out, saved);
+        match items {
+            Some(items) => Doc::List(text, items),
+            None => Doc::Text(text)
+        }
+    }
+
+    /// The abbreviation of `x` and the datum after it, if `x` is like
+    /// `(quote datum)`.
+    fn abbreviation(&self, x: &SExpr) -> Option<(&'static str, SExpr)> {
+        let pair = match x {
+            SExpr::Pair(pair) => pair,
+            _ => return None
+        };
+
+        let abbreviation = match pair.car() {
+            SExpr::Atom(Token::Symbol(ref name)) => match &**name {
+                "quote" => "'",
+                "quasiquote" => "`",
+                "unquote" => ",",
+                "unquote-splicing" => ",@",
+                _ => return None
+            },
+            _ => return None
+        };
+
+        match pair.cdr() {
+            // The label of the rest of the list has to be written
+            ref rest if self.has_label(rest) => None,
+            SExpr::Pair(ref rest) if rest.cdr().is_nil() => Some((abbreviation, rest.car())),
+            _ => None
+        }
+    }
+
+    fn print_all(&mut self, xs: &[SExpr]) {
+        for (i, x) in xs.iter().enumerate() {
+            if i > 0 {
+                self.out.push(' ');
+            }
+            self.print(x);
+        }
+    }
+
+    fn print(&mut self, x: &SExpr) {
+        if self.label(x) {
+            return
+        }
+
+        match x {
+            SExpr::Atom(Token::Str(x)) if self.display => self.out.push_str(&x.borrow()),
+            SExpr::Atom(Token::Chr(x)) if self.display => self.out.push(*x),
+            SExpr::Atom(x) => self.out.push_str(&x.to_string()),
+            SExpr::Procedure(x) => self.out.push_str(&x.to_string()),
+            SExpr::Eof => self.out.push_str("#<eof>"),
+            SExpr::Unspecified => self.out.push_str("<unspecified>"),
+            SExpr::Record(x) => {
+                self.out.push_str(&format!("#<record {}", x.rtype.name()));
+                let values = x.values.borrow().clone();
+                for (field, value) in x.rtype.0.fields.iter().zip(values.iter()) {
+                    self.out.push_str(&format!(" {}: ", base_name(field)));
+                    self.print(value);
+                }
+                self.out.push('>');
+            },
+            SExpr::RecordType(x) => self.out.push_str(&format!("#<record-type {}>", x.name())),
+            SExpr::HashTable(x) => self.out.push_str(&format!("#<hash-table {}>", x.len())),
+            SExpr::Port(_port) => self.out.push_str("#<a port>"),
+            SExpr::Macro(_) => self.out.push_str("#<macro>"),
+            SExpr::Condition(x) => self.out.push_str(&format!("#<condition: {}>", x.0)),
+            SExpr::Values(xs) => self.print_all(xs),
+            SExpr::Nil => self.out.push_str("()"),
+            SExpr::Vector(xs) => {
+                let xs = xs.borrow().clone();
+                self.out.push_str("#(");
+                self.print_all(&xs);
+                self.out.push(')');
+            },
+            SExpr::Bytevector(xs) => {
+                let u8s = xs.borrow()
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>();
+                self.out.push_str(&format!("#u8({})", u8s.join(" ")));
+            },
+            SExpr::Pair(pair) => {
+                self.out.push('(');
+                self.print(&pair.car());
+                let mut rest = pair.cdr();
+                loop {
+                    match rest {
+                        SExpr::Nil => break,
+                        // A pair with a label has to be written as the
+                        // dotted tail, so that the label has a place
+                        SExpr::Pair(ref next) if !self.has_label(&rest) => {
+                            self.out.push(' ');
+                            self.print(&next.car());
+                            let cdr = next.cdr();
+                            rest = cdr;
+                        },
+                        tail => {
+                            self.out.push_str(" . ");
+                            self.print(&tail);
+                            break
+                        }
+                    }
+                }
+                self.out.push(')');
+            },
         };
-        Ok(())
     }
 }
 
@@ -58,6 +507,8 @@ impl fmt::Display for ProcedureData {
         match self {
             ProcedureData::Compound(x)  => fmt.write_str(&format!("{}", x)),
             ProcedureData::Primitive(x) => fmt.write_str(&format!("{}", x)),
+            ProcedureData::Continuation(_) => fmt.write_str("#<continuation>"),
+            ProcedureData::Parameter(_) => fmt.write_str("#<parameter>"),
         };
         Ok(())
     }
@@ -67,7 +518,10 @@ impl fmt::Display for ProcedureData {
 #[allow(unused_must_use)]
 impl fmt::Display for CompoundData {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.write_str(&format!("#<compound-procedure {:?}>", self as *const _));
+        match self.name {
+            Some(ref name) => fmt.write_str(&format!("#<compound-procedure {}>", name)),
+            None => fmt.write_str(&format!("#<compound-procedure {:?}>", self as *const _))
+        };
         Ok(())
     }
 }
@@ -80,16 +534,60 @@ impl fmt::Display for PrimitiveData {
     }
 }
 
-#[allow(unused_must_use)]
-fn str_list(xs: &[SExpr]) -> String {
+/// Symbols that wouldn't be read back as the same symbol are written
+/// between bars.
+fn format_symbol(name: &str) -> String {
+    let plain = !name.is_empty()
+        && name != "."
+        && !name.starts_with(|c| "#'`,".contains(c))
+        && !name.chars().any(|c| c.is_whitespace() || "()[]\";|\\".contains(c))
+        && parse_number(name).is_none();
+
+    if plain {
+        name.to_string()
+    } else {
+        format!("|{}|", escape(name, '|'))
+    }
+}
+
+fn format_char(chr: char) -> String {
+    let name = match chr {
+        '\x07' => "alarm",
+        '\x08' => "backspace",
+        '\x7f' => "delete",
+        '\x1b' => "escape",
+        '\n' => "newline",
+        '\0' => "null",
+        '\r' => "return",
+        ' ' => "space",
+        '\t' => "tab",
+        c if c.is_control() => return format!("#\\x{:x}", c as u32),
+        c => return format!("#\\{}", c)
+    };
+
+    format!("#\\{}", name)
+}
 
-    let mut lstr = String::new();
-    let mut sp = "";
-    for x in xs {
-        lstr.push_str(sp);
-        lstr.push_str(&format!("{}", x));
-        sp = " ";
+/// Escapes the contents of a string or a `|symbol|` that is delimited
+/// with `delimiter`.
+fn escape(string: &str, delimiter: char) -> String {
+    let mut result = String::new();
+    for c in string.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\x07' => result.push_str("\\a"),
+            '\x08' => result.push_str("\\b"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            c if c == delimiter => {
+                result.push('\\');
+                result.push(c);
+            },
+            c if c.is_control() => result.push_str(&format!("\\x{:x};", c as u32)),
+            c => result.push(c)
+        }
     }
 
-    lstr
+    result
 }
@@ -0,0 +1,130 @@
+use parser::SExpr;
+use evaluator::Args;
+use serr::{SErr, SResult};
+use utils::new_rc_ref_cell;
+use primitives::vector::{index, bounds};
+
+pub fn new_bytevector(u8s: Vec<u8>) -> SExpr {
+    SExpr::Bytevector(new_rc_ref_cell(u8s))
+}
+
+pub fn bytevector(args: Args) -> SResult<SExpr> {
+    let u8s = args.evaled()?
+        .into_iter()
+        .map(|x| x.into_u8())
+        .collect::<SResult<_>>()?;
+
+    Ok(new_bytevector(u8s))
+}
+
+pub fn make_bytevector(args: Args) -> SResult<SExpr> {
+    let mut iter = args.evaled()?.into_iter();
+    let len = index(iter.next().ok_or_else(|| SErr::WrongArgCount(1, 0))?)?;
+    let fill = match iter.next() {
+        Some(x) => x.into_u8()?,
+        None => 0
+    };
+
+    Ok(new_bytevector(vec![fill; len]))
+}
+
+pub fn bytevector_length(args: Args) -> SResult<SExpr> {
+    let bytevector = args.evaled()?.own_one()?.as_bytevector()?;
+    let len = bytevector.borrow().len();
+    Ok(len.into())
+}
+
+pub fn bytevector_u8_ref(args: Args) -> SResult<SExpr> {
+    let (bytevector_, k_) = args.evaled()?.own_two()?;
+    let bytevector = bytevector_.as_bytevector()?;
+    let k = index(k_)?;
+    let u8s = bytevector.borrow();
+
+    u8s.get(k)
+        .map(|&u| sint!(i64::from(u)))
+        .ok_or_else(|| SErr::IndexOutOfBounds(u8s.len(), k))
+}
+
+pub fn bytevector_u8_set_em(args: Args) -> SResult<SExpr> {
+    let (bytevector_, k_, byte) = args.evaled()?.own_three()?;
+    let bytevector = bytevector_.as_bytevector()?;
+    let k = index(k_)?;
+    let byte = byte.into_u8()?;
+    let mut u8s = bytevector.borrow_mut();
+    let len = u8s.len();
+
+    *u8s.get_mut(k).ok_or_else(|| SErr::IndexOutOfBounds(len, k))? = byte;
+    Ok(SExpr::Unspecified)
+}
+
+pub fn bytevector_copy(args: Args) -> SResult<SExpr> {
+    let (u8s, start, end) = bytevector_range(args)?;
+    Ok(new_bytevector(u8s[start..end].to_vec()))
+}
+
+/// `(bytevector-copy! to at from [start [end]])`
+pub fn bytevector_copy_em(args: Args) -> SResult<SExpr> {
+    let mut iter = args.evaled()?.into_iter();
+    let to = iter.next()
+        .ok_or_else(|| SErr::WrongArgCount(3, 0))?
+        .as_bytevector()?;
+    let at = index(iter.next().ok_or_else(|| SErr::WrongArgCount(3, 1))?)?;
+    let from = iter.next()
+        .ok_or_else(|| SErr::WrongArgCount(3, 2))?
+        .as_bytevector()?;
+
+    let u8s = {
+        let from = from.borrow();
+        let (start, end) = bounds(from.len(), iter.collect())?;
+        from[start..end].to_vec()
+    };
+
+    let mut to = to.borrow_mut();
+    if at > to.len() || to.len() - at < u8s.len() {
+        bail!(IndexOutOfBounds => to.len(), at + u8s.len())
+    }
+
+    to[at..at + u8s.len()].copy_from_slice(&u8s);
+    Ok(SExpr::Unspecified)
+}
+
+pub fn bytevector_append(args: Args) -> SResult<SExpr> {
+    let mut result = vec![];
+    for x in args.evaled()?.into_iter() {
+        result.extend_from_slice(&x.as_bytevector()?.borrow());
+    }
+
+    Ok(new_bytevector(result))
+}
+
+pub fn utf8_to_string(args: Args) -> SResult<SExpr> {
+    let (u8s, start, end) = bytevector_range(args)?;
+    let string = String::from_utf8(u8s[start..end].to_vec())
+        .map_err(|_| SErr::new_generic("utf8->string: invalid UTF-8 sequence"))?;
+
+    Ok(sstr!(string))
+}
+
+pub fn string_to_utf8(args: Args) -> SResult<SExpr> {
+    let (string_, rest) = args.evaled()?.own_one_rest()?;
+    let chars = string_.into_str()?
+        .chars()
+        .collect::<Vec<_>>();
+    let (start, end) = bounds(chars.len(), rest)?;
+    let string = chars[start..end].iter().collect::<String>();
+
+    Ok(new_bytevector(string.into_bytes()))
+}
+
+//
+// Helpers
+//
+/// Returns the bytes of a `(f bytevector [start [end]])` call alongside
+/// the range.
+fn bytevector_range(args: Args) -> SResult<(Vec<u8>, usize, usize)> {
+    let (bytevector_, rest) = args.evaled()?.own_one_rest()?;
+    let u8s = bytevector_.as_bytevector()?.borrow().clone();
+    let (start, end) = bounds(u8s.len(), rest)?;
+
+    Ok((u8s, start, end))
+}
@@ -1,84 +1,135 @@
 use parser::SExpr;
 use parser::SExprs;
-use evaluator::Args;
-use evaluator::ToArgs;
+use env::EnvRef;
+use evaluator::{self, Args, State, ContRef, Cont, Frame};
+use primitives::equivalence::eqv;
 use serr::{SErr, SResult};
 
+//
+// Every form evaluates its last expression in tail position, so they only
+// evaluate the tests themselves and let the evaluator handle the rest
+// through `Frame::Call`.
+//
 
-pub fn cond(args: Args) -> SResult<SExpr> {
-    let clauses = args.iter()
-        .map(|x| {
-            if let SExpr::List(clause) = x {
-                let mut current = 0;
-                let test = clause.get(current)
-                    .ok_or_else(|| SErr::new_unexpected_form(x))?;
-
-                current += 1;
-                if clause.len() == 3 {
-                    // Consume `=>`
-                    // FIXME: check if `clause.get(current)` is => otherwise panic!
-                    current += 1;
-                }
-
-                let expr = clause.get(current)
-                    .ok_or_else(|| SErr::new_unexpected_form(x))?;
-
-                Ok((test, expr))
-            } else {
-                bail!(TypeMismatch => "list of clauses", x)
-            }
-        })
-        .collect::<SResult<Vec<_>>>()?;
-
-    let mut else_clause: Option<SExpr> = None;
-    for (test, expr) in clauses {
-        if test.is_symbol("else") {
-            if else_clause.is_some() { bail!(UnexpectedForm => test) }
-            else_clause = Some(expr.clone());
-        } else if test.eval(&args.env)?.to_bool() {
-            return expr.eval(&args.env)
-        }
+pub fn cond(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    next_clause(args.into_iter().collect(), env, k)
+}
+
+fn next_clause(clauses: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    let test = match clauses.first() {
+        Some(SExpr::Pair(clause)) => clause.car(),
+        Some(x) => bail!(TypeMismatch => "list of clauses", x.clone()),
+        None => return Ok(State::Return(SExpr::Unspecified, k))
+    };
+
+    if test.is_symbol("else") {
+        if clauses.len() > 1 { bail!(UnexpectedForm => test) }
+        let body = clause_body(clauses)?;
+        return evaluator::eval_body(body, env, k)
     }
 
-    if else_clause.is_some() {
-        else_clause.unwrap()
-            .eval(&args.env)
+    let k = Cont::push(Frame::Call(cond_test, clauses, env.clone_ref()), k);
+    Ok(State::Eval(test, env, k))
+}
+
+fn cond_test(value: SExpr, mut clauses: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    if value.to_bool() {
+        clauses.truncate(1);
+        let body = clause_body(clauses)?;
+        clause_result(value, body, env, k)
     } else {
-        Ok(SExpr::Unspecified)
+        clauses.remove(0);
+        next_clause(clauses, env, k)
+    }
+}
+
+pub fn case(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (key, clauses) = args.own_one_rest()?;
+
+    let k = Cont::push(Frame::Call(case_key, clauses, env.clone_ref()), k);
+    Ok(State::Eval(key, env, k))
+}
+
+fn case_key(key: SExpr, clauses: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    for clause in clauses {
+        let matches = match clause {
+            SExpr::Pair(ref xs) => match xs.car() {
+                ref x if x.is_symbol("else") => true,
+                data@SExpr::Nil | data@SExpr::Pair(_) => data.into_list()?.iter().any(|x| eqv(x, &key)),
+                x => bail!(TypeMismatch => "list of data", x)
+            },
+            x => bail!(UnexpectedForm => x)
+        };
+
+        if matches {
+            let body = clause_body(vec![clause])?;
+            return clause_result(key, body, env, k)
+        }
     }
+
+    Ok(State::Return(SExpr::Unspecified, k))
+}
+
+pub fn or(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    next_test(sbool!(false), args.into_iter().collect(), env, k, true)
 }
 
-pub fn case(args: Args) -> SResult<SExpr> {
-    let test = args.get(0)
-        .ok_or_else(|| SErr::WrongArgCount(1,0))?;
-
-    let args_vec: SExprs = args.iter()
-        .skip(1)
-        .map(|clause| {
-            if let SExpr::List(xs) = clause {
-                let test = slist![ssymbol!("eqv?"), xs[0].clone(), test.clone()];
-                Ok(slist![test, xs[1].clone()])
-            } else {
-                bail!(UnexpectedForm => clause)
-            }
-        })
-        .collect::<SResult<_>>()?;
-
-    cond(args_vec.to_args(&args.env))
+pub fn and(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    next_test(sbool!(true), args.into_iter().collect(), env, k, false)
 }
 
-pub fn or(args: Args) -> SResult<SExpr> {
-    for expr in args.iter() {
-        if expr.eval(&args.env)?.to_bool() { return Ok(sbool!(true)) }
+fn or_test(value: SExpr, exprs: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    next_test(value, exprs, env, k, true)
+}
+
+fn and_test(value: SExpr, exprs: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    next_test(value, exprs, env, k, false)
+}
+
+/// Stops at the first value whose truthiness is `stop_at`, otherwise
+/// continues with the next expression. The last one is in tail position.
+fn next_test(value: SExpr, mut exprs: SExprs, env: EnvRef, k: ContRef, stop_at: bool) -> SResult<State> {
+    if exprs.is_empty() || value.to_bool() == stop_at {
+        return Ok(State::Return(value, k))
+    }
+
+    let expr = exprs.remove(0);
+    if exprs.is_empty() {
+        return Ok(State::Eval(expr, env, k))
     }
 
-    Ok(sbool!(false))
+    let callback = if stop_at { or_test } else { and_test };
+    let k = Cont::push(Frame::Call(callback, exprs, env.clone_ref()), k);
+    Ok(State::Eval(expr, env, k))
 }
 
-pub fn and(args: Args) -> SResult<SExpr> {
-    for expr in args.iter() {
-        if !expr.eval(&args.env)?.to_bool() { return Ok(sbool!(false)) }
+//
+// Helpers
+//
+/// Returns the expressions that come after the test of the clause.
+fn clause_body(clauses: SExprs) -> SResult<SExprs> {
+    match clauses.into_iter().next() {
+        Some(SExpr::Pair(xs)) => xs.cdr().into_list(),
+        Some(x) => bail!(TypeMismatch => "list of clauses", x),
+        None => Err(SErr::FoundNothing)
     }
+}
 
-    Ok(sbool!(true))
+/// Handles `(test => receiver)` clauses, clauses without a body and
+/// the ordinary ones.
+fn clause_result(value: SExpr, body: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    if body.is_empty() {
+        Ok(State::Return(value, k))
+    } else if body[0].is_symbol("=>") {
+        let receiver = body.into_iter()
+            .nth(1)
+            .ok_or_else(|| SErr::new_expr_not_found("nothing"))?;
+        Ok(State::Eval(slist![receiver, quote!(value)], env, k))
+    } else {
+        evaluator::eval_body(body, env, k)
+    }
 }
@@ -0,0 +1,51 @@
+use parser::SExpr;
+use parser::SExprs;
+use env::EnvRef;
+use evaluator;
+use evaluator::{Args, State, ContRef, Cont, Frame};
+use procedure::{ProcedureData, ContinuationData};
+use serr::SResult;
+
+pub fn call_cc(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let procedure = args.own_one()?;
+    let continuation = SExpr::Procedure(ProcedureData::Continuation(ContinuationData {
+        k: k.clone(),
+        winders: evaluator::current_winders(),
+        handlers: evaluator::current_handlers(),
+        run: evaluator::current_run()
+    }));
+
+    Ok(State::Apply(procedure, vec![continuation], env, k))
+}
+
+pub fn dynamic_wind(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (before, thunk, after) = args.own_three()?;
+
+    evaluator::apply(&before, vec![], &env)?;
+    evaluator::push_winder(before, after, &env);
+
+    // `after` is called when the thunk returns, see `Frame::Unwind`.
+    Ok(State::Apply(thunk, vec![], env, Cont::push(Frame::Unwind, k)))
+}
+
+pub fn values(args: Args) -> SResult<SExpr> {
+    if args.len() == 1 {
+        args.own_one()
+    } else {
+        Ok(SExpr::Values(args.into_iter().collect()))
+    }
+}
+
+pub fn call_with_values(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (producer, consumer) = args.own_two()?;
+
+    let k = Cont::push(Frame::Call(apply_consumer, vec![consumer], env.clone_ref()), k);
+    Ok(State::Apply(producer, vec![], env, k))
+}
+
+fn apply_consumer(values: SExpr, mut consumer: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    Ok(State::Apply(consumer.remove(0), values.into_values(), env, k))
+}
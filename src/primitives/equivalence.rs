@@ -1,4 +1,5 @@
-use lexer::Token;
+use std::rc::Rc;
+
 use parser::SExpr;
 use evaluator::Args;
 use serr::SResult;
@@ -8,44 +9,39 @@ pub fn eq_qm(args: Args) -> SResult<SExpr> {
 }
 
 pub fn eqv_qm(args: Args) -> SResult<SExpr> {
-    equality(args, |args| {
-        let evaled = args.eval()?;
-        let result = match (&evaled[0], &evaled[1]) {
-            (SExpr::Atom(x), SExpr::Atom(y)) => x == y,
-            (SExpr::List(x), SExpr::List(y)) => x.is_empty() && y.is_empty(),
-            (_,_) => false
-        };
-
-        Ok(result)
-    })
+    equality(args, |x, y| eqv(x, y))
 }
 
 pub fn equal_qm(args: Args) -> SResult<SExpr> {
-    equality(args, |args| {
-        let evaled = args.eval()?;
-        let obj1 = &evaled[0];
-        let obj2 = &evaled[1];
+    equality(args, |x, y| x == y)
+}
 
-        Ok(obj1 == obj2)
-    })
+pub fn eqv(x: &SExpr, y: &SExpr) -> bool {
+    match (x, y) {
+        (SExpr::Atom(x), SExpr::Atom(y)) => x == y,
+        (SExpr::Nil, SExpr::Nil) => true,
+        (SExpr::Eof, SExpr::Eof) => true,
+        (SExpr::Pair(x), SExpr::Pair(y)) => x.ptr_eq(y),
+        (SExpr::Vector(x), SExpr::Vector(y)) => Rc::ptr_eq(x, y),
+        (SExpr::Bytevector(x), SExpr::Bytevector(y)) => Rc::ptr_eq(x, y),
+        (x@SExpr::Record(_), y@SExpr::Record(_)) => x == y,
+        (x@SExpr::RecordType(_), y@SExpr::RecordType(_)) => x == y,
+        (x@SExpr::HashTable(_), y@SExpr::HashTable(_)) => x == y,
+        (x@SExpr::Procedure(_), y@SExpr::Procedure(_)) => x == y,
+        (_,_) => false
+    }
 }
 
-fn equality<F>(args: Args, mut non_atom: F) -> SResult<SExpr>
-where F: (FnMut(&Args) -> SResult<bool>) {
+fn equality<F>(args: Args, non_atom: F) -> SResult<SExpr>
+where F: (Fn(&SExpr, &SExpr) -> bool) {
     if args.len() < 2 {
         return Ok(sbool!(true));
     }
 
-    let result = match (&args[0], &args[1]) {
-        (x@SExpr::Atom(Token::Symbol(_)), y@SExpr::Atom(Token::Symbol(_))) => {
-            x.eval_ref(&args.env, |x| {
-                y.eval_ref(&args.env, |y| Ok(x == y))
-            })?
-        },
+    let evaled = args.evaled()?;
+    let result = match (&evaled[0], &evaled[1]) {
         (SExpr::Atom(x), SExpr::Atom(y)) => x == y,
-        _ => {
-            non_atom(&args)?
-        }
+        (x, y) => non_atom(x, y)
     };
 
     Ok(sbool!(result))
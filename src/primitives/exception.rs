@@ -0,0 +1,115 @@
+use parser::SExpr;
+use parser::SExprs;
+use env::{Env, EnvRef};
+use evaluator;
+use evaluator::{Args, State, ContRef, Cont, Frame, Handler};
+use procedure::{ProcedureData, ContinuationData};
+use primitives::conditionals;
+use serr::{SErr, SResult};
+
+use std::rc::Rc;
+
+pub fn raise(args: Args) -> SResult<SExpr> {
+    // The evaluator passes the object to the current handler
+    bail!(Raised => args.own_one()?)
+}
+
+pub fn raise_continuable(args: Args, k: ContRef) -> SResult<State> {
+    evaluator::raise(args.own_one()?, true, k)
+}
+
+pub fn error(args: Args) -> SResult<SExpr> {
+    let (message, irritants) = args.own_one_rest()?;
+    bail!(Error => message.into_str()?, irritants)
+}
+
+pub fn with_exception_handler(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (handler, thunk) = args.own_two()?;
+    handler.as_proc()?;
+
+    let outer = evaluator::current_handlers();
+    evaluator::set_handlers(Some(Rc::new(Handler {
+        handler,
+        env: env.clone_ref(),
+        parent: outer.clone()
+    })));
+
+    Ok(State::Apply(thunk, vec![], env, Cont::push(Frame::Handlers(outer), k)))
+}
+
+/// `(guard (var clause ...) body ...)`
+/// Installs a handler that jumps back to the `guard` and evaluates the
+/// clauses like `cond` with `var` bound to the raised object. If no clause
+/// matches, the object is raised again.
+pub fn guard(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (spec, body) = args.own_one_rest()?;
+    let clauses = spec.into_list()?;
+    if clauses.is_empty() {
+        bail!(WrongArgCount => 1 as usize, 0 as usize)
+    }
+    clauses[0].as_symbol()?;
+
+    let outer = evaluator::current_handlers();
+    let catch = Cont::push(Frame::Call(guard_clauses, clauses, env.clone_ref()), k.clone());
+    let handler = SExpr::Procedure(ProcedureData::Continuation(ContinuationData {
+        k: catch,
+        winders: evaluator::current_winders(),
+        handlers: outer.clone(),
+        run: evaluator::current_run()
+    }));
+
+    evaluator::set_handlers(Some(Rc::new(Handler {
+        handler,
+        env: env.clone_ref(),
+        parent: outer.clone()
+    })));
+
+    evaluator::eval_body(body, env, Cont::push(Frame::Handlers(outer), k))
+}
+
+fn guard_clauses(obj: SExpr, mut clauses: SExprs, parent_env: EnvRef, k: ContRef) -> SResult<State> {
+    let var = clauses.remove(0).into_symbol()?;
+    let env = Env::new(parent_env).into_ref();
+    env.define(var.clone(), obj);
+
+    let has_else = match clauses.last() {
+        Some(SExpr::Pair(xs)) => xs.car().is_symbol("else"),
+        _ => false
+    };
+
+    if !has_else {
+        // (else (raise var))
+        let reraise = slist![ProcedureData::new_primitive(raise), ssymbol!(var)];
+        clauses.push(slist![ssymbol!("else"), reraise]);
+    }
+
+    conditionals::cond(Args::new(clauses, &env), k)
+}
+
+pub fn error_object_message(args: Args) -> SResult<SExpr> {
+    Ok(sstr!(condition(args)?.message()))
+}
+
+pub fn error_object_irritants(args: Args) -> SResult<SExpr> {
+    Ok(SExpr::list(condition(args)?.irritants()))
+}
+
+pub fn file_error_qm(args: Args) -> SResult<SExpr> {
+    Ok(sbool!(condition(args).map_or(false, |x| x.is_file_error())))
+}
+
+pub fn read_error_qm(args: Args) -> SResult<SExpr> {
+    Ok(sbool!(condition(args).map_or(false, |x| x.is_read_error())))
+}
+
+//
+// Helpers
+//
+fn condition(args: Args) -> SResult<Rc<SErr>> {
+    match args.own_one()? {
+        SExpr::Condition(x) => Ok(x.0),
+        x => bail!(TypeMismatch => "error object", x)
+    }
+}
@@ -0,0 +1,120 @@
+use parser::SExpr;
+use env::EnvRef;
+use evaluator::Args;
+use hash_table::{HashTableData, Equivalence};
+use serr::{SErr, SResult};
+
+/// `(make-hash-table [equivalence])`, `equivalence` is one of `eq?`,
+/// `eqv?`, `equal?`, `=` or `string=?` and defaults to `equal?`.
+pub fn make_hash_table(args: Args) -> SResult<SExpr> {
+    let env = args.env();
+    let evaled = args.evaled()?;
+    let equivalence = match evaled.len() {
+        0 => Equivalence::Equal,
+        1 => equivalence_of(&evaled[0], &env)?,
+        x => bail!(WrongArgCount => 1 as usize, x)
+    };
+
+    Ok(SExpr::HashTable(HashTableData::new(equivalence)))
+}
+
+/// `(alist->hash-table alist [equivalence])`, the first one wins if a key
+/// appears more than once.
+pub fn alist_to_hash_table(args: Args) -> SResult<SExpr> {
+    let env = args.env();
+    let (alist, rest) = args.evaled()?.own_one_rest()?;
+    let equivalence = match rest.len() {
+        0 => Equivalence::Equal,
+        1 => equivalence_of(&rest[0], &env)?,
+        x => bail!(WrongArgCount => 2 as usize, x + 1)
+    };
+
+    let table = HashTableData::new(equivalence);
+    for pair in alist.into_list()?.into_iter().rev() {
+        table.insert(pair.car()?, pair.cdr()?);
+    }
+
+    Ok(SExpr::HashTable(table))
+}
+
+pub fn hash_table_ref_default(args: Args) -> SResult<SExpr> {
+    let (table, key, default) = args.evaled()?.own_three()?;
+    Ok(table.into_hash_table()?.get(&key).unwrap_or(default))
+}
+
+pub fn hash_table_set_em(args: Args) -> SResult<SExpr> {
+    let (table, key, value) = args.evaled()?.own_three()?;
+    table.into_hash_table()?.insert(key, value);
+    Ok(SExpr::Unspecified)
+}
+
+pub fn hash_table_delete_em(args: Args) -> SResult<SExpr> {
+    let (table, key) = args.evaled()?.own_two()?;
+    table.into_hash_table()?.remove(&key);
+    Ok(SExpr::Unspecified)
+}
+
+pub fn hash_table_contains_qm(args: Args) -> SResult<SExpr> {
+    let (table, key) = args.evaled()?.own_two()?;
+    Ok(sbool!(table.into_hash_table()?.get(&key).is_some()))
+}
+
+pub fn hash_table_size(args: Args) -> SResult<SExpr> {
+    let table = args.evaled()?.own_one()?.into_hash_table()?;
+    Ok(table.len().into())
+}
+
+pub fn hash_table_keys(args: Args) -> SResult<SExpr> {
+    let table = args.evaled()?.own_one()?.into_hash_table()?;
+    let keys = table.entries()
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+
+    Ok(SExpr::list(keys))
+}
+
+pub fn hash_table_values(args: Args) -> SResult<SExpr> {
+    let table = args.evaled()?.own_one()?.into_hash_table()?;
+    let values = table.entries()
+        .into_iter()
+        .map(|(_, v)| v)
+        .collect();
+
+    Ok(SExpr::list(values))
+}
+
+pub fn hash_table_to_alist(args: Args) -> SResult<SExpr> {
+    let table = args.evaled()?.own_one()?.into_hash_table()?;
+    let alist = table.entries()
+        .into_iter()
+        .map(|(k, v)| SExpr::cons(k, v))
+        .collect();
+
+    Ok(SExpr::list(alist))
+}
+
+pub fn hash_table_copy(args: Args) -> SResult<SExpr> {
+    // (hash-table-copy table [mutable?]), every table is mutable here.
+    let (table, _) = args.evaled()?.own_one_rest()?;
+    Ok(SExpr::HashTable(table.into_hash_table()?.copy()))
+}
+
+pub fn hash_table_clear_em(args: Args) -> SResult<SExpr> {
+    args.evaled()?.own_one()?.into_hash_table()?.clear();
+    Ok(SExpr::Unspecified)
+}
+
+//
+// Helpers
+//
+/// Finds the equivalence of `procedure` by comparing it to the
+/// equivalence procedures that the table is created in the reach of.
+fn equivalence_of(procedure: &SExpr, env: &EnvRef) -> SResult<Equivalence> {
+    let equivalences = [("eq?", Equivalence::Eqv), ("eqv?", Equivalence::Eqv),
+                        ("equal?", Equivalence::Equal), ("=", Equivalence::Numeric)];
+    equivalences.iter()
+        .find(|(name, _)| env.get(name).map_or(false, |x| x == *procedure))
+        .map(|(_, equivalence)| *equivalence)
+        .ok_or_else(|| SErr::new_generic(&format!("Unsupported hash table equivalence: {}", procedure)))
+}
@@ -1,8 +1,14 @@
-use lexer::TokenIterator;
+use std::cell::Cell;
+
+use lexer::{Token, TokenIterator, Source};
 use evaluator::Args;
 use parser::{SExpr, parse_single};
-use port::{PortData, current_input_port, current_output_port};
+use port::{PortData, CustomPort, current_input_port, current_output_port};
+use utils::new_rc_ref_cell;
 use serr::{SErr, SResult};
+use pretty_print::{external, pretty, Labels};
+use primitives::vector::{index, bounds};
+use primitives::bytevector::new_bytevector;
 
 //
 // Helpers
@@ -21,30 +27,26 @@ fn get_path_from_args(args: Args) -> SResult<String> {
 macro_rules! call_read_fn(
     ($args: ident, $fn: ident) => {{
         if $args.len() == 0 {
-            let (_size, result) = current_input_port().$fn()?;
-            Ok(result)
+            current_input_port()?.$fn()
         } else {
-            let (_size, result) = $args.evaled()?
+            $args.evaled()?
                 .own_one()?
                 .as_port_mut()?
-                .$fn()?;
-
-            Ok(result)
+                .$fn()
         }
     }};
 );
 
 macro_rules! call_write_fn(
-    ($args: ident, $fn: ident, $thing: expr) => {{
-        if $args.len() <= 1 {
-            current_output_port().$fn(&$thing)?;
-        } else if $args.len() == 1 {
-            $args.evaled()?
-                .own_one()?
+    ($args: ident, $port: expr, $fn: ident, $thing: expr) => {{
+        if $args.len() <= $port {
+            current_output_port()?.$fn(&$thing)?;
+        } else if $args.len() == $port + 1 {
+            $args[$port].clone()
                 .as_port_mut()?
                 .$fn(&$thing)?;
         } else {
-            bail!(WrongArgCount => 1 as usize, $args.len())
+            bail!(WrongArgCount => $port + 1 as usize, $args.len())
         }
 
         Ok(SExpr::Unspecified)
@@ -70,41 +72,251 @@ pub fn open_binary_output_file(args: Args) -> SResult<SExpr> {
     Ok(SExpr::Port(PortData::new_binary_file_output(&get_path_from_args(args)?)?))
 }
 
-pub fn read(args: Args) -> SResult<SExpr> {
-    // I just couldn't define this closure as a simple variable
-    macro_rules! parse_chars(() => {
-        |chars| {
-            let mut iter = TokenIterator::new(chars).peekable();
-            Ok(parse_single(&mut iter)?)
+pub fn open_input_string(args: Args) -> SResult<SExpr> {
+    let string = args.evaled()?.own_one()?.into_str()?;
+    Ok(SExpr::Port(PortData::new_string_input(&string)))
+}
+
+pub fn open_output_string(args: Args) -> SResult<SExpr> {
+    if !args.is_empty() {
+        bail!(WrongArgCount => 0 as usize, args.len())
+    }
+
+    Ok(SExpr::Port(PortData::new_string_output()))
+}
+
+pub fn get_output_string(args: Args) -> SResult<SExpr> {
+    let port = args.evaled()?.own_one()?;
+    Ok(sstr!(port.as_port()?.output_string()?))
+}
+
+/// `(make-custom-*-port id read!/write! get-position set-position! close)`
+/// like in R6RS. `get-position` and `set-position!` may be procedures or
+/// `#f`, they are not used since ports don't have positions. `close` may
+/// be `#f`.
+fn make_custom_port(args: Args, binary: bool) -> SResult<CustomPort> {
+    let env = args.env();
+    let args = args.evaled()?;
+    if args.len() != 5 {
+        bail!(WrongArgCount => 5 as usize, args.len())
+    }
+
+    let mut iter = args.into_iter();
+    let id = iter.next().unwrap().into_str()?;
+    let procedure = iter.next().unwrap();
+    procedure.as_proc()?;
+
+    let _get_position = optional_procedure(iter.next().unwrap())?;
+    let _set_position = optional_procedure(iter.next().unwrap())?;
+    let close = optional_procedure(iter.next().unwrap())?;
+
+    Ok(CustomPort::new(id, procedure, close, binary, &env))
+}
+
+fn optional_procedure(x: SExpr) -> SResult<Option<SExpr>> {
+    match x {
+        SExpr::Atom(Token::Boolean(false)) => Ok(None),
+        x => {
+            x.as_proc()?;
+            Ok(Some(x))
+        }
+    }
+}
+
+pub fn make_custom_textual_input_port(args: Args) -> SResult<SExpr> {
+    let port = make_custom_port(args, false)?;
+    Ok(SExpr::Port(PortData::CustomTextualInput(new_rc_ref_cell(port))))
+}
+
+pub fn make_custom_textual_output_port(args: Args) -> SResult<SExpr> {
+    let port = make_custom_port(args, false)?;
+    Ok(SExpr::Port(PortData::CustomTextualOutput(new_rc_ref_cell(port))))
+}
+
+pub fn make_custom_binary_input_port(args: Args) -> SResult<SExpr> {
+    let port = make_custom_port(args, true)?;
+    Ok(SExpr::Port(PortData::CustomBinaryInput(new_rc_ref_cell(port))))
+}
+
+pub fn make_custom_binary_output_port(args: Args) -> SResult<SExpr> {
+    let port = make_custom_port(args, true)?;
+    Ok(SExpr::Port(PortData::CustomBinaryOutput(new_rc_ref_cell(port))))
+}
+
+/// The converter of `current-input-port`, accepts only input ports.
+pub fn input_port(args: Args) -> SResult<SExpr> {
+    let port = args.evaled()?.own_one()?;
+    if !port.as_port()?.is_input() {
+        bail!(TypeMismatch => "input port", port)
+    }
+
+    Ok(port)
+}
+
+/// The converter of `current-output-port` and `current-error-port`,
+/// accepts only output ports.
+pub fn output_port(args: Args) -> SResult<SExpr> {
+    let port = args.evaled()?.own_one()?;
+    if !port.as_port()?.is_output() {
+        bail!(TypeMismatch => "output port", port)
+    }
+
+    Ok(port)
+}
+
+/// The characters of a port for the reader. A character is consumed only
+/// when the next one is taken, so the one that the lexer looks ahead at
+/// can be left in the port.
+struct ReaderChars<'a> {
+    port: &'a mut PortData,
+    /// Whether the last character is given but not consumed yet.
+    pending: bool,
+    stopped: &'a Cell<bool>,
+    error: Option<SErr>,
+}
+
+impl<'a> ReaderChars<'a> {
+    fn consume_pending(&mut self) -> SResult<()> {
+        if self.pending {
+            self.pending = false;
+            self.port.read_char()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for ReaderChars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.stopped.get() || self.error.is_some() {
+            return None
+        }
+
+        let chr = self.consume_pending().and_then(|_| self.port.peek_char());
+        match chr {
+            Ok(chr) => {
+                self.pending = chr.is_some();
+                chr
+            },
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
         }
-    };);
+    }
+}
+
+fn read_datum(port: &mut PortData) -> SResult<SExpr> {
+    let stopped = Cell::new(false);
+    let mut chars = ReaderChars { port, pending: false, stopped: &stopped, error: None };
+
+    let (result, lookahead) = {
+        let mut tokens = TokenIterator::new(&mut chars, Source::new("port", ""));
+        let result = parse_single(&mut tokens.by_ref().peekable());
+        stopped.set(true);
+        (result, tokens.has_lookahead())
+    };
 
+    // The char that the lexer looked ahead at belongs to the next datum
+    if !lookahead {
+        chars.consume_pending()?;
+    }
+    if let Some(e) = chars.error {
+        return Err(e)
+    }
+
+    match result {
+        Err(SErr::FoundNothing) => Ok(SExpr::Eof),
+        // The positions are counted from where this read started and the
+        // text isn't kept, so leave them out and let the error be located
+        // at the `read` call instead
+        Err(SErr::ReadError(_, x, y)) => Err(SErr::ReadError(None, x, y)),
+        x => x
+    }
+}
+
+pub fn read(args: Args) -> SResult<SExpr> {
     if args.len() == 0 {
-        current_input_port().with_chars(parse_chars!())
-    } else if args.len() == 0 {
-        args.evaled()?
-            .own_one()?
-            .as_port_mut()?
-            .with_chars(parse_chars!())
+        read_datum(&mut current_input_port()?)
+    } else if args.len() == 1 {
+        read_datum(args.evaled()?.own_one()?.as_port_mut()?)
     } else {
         bail!(WrongArgCount => 1 as usize, args.len())
     }
 }
 
 pub fn read_line(args: Args) -> SResult<SExpr> {
-    // I couldn't understand why it can't infer the type of x.
-    let x: SResult<String> = call_read_fn!(args, read_line);
-    Ok(sstr!(x?.trim_right_matches(|c| c == '\n')))
+    let (size, line) = call_read_fn!(args, read_line)?;
+    if size == 0 {
+        return Ok(SExpr::Eof)
+    }
+
+    Ok(sstr!(line.trim_right_matches(|c| c == '\n')))
 }
 
 pub fn read_char(args: Args) -> SResult<SExpr> {
-    let x: SResult<char> = call_read_fn!(args, read_char);
-    Ok(schr!(x?))
+    Ok(call_read_fn!(args, read_char)?.map_or(SExpr::Eof, |c| schr!(c)))
+}
+
+pub fn peek_char(args: Args) -> SResult<SExpr> {
+    Ok(call_read_fn!(args, peek_char)?.map_or(SExpr::Eof, |c| schr!(c)))
+}
+
+pub fn char_ready_qm(args: Args) -> SResult<SExpr> {
+    Ok(sbool!(call_read_fn!(args, char_ready)?))
+}
+
+/// `(read-string k [port])`
+pub fn read_string(args: Args) -> SResult<SExpr> {
+    let (k_, rest) = args.evaled()?.own_one_rest()?;
+    let k = index(k_)?;
+
+    let string = match rest.len() {
+        0 => current_input_port()?.read_string(k)?,
+        1 => rest[0].clone().as_port_mut()?.read_string(k)?,
+        x => bail!(WrongArgCount => 2 as usize, x + 1)
+    };
+
+    Ok(string.map_or(SExpr::Eof, |x| sstr!(x)))
 }
 
 pub fn read_u8(args: Args) -> SResult<SExpr> {
-    let x: SResult<u8> = call_read_fn!(args, read_u8);
-    Ok(sint!(i64::from(x?)))
+    Ok(call_read_fn!(args, read_u8)?.map_or(SExpr::Eof, |x| sint!(i64::from(x))))
+}
+
+pub fn peek_u8(args: Args) -> SResult<SExpr> {
+    Ok(call_read_fn!(args, peek_u8)?.map_or(SExpr::Eof, |x| sint!(i64::from(x))))
+}
+
+pub fn u8_ready_qm(args: Args) -> SResult<SExpr> {
+    Ok(sbool!(call_read_fn!(args, u8_ready)?))
+}
+
+pub fn read_bytevector(args: Args) -> SResult<SExpr> {
+    let (k_, rest) = args.evaled()?.own_one_rest()?;
+    let k = index(k_)?;
+
+    let (size, u8s) = match rest.len() {
+        0 => current_input_port()?.read_bytevector(k)?,
+        1 => rest[0].clone().as_port_mut()?.read_bytevector(k)?,
+        x => bail!(WrongArgCount => 2 as usize, x + 1)
+    };
+
+    if size == 0 && k > 0 {
+        return Ok(SExpr::Eof)
+    }
+
+    Ok(new_bytevector(u8s))
+}
+
+pub fn eof_object(args: Args) -> SResult<SExpr> {
+    if !args.is_empty() {
+        bail!(WrongArgCount => 0 as usize, args.len())
+    }
+
+    Ok(SExpr::Eof)
 }
 
 pub fn read_all(args: Args) -> SResult<SExpr> {
@@ -116,60 +328,132 @@ pub fn read_all(args: Args) -> SResult<SExpr> {
         Ok(sstr!(string))
     } else if port.is_binary() && port.is_input() {
         let (_size, u8s) = port.read_all_u8()?;
-        Ok(SExpr::List(u8s.into_iter().map(|u| sint!(i64::from(u))).collect()))
+        Ok(new_bytevector(u8s))
     } else {
         bail!(TypeMismatch => "a textual or binary input port", SExpr::Port(port.clone()))
     }
 }
 
 pub fn write(args: Args) -> SResult<SExpr> {
-    let string = args.get(0)
-        .ok_or_else(|| SErr::WrongArgCount(1, 0))?
-        .eval(&args.env)?
-        .to_string();
-    call_write_fn!(args, write_string, string)
+    write_with(args, Labels::Cycles)
+}
+
+pub fn write_shared(args: Args) -> SResult<SExpr> {
+    write_with(args, Labels::Shared)
+}
+
+pub fn write_simple(args: Args) -> SResult<SExpr> {
+    write_with(args, Labels::None)
+}
+
+fn write_with(args: Args, labels: Labels) -> SResult<SExpr> {
+    let args = args.evaled()?;
+    let obj = args.get(0)
+        .ok_or_else(|| SErr::WrongArgCount(1, 0))?;
+    let string = external(obj, false, labels);
+    call_write_fn!(args, 1, write_string, string)
 }
 
 pub fn write_string(args: Args) -> SResult<SExpr> {
     // TODO: (write-string string port START)
     // TODO: (write-string string port START END)
+    let args = args.evaled()?;
     let string = args.get(0)
         .ok_or_else(|| SErr::WrongArgCount(1, 0))?
-        .eval(&args.env)?
+        .clone()
         .into_str()?;
 
-    call_write_fn!(args, write_string, string)
+    call_write_fn!(args, 1, write_string, string)
 }
 
-pub fn newline(args: Args) -> SResult<SExpr> {
-    call_write_fn!(args, write_string, "\n")
+pub fn write_u8(args: Args) -> SResult<SExpr> {
+    let args = args.evaled()?;
+    let byte = args.get(0)
+        .ok_or_else(|| SErr::WrongArgCount(1, 0))?
+        .clone()
+        .into_u8()?;
+
+    call_write_fn!(args, 1, write_u8s, vec![byte])
 }
 
-pub fn display(args: Args) -> SResult<SExpr> {
-    let obj = args.get(0)
+/// `(flush-output-port [port])`
+pub fn flush_output_port(args: Args) -> SResult<SExpr> {
+    match args.len() {
+        0 => current_output_port()?.flush()?,
+        1 => args.evaled()?.own_one()?.as_port_mut()?.flush()?,
+        n => bail!(WrongArgCount => 1 as usize, n)
+    };
+
+    Ok(SExpr::Unspecified)
+}
+
+/// `(write-bytevector bytevector [port [start [end]]])`
+pub fn write_bytevector(args: Args) -> SResult<SExpr> {
+    let mut iter = args.evaled()?.into_iter();
+    let u8s = iter.next()
         .ok_or_else(|| SErr::WrongArgCount(1, 0))?
-        .eval(&args.env)?;
+        .as_bytevector()?;
+    let mut port = match iter.next() {
+        Some(x) => x,
+        None => SExpr::Port(current_output_port()?)
+    };
 
-    let string = if obj.is_str() {
-        obj.into_str().unwrap()
-    } else if obj.is_chr() {
-        obj.into_chr().unwrap().to_string()
-    } else {
-        obj.to_string()
+    let u8s = u8s.borrow();
+    let (start, end) = bounds(u8s.len(), iter.collect())?;
+    port.as_port_mut()?.write_u8s(&u8s[start..end])?;
+
+    Ok(SExpr::Unspecified)
+}
+
+/// The line width `pretty-print` uses if it isn't given.
+const PRETTY_PRINT_WIDTH: usize = 79;
+
+/// `(pretty-print obj [port [width]])`, writes `obj` broken into lines
+/// and indented, followed by a newline.
+pub fn pretty_print(args: Args) -> SResult<SExpr> {
+    let (obj, rest) = args.evaled()?.own_one_rest()?;
+    if rest.len() > 2 {
+        bail!(WrongArgCount => 3 as usize, rest.len() + 1)
+    }
+
+    let width = match rest.get(1) {
+        Some(x) => index(x.clone())?,
+        None => PRETTY_PRINT_WIDTH
     };
+    let string = format!("{}\n", pretty(&obj, width));
+    match rest.into_iter().next() {
+        Some(mut port) => port.as_port_mut()?.write_string(&string)?,
+        None => current_output_port()?.write_string(&string)?
+    }
 
-    call_write_fn!(args, write_string, string)
+    Ok(SExpr::Unspecified)
+}
+
+pub fn newline(args: Args) -> SResult<SExpr> {
+    let args = args.evaled()?;
+    call_write_fn!(args, 0, write_string, "\n")
+}
+
+pub fn display(args: Args) -> SResult<SExpr> {
+    let args = args.evaled()?;
+    let obj = args.get(0)
+        .ok_or_else(|| SErr::WrongArgCount(1, 0))?;
+    let string = external(obj, true, Labels::Cycles);
+    call_write_fn!(args, 1, write_string, string)
 }
 
 pub fn close_port(args: Args) -> SResult<SExpr> {
     let env = args.env();
     let id = args.own_one()?;
-    let remove = id.eval(&env)?.is_port();
+    let mut port = id.eval(&env)?;
+    let remove = port.is_port();
 
     if remove {
+        port.as_port_mut()?.close()?;
+
         let id = id.as_symbol();
         if id.is_ok() {
-            let id_ = id.unwrap().clone();
+            let id_ = id.unwrap().to_string();
             env.set(id_, SExpr::Port(PortData::Closed))?;
         } else {
             // This means port is created on the fly.
@@ -1,20 +1,17 @@
+use std::mem;
+
 use lexer::Token;
 use parser::SExpr;
 use parser::SExprs;
-use evaluator::Args;
-use evaluator::Extra;
-use procedure::ProcedureData;
+use evaluator;
+use evaluator::{Args, Extra, State, ContRef, Cont, Frame};
+use procedure::{ProcedureData, Param};
+use expander::{MacroData, strip_syntax};
 use env::EnvRef;
 use env::Env;
+use port;
 use serr::{SErr, SResult};
-
-pub fn define(args: Args) -> SResult<SExpr> {
-    env_add(EnvAddType::Define, args)
-}
-
-pub fn set(args: Args) -> SResult<SExpr> {
-    env_add(EnvAddType::Set, args)
-}
+use utils::new_rc_ref_cell;
 
 pub fn lambda(args: Args) -> SResult<SExpr> {
     let env = args.env();
@@ -22,7 +19,7 @@ pub fn lambda(args: Args) -> SResult<SExpr> {
     ProcedureData::new_compound(params, body, &env)
 }
 
-pub fn apply(args: Args) -> SResult<SExpr> {
+pub fn apply(args: Args, k: ContRef) -> SResult<State> {
     let env = args.env();
     let evaled = args.evaled()?;
     let (proc, arg_list) = if evaled.len() == 2 {
@@ -45,26 +42,182 @@ pub fn apply(args: Args) -> SResult<SExpr> {
         bail!(WrongArgCount => 2 as usize, evaled.len())
     };
 
-    // Because the proc will try to reevaluate the arguments,
-    // need to quote them before calling proc.
-    let args_quoted = arg_list.into_iter()
-        .map(|x| quote!(x))
-        .collect();
+    Ok(State::Apply(proc, arg_list, env, k))
+}
+
+/// `let` and named `let` are turned into procedure calls, so the body
+/// is evaluated in tail position.
+pub fn let_(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (first, rest) = args.own_one_rest()?;
+
+    let (procedure, inits) = match first {
+        // (let name ((var init) ...) body ...)
+        SExpr::Atom(Token::Symbol(name)) => {
+            let mut iter = rest.into_iter();
+            let bindings = iter.next()
+                .ok_or_else(|| SErr::new_expr_not_found("nothing"))?;
+            let (params, inits) = split_bindings(bindings)?;
+
+            let loop_env = Env::new(env.clone_ref()).into_ref();
+            let procedure = ProcedureData::new_compound_with(binding_params(params)?, iter.collect(), &loop_env);
+            loop_env.define(name.to_string(), procedure.clone());
+            (procedure, inits)
+        },
+        bindings => {
+            let (params, inits) = split_bindings(bindings)?;
+            (ProcedureData::new_compound_with(binding_params(params)?, rest, &env), inits)
+        }
+    };
+
+    evaluator::call(procedure, inits, env, k)
+}
+
+/// Each binding of `let*` gets an environment of its own, nested in the
+/// one of the previous binding.
+pub fn let_star(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (bindings, body) = args.own_one_rest()?;
+    let mut state = vec![SExpr::list(body)];
+    state.extend(bindings.into_list()?.into_iter().rev());
+
+    next_star_binding(state, env, k)
+}
+
+/// `state` is the body and the remaining `(var init)` clauses, the next
+/// one last.
+fn next_star_binding(mut state: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    let clause = match state.pop() {
+        Some(clause) if !state.is_empty() => clause,
+        body => {
+            let body = body.unwrap().into_list()?;
+            return evaluator::eval_body(body, Env::new(env).into_ref(), k)
+        }
+    };
+
+    let (id, init) = clause.list_own_two()?;
+    id.as_symbol()?;
+    state.push(id);
+
+    let k = Cont::push(Frame::Call(let_star_bind, state, env.clone_ref()), k);
+    Ok(State::Eval(init, env, k))
+}
+
+fn let_star_bind(value: SExpr, mut state: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    let id = state.pop().unwrap().into_symbol()?;
+    let inner_env = Env::new(env).into_ref();
+    inner_env.define(id, value);
+
+    next_star_binding(state, inner_env, k)
+}
+
+pub fn let_rec(args: Args, k: ContRef) -> SResult<State> {
+    let parent_env = args.env();
+    let (bindings, body) = args.own_one_rest()?;
+    let (params, inits) = split_bindings(bindings)?;
+
+    // Every init is evaluated inside the new environment, in order.
+    let mut exprs = params.into_iter()
+        .zip(inits.into_iter())
+        .map(|(param, init)| slist![ssymbol!("define"), param, init])
+        .collect::<SExprs>();
+    exprs.extend(body.into_iter());
+
+    let env = Env::new(parent_env).into_ref();
+    evaluator::eval_body(exprs, env, k)
+}
+
+/// `(receive formals expr body ...)` from SRFI-8.
+pub fn receive(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (formals, rest) = args.own_one_rest()?;
+    let mut iter = rest.into_iter();
+    let expr = iter.next()
+        .ok_or_else(|| SErr::new_expr_not_found("nothing"))?;
+
+    let procedure = ProcedureData::new_compound(formals, iter.collect(), &env)?;
+    let k = Cont::push(Frame::Call(apply_to_values, vec![procedure], env.clone_ref()), k);
+    Ok(State::Eval(expr, env, k))
+}
+
+fn apply_to_values(values: SExpr, mut procedure: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    Ok(State::Apply(procedure.remove(0), values.into_values(), env, k))
+}
+
+/// Every init of `let-values` is evaluated in the outer environment and
+/// the formals are bound after all of them are evaluated.
+pub fn let_values(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (bindings, body) = args.own_one_rest()?;
+    let mut state = vec![SExpr::list(body), SExpr::Nil];
+    state.extend(bindings.into_list()?.into_iter());
+
+    next_values(state, env, k, let_values_bind)
+}
+
+pub fn let_star_values(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (bindings, body) = args.own_one_rest()?;
+    let mut state = vec![SExpr::list(body), SExpr::Nil];
+    state.extend(bindings.into_list()?.into_iter());
 
-    proc.as_proc()?.apply(Args::new(args_quoted, &env))
+    next_values(state, env, k, let_star_values_bind)
 }
 
-pub fn let_(args: Args) -> SResult<SExpr> {
-    let_generic(args, |expr, _, parent_env| expr.eval(parent_env))
+/// `state` is the body, the formals waiting for their values and the
+/// remaining `(formals init)` clauses.
+fn next_values(mut state: SExprs, env: EnvRef, k: ContRef, callback: evaluator::Callback) -> SResult<State> {
+    if state.len() == 2 {
+        let body = state.remove(0).into_list()?;
+        let bound = state.remove(0).into_list()?;
+
+        // let-values binds all of them at the end, let*-values binds them
+        // as they come
+        let inner_env = Env::new(env).into_ref();
+        for pair in bound {
+            let (formals, values) = pair.list_own_two()?;
+            Param::new(formals)?.bind(values.into_list()?, &inner_env)?;
+        }
+
+        return evaluator::eval_body(body, inner_env, k)
+    }
+
+    let clause = state.remove(2);
+    let (formals, init) = clause.list_own_two()?;
+    state.push(formals);
+
+    let k = Cont::push(Frame::Call(callback, state, env.clone_ref()), k);
+    Ok(State::Eval(init, env, k))
 }
 
-pub fn let_star(args: Args) -> SResult<SExpr> {
-    let_generic(args, |expr, env, _| expr.eval(env))
+fn let_values_bind(values: SExpr, mut state: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    let formals = state.pop().unwrap();
+    let bound = mem::replace(&mut state[1], SExpr::Nil);
+    state[1] = SExpr::cons(slist![formals, SExpr::list(values.into_values())], bound);
+
+    next_values(state, env, k, let_values_bind)
 }
 
-pub fn let_rec(args: Args) -> SResult<SExpr> {
-    // FIXME: (letrec ([x y] [y x]) 3) will fail
-    let_star(args)
+fn let_star_values_bind(values: SExpr, mut state: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    let formals = state.pop().unwrap();
+    let inner_env = Env::new(env).into_ref();
+    Param::new(formals)?.bind(values.into_values(), &inner_env)?;
+
+    next_values(state, inner_env, k, let_star_values_bind)
+}
+
+/// `(define-values formals expr)`
+pub fn define_values(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (formals, expr) = args.own_two()?;
+
+    let k = Cont::push(Frame::Call(define_values_bind, vec![formals], env.clone_ref()), k);
+    Ok(State::Eval(expr, env, k))
+}
+
+fn define_values_bind(values: SExpr, mut formals: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    Param::new(formals.remove(0))?.bind(values.into_values(), &env)?;
+    Ok(State::Return(SExpr::Unspecified, k))
 }
 
 pub fn quote(args: Args) -> SResult<SExpr> {
@@ -72,7 +225,7 @@ pub fn quote(args: Args) -> SResult<SExpr> {
         bail!(WrongArgCount => 1 as usize, args.len())
     }
 
-    Ok(args[0].clone())
+    Ok(strip_syntax(args[0].clone()))
 }
 
 pub fn quasiquote(mut args: Args) -> SResult<SExpr> {
@@ -128,86 +281,124 @@ pub fn eval_unquoted(args: Args) -> SResult<SExpr> {
     };
 
     match arg {
-        SExpr::List(xs) => match xs[0] {
-            SExpr::Atom(Token::Symbol(ref x)) if x.as_str() == "unquote" => {
-                unquote(Args::new_with_extra(xs[1..].to_vec(), Extra::QQLevel(level), &args.env))
-            },
-            SExpr::Atom(Token::Symbol(ref x)) if x.as_str() == "quasiquote" => {
-                quasiquote(Args::new_with_extra(xs[1..].to_vec(), Extra::QQLevel(level), &args.env))
-            },
-            SExpr::List(ref xs2) => {
-                Ok(SExpr::List(vec![eval_unquoted(Args::new_with_extra(vec![SExpr::List(xs2.clone())], Extra::QQLevel(level), &args.env))?]))
-            },
-            _ => {
-                let result = xs.iter()
-                    .map(|x| eval_unquoted(Args::new_with_extra(vec![x.clone()], Extra::QQLevel(level), &args.env)))
-                    .collect::<SResult<_>>();
-                Ok(SExpr::List(result?))
+        SExpr::Pair(_) => {
+            let (xs, tail) = arg.list_parts()?;
+            match xs[0] {
+                ref x if x.is_symbol("unquote") => {
+                    unquote(Args::new_with_extra(xs[1..].to_vec(), Extra::QQLevel(level), &args.env))
+                },
+                ref x if x.is_symbol("quasiquote") => {
+                    quasiquote(Args::new_with_extra(xs[1..].to_vec(), Extra::QQLevel(level), &args.env))
+                },
+                _ => {
+                    let eval_one = |x: SExpr| eval_unquoted(Args::new_with_extra(vec![x], Extra::QQLevel(level), &args.env));
+                    let result = xs.into_iter()
+                        .map(&eval_one)
+                        .collect::<SResult<_>>()?;
+                    Ok(SExpr::dottedlist(result, eval_one(tail)?))
+                }
             }
         },
-        x => Ok(x.clone())
+        SExpr::Vector(xs) => {
+            let result = xs.borrow()
+                .iter()
+                .map(|x| eval_unquoted(Args::new_with_extra(vec![x.clone()], Extra::QQLevel(level), &args.env)))
+                .collect::<SResult<_>>()?;
+            Ok(SExpr::Vector(new_rc_ref_cell(result)))
+        },
+        x => Ok(strip_syntax(x.clone()))
     }
 }
 
-//
-// Helpers
-//
-#[derive(Clone, Copy)]
-enum EnvAddType {
-    Define,
-    Set
+pub fn syntax_rules(args: Args) -> SResult<SExpr> {
+    let env = args.env();
+    Ok(SExpr::Macro(MacroData::new(args.into_iter().collect(), &env)?))
 }
 
-fn env_add(t: EnvAddType, args: Args) -> SResult<SExpr> {
+pub fn define_syntax(args: Args) -> SResult<SExpr> {
     let env = args.env();
-    let name_expr = args.get(0)
-        .ok_or_else(|| SErr::new_id_not_found("nothing"))?
-        .clone();
+    let (keyword, transformer) = args.own_two()?;
+    let id = keyword.into_symbol()?;
+    let macro_ = transformer.eval(&env)?;
+    macro_.as_macro()?;
+
+    env.define(id, macro_);
+    Ok(SExpr::Unspecified)
+}
+
+pub fn let_syntax(args: Args) -> SResult<SExpr> {
+    let_generic(args, |expr, _, parent_env| {
+        let macro_ = expr.eval(parent_env)?;
+        macro_.as_macro()?;
+        Ok(macro_)
+    })
+}
+
+pub fn letrec_syntax(args: Args) -> SResult<SExpr> {
+    // Transformers are created inside the new environment, so they can
+    // refer to each other.
+    let_generic(args, |expr, env, _| {
+        let macro_ = expr.eval(env)?;
+        macro_.as_macro()?;
+        Ok(macro_)
+    })
+}
+
+//
+// Helpers
+//
+/// Splits a `define` form into the identifier and the expression
+/// that gives its value. For procedure definitions, like
+/// `(define (f x) ...)`, the procedure is created right away.
+pub fn definition(args: SExprs, env: &EnvRef) -> SResult<(String, SExpr)> {
+    let mut iter = args.into_iter();
+    let name_expr = iter.next()
+        .ok_or_else(|| SErr::new_id_not_found("nothing"))?;
 
     let (id, value) = match name_expr {
         SExpr::Atom(Token::Symbol(id)) => {
-            let value = args.get(1)
+            let value = iter.next()
                 .ok_or_else(|| SErr::new_expr_not_found("nothing"))?;
 
-            let value_sexpr = value.eval(&args.env)?;
-
-            (id.clone(), value_sexpr)
-        },
-        SExpr::List(_) => {
-            let (header, body) = args.own_one_rest()?;
-            let (id, params) = header.list_own_one_rest()?;
-
-            (id.into_symbol()?, ProcedureData::new_compound(SExpr::List(params), body, &env)?)
+            (id.to_string(), value)
         },
-        SExpr::DottedList(xs,y) => {
-            let mut iter = xs.into_iter();
-            let id = iter.next()
-                .ok_or_else(|| SErr::new_generic("Expected an identifier, found nothing."))?;
-            let head = iter.take_while(|_| true).collect::<SExprs>();
-            let (_, body) = args.own_one_rest()?;
-
-            let arg_list = match head.len() {
-                // (define (x . y) ...)
-                0 => *y,
-                // (define (x y ... . z) ...)
-                _ => SExpr::DottedList(head, y)
-            };
-
-            (id.into_symbol()?, ProcedureData::new_compound(arg_list, body, &env)?)
+        // (define (x y ...) ...), (define (x . y) ...) or (define (x y ... . z) ...)
+        SExpr::Pair(header) => {
+            let body = iter.collect::<SExprs>();
+            (header.car().into_symbol()?, ProcedureData::new_compound(header.cdr(), body, env)?)
         },
         x => return Err(SErr::new_id_not_found(&x.to_string()))
     };
 
+    Ok((id, value))
+}
 
-    match t {
-        EnvAddType::Define => {
-            env.define(id.clone(), value);
-            Ok(SExpr::Unspecified)
-        },
-        EnvAddType::Set => {
-            env.set(id.clone(), value)
-        }
+/// Splits `((var init) ...)` into variables and inits.
+fn split_bindings(bindings: SExpr) -> SResult<(SExprs, SExprs)> {
+    let mut params = vec![];
+    let mut inits = vec![];
+    for x in bindings.into_list()? {
+        let mut bind = x.into_list()?.into_iter();
+        let id = bind.next()
+            .ok_or_else(|| SErr::new_expr_not_found("nothing"))?;
+        id.as_symbol()?;
+
+        let expr = bind.next()
+            .ok_or_else(|| SErr::new_expr_not_found("nothing"))?;
+
+        params.push(id);
+        inits.push(expr);
     }
+
+    Ok((params, inits))
+}
+
+/// The parameters of the procedure that a `let` is turned into.
+fn binding_params(params: SExprs) -> SResult<Param> {
+    let names = params.into_iter()
+        .map(SExpr::into_symbol)
+        .collect::<SResult<_>>()?;
+    Ok(Param::Fixed(names))
 }
 
 pub fn let_generic<F>(args: Args, mut eval_expr: F) -> SResult<SExpr>
@@ -241,5 +432,6 @@ where F: (FnMut(&SExpr,/*env:*/ &EnvRef,/*parent_env:*/&EnvRef) -> SResult<SExpr
 }
 
 pub fn exit(args: Args) -> SResult<SExpr> {
+    port::flush_all()?;
     ::std::process::exit(0);
 }
@@ -6,58 +6,43 @@ pub fn cons(args: Args) -> SResult<SExpr> {
     let (x, xs) = args.evaled()?
         .own_two()?;
 
-    let result = match xs {
-        SExpr::List(mut xs) => {
-            xs.insert(0, x);
-            SExpr::List(xs)
-        },
-        SExpr::DottedList(mut xs, y) => {
-            xs.insert(0, x);
-            SExpr::DottedList(xs, y)
-        },
-        y => SExpr::DottedList(vec![x], Box::new(y))
-    };
-
-    Ok(result)
+    Ok(SExpr::cons(x, xs))
 }
 
 pub fn car(args: Args) -> SResult<SExpr> {
-    let xs = args.evaled()?
-        .own_one()?;
-
-    match xs {
-        SExpr::List(ys) | SExpr::DottedList(ys, _) => {
-            ys.into_iter()
-                .next()
-                .ok_or_else(|| SErr::new_generic("car: empty list"))
-        },
-        x => bail!(UnexpectedForm => x)
-    }
+    args.evaled()?
+        .own_one()?
+        .car()
 }
 
 pub fn cdr(args: Args) -> SResult<SExpr> {
-    let xs = args.evaled()?
-        .own_one()?;
-
-    let result = match xs {
-        SExpr::List(ys) => {
-            let mut iter = ys.into_iter();
-            iter.next() // Skip 1
-                .ok_or_else(|| SErr::new_generic("cdr: empty list"))?;
-
-            SExpr::List(iter.collect())
-        },
-        SExpr::DottedList(ys, y) => {
-            if ys.len() == 1 {
-                *y
-            } else {
-                SExpr::DottedList(ys.into_iter().skip(1).collect(), y)
-            }
-        },
-        x => bail!(UnexpectedForm => x)
-    };
+    args.evaled()?
+        .own_one()?
+        .cdr()
+}
 
-    Ok(result)
+pub fn set_car_bang(args: Args) -> SResult<SExpr> {
+    let (pair, x) = args.evaled()?
+        .own_two()?;
+
+    match pair {
+        SExpr::Pair(p) => p.set_car(x),
+        y => bail!(TypeMismatch => "pair", y)
+    }
+
+    Ok(SExpr::Unspecified)
+}
+
+pub fn set_cdr_bang(args: Args) -> SResult<SExpr> {
+    let (pair, x) = args.evaled()?
+        .own_two()?;
+
+    match pair {
+        SExpr::Pair(p) => p.set_cdr(x),
+        y => bail!(TypeMismatch => "pair", y)
+    }
+
+    Ok(SExpr::Unspecified)
 }
 
 pub fn list_copy(args: Args) -> SResult<SExpr> {
@@ -86,39 +71,19 @@ pub fn list_copy(args: Args) -> SResult<SExpr> {
         bail!(WrongArgCount => 3 as usize, evaled.len())
     };
 
-    Ok(SExpr::List(list))
+    Ok(SExpr::list(list))
 }
 
 pub fn append(args: Args) -> SResult<SExpr> {
-    let len = args.len();
-    if len == 1 {
-        return args[0].eval(&args.env)
-    }
+    let mut lists = args.evaled()?;
 
-    let (xs, rest) = args.evaled()?
-        .own_one_rest()?;
-    let mut list = xs.into_list()?;
-    let iter = rest.into_iter();
-
-    for (i, expr) in iter.enumerate() {
-        // list is the first element, and i starts from 0, so -2
-        if i == len - 2 {
-            match expr {
-                SExpr::List(mut xs) => {
-                    list.append(&mut xs);
-                    return Ok(SExpr::List(list))
-                },
-                SExpr::DottedList(mut xs, y) => {
-                    list.append(&mut xs);
-                    return Ok(SExpr::dottedlist(list, *y))
-                },
-                x => return Ok(SExpr::dottedlist(list, x))
-            }
-        } else {
-            list.append(&mut expr.into_list()?);
-        }
-    }
+    // The last argument is not copied, the result shares it.
+    let last = match lists.pop() {
+        Some(x) => x,
+        None => return Ok(SExpr::Nil)
+    };
 
-    // Just for satisfying compiler
-    Ok(SExpr::Unspecified)
+    lists.into_iter()
+        .rev()
+        .try_fold(last, |acc, xs| Ok(SExpr::dottedlist(xs.into_list()?, acc)))
 }
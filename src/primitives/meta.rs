@@ -12,22 +12,40 @@ pub fn type_of(args: Args) -> SResult<SExpr> {
 
     Ok(match item {
         Atom(Symbol(_)) => ssymbol!("symbol"),
-        Atom(Integer(_)) => ssymbol!("integer"),
+        Atom(Integer(_)) | Atom(BigInt(_)) => ssymbol!("integer"),
         Atom(Fraction(_)) => ssymbol!("fraction"),
         Atom(Float(_)) => ssymbol!("float"),
+        Atom(Complex(_)) => ssymbol!("complex"),
         Atom(Boolean(_)) => ssymbol!("boolean"),
         Atom(Chr(_)) => ssymbol!("chr"),
         Atom(Str(_)) => ssymbol!("str"),
         Atom(_) => ssymbol!("atom"),
-        List(_) => ssymbol!("list"),
-        DottedList(_,_) => ssymbol!("list-dotted"),
+        Nil => ssymbol!("list"),
+        ref x@Pair(_) if x.is_proper_list() => ssymbol!("list"),
+        Pair(_) => ssymbol!("list-dotted"),
+        Vector(_) => ssymbol!("vector"),
+        Bytevector(_) => ssymbol!("bytevector"),
+        Record(ref x) => ssymbol!(x.rtype.name()),
+        RecordType(_) => ssymbol!("record-type"),
+        HashTable(_) => ssymbol!("hash-table"),
         Procedure(_) => ssymbol!("procedure"),
+        Macro(_) => ssymbol!("macro"),
+        Condition(_) => ssymbol!("condition"),
+        Values(_) => ssymbol!("values"),
+        Eof => ssymbol!("eof"),
         Port(TextualFileInput(_,_)) => ssymbol!("port-textual-in"),
         Port(TextualFileOutput(_,_)) => ssymbol!("port-textual-out"),
         Port(BinaryFileInput(_,_)) => ssymbol!("port-binary-in"),
         Port(BinaryFileOutput(_,_)) => ssymbol!("port-binary-out"),
         Port(StdInput(_)) => ssymbol!("port-std-in"),
         Port(StdOutput(_)) => ssymbol!("port-std-out"),
+        Port(StdError(_)) => ssymbol!("port-std-err"),
+        Port(StringInput(_)) => ssymbol!("port-string-in"),
+        Port(StringOutput(_)) => ssymbol!("port-string-out"),
+        Port(CustomTextualInput(_)) => ssymbol!("port-textual-in"),
+        Port(CustomTextualOutput(_)) => ssymbol!("port-textual-out"),
+        Port(CustomBinaryInput(_)) => ssymbol!("port-binary-in"),
+        Port(CustomBinaryOutput(_)) => ssymbol!("port-binary-out"),
         Port(Closed) => ssymbol!("port-closed"),
         _ => bail!(Generic => "Is that a thing?")
     })
@@ -37,13 +55,13 @@ pub fn convert_type(args: Args) -> SResult<SExpr> {
     let (typ, arg) = args.evaled()?.own_two()?;
 
     Ok(match typ {
-        Atom(Symbol(ref t)) if t == "symbol" => match arg {
+        Atom(Symbol(ref t)) if &**t == "symbol" => match arg {
             x@Atom(Symbol(_)) => x,
             x@Atom(Str(_)) => ssymbol!(x.into_str()?),
             Atom(Chr(x)) => ssymbol!(x.to_string()),
             x => bail!(Cast => "symbol", x)
         },
-        Atom(Symbol(ref t)) if t == "chr" => match arg {
+        Atom(Symbol(ref t)) if &**t == "chr" => match arg {
             x@Atom(Chr(_)) => x,
             Atom(Integer(x)) => {
                 let result = char::from_u32(x as u32)
@@ -61,45 +79,43 @@ pub fn convert_type(args: Args) -> SResult<SExpr> {
             },
             x => bail!(Cast => "chr", x)
         },
-        Atom(Symbol(ref t)) if t == "integer" => match arg {
+        Atom(Symbol(ref t)) if &**t == "integer" => match arg {
             x@Atom(Integer(_)) => x,
             Atom(Chr(x)) => sint!(x as i64),
             x => bail!(Cast => "chr", x)
         },
-        Atom(Symbol(ref t)) if t == "str" => match arg {
+        Atom(Symbol(ref t)) if &**t == "str" => match arg {
             x@Atom(Str(_)) => x,
-            Atom(Symbol(x)) => sstr!(x),
+            Atom(Symbol(x)) => sstr!(&*x),
             Atom(Chr(x)) => sstr!(x.to_string()),
-            List(xs) => {
-                let result = xs.into_iter()
-                    .map(|x| x.into_chr())
-                    .collect::<SResult<String>>()?;
-
-                sstr!(result)
-            },
-            DottedList(xs, y) => {
+            x@Nil | x@Pair(_) => {
+                let (xs, y) = x.list_parts()?;
                 let mut result = xs.into_iter()
                     .map(|x| x.into_chr())
                     .collect::<SResult<String>>()?;
-                result.push(y.into_chr()?);
+                if !y.is_nil() {
+                    result.push(y.into_chr()?);
+                }
 
                 sstr!(result)
             },
             x => bail!(Cast => "str", x)
         },
-        Atom(Symbol(ref t)) if t == "list" => match arg {
+        Atom(Symbol(ref t)) if &**t == "list" => match arg {
             Atom(Str(x)) => {
                 let result = x.borrow()
                     .chars()
                     .map(|c| schr!(c))
                     .collect();
 
-                List(result)
+                SExpr::list(result)
             },
-            x@List(_) => x,
-            DottedList(mut xs, y) => {
-                xs.push(*y);
-                List(xs)
+            x@Nil => x,
+            ref x@Pair(_) if x.is_proper_list() => x.clone(),
+            x@Pair(_) => {
+                let (mut xs, y) = x.list_parts()?;
+                xs.push(y);
+                SExpr::list(xs)
             },
             x => bail!(Cast => "list", x)
         },
@@ -5,41 +5,40 @@ pub mod numeric;
 pub mod ordering;
 pub mod conditionals;
 pub mod list;
+pub mod vector;
+pub mod bytevector;
+pub mod record;
+pub mod hash_table;
 #[macro_use]
 pub mod string;
 pub mod io;
 pub mod system;
 pub mod prelude;
 pub mod meta;
+pub mod control;
+pub mod exception;
+pub mod parameter;
 
 use primitives::prelude::PRELUDE;
+use utils::fraction::Fraction;
 use env::{EnvRef, EnvValues};
-use lexer::tokenize;
+use lexer::{tokenize, Source};
 use parser::parse;
+use port;
 use serr::SResult;
 
 pub fn load_prelude(env: &EnvRef) -> SResult<()> {
-    for sexpr in parse(tokenize(&mut PRELUDE.to_string().chars().into_iter().peekable()))? {
-        sexpr.eval(&env)?;
+    for (sexpr, span) in parse(tokenize(&Source::new("prelude", PRELUDE))?)? {
+        sexpr.eval_at(&span, &env)?;
     }
     Ok(())
 }
 
 pub fn env() -> EnvValues {
-    environment! {
+    let mut env = environment! {
         "typeof"        => meta::type_of,
         "convert-type"  => meta::convert_type,
 
-        "define"      => lang::define,
-        "set!"        => lang::set,
-        "λ"           => lang::lambda,
-        "lambda"      => lang::lambda,
-        "apply"       => lang::apply,
-        "let"         => lang::let_,
-        "let*"        => lang::let_star,
-        "letrec"      => lang::let_rec,
-        "quote"       => lang::quote,
-        "quasiquote"  => lang::quasiquote,
         "exit"        => lang::exit,
 
         "eqv?"   => equivalence::eqv_qm,
@@ -50,24 +49,46 @@ pub fn env() -> EnvValues {
         "-"  => |args| numeric::calc('-', args),
         "*"  => |args| numeric::calc('*', args),
         "/"  => |args| numeric::calc('/', args),
+        "quotient"    => numeric::quotient,
         "remainder"   => numeric::remainder,
         "modulo"      => numeric::modulo,
+        "floor/"      => numeric::floor_slash,
+        "floor-quotient"     => numeric::floor_quotient,
+        "floor-remainder"    => numeric::modulo,
+        "truncate/"          => numeric::truncate_slash,
+        "truncate-quotient"  => numeric::quotient,
+        "truncate-remainder" => numeric::remainder,
         "numerator"   => numeric::numerator,
         "denominator" => numeric::denominator,
-        "sqrt"        => call_float_fun!(sqrt),
-        "expt"        => call_float_fun!(sqrt),
-        "ceiling"     => call_float_fun!(ceil),
-        "floor"       => call_float_fun!(floor),
-        "truncate"    => call_float_fun!(trunc),
-        "round"       => call_float_fun!(round),
+        "gcd"         => numeric::gcd,
+        "lcm"         => numeric::lcm,
+        "sqrt"        => numeric::sqrt,
+        "exact-integer-sqrt" => numeric::exact_integer_sqrt,
+        "expt"        => numeric::expt,
+        "exact"       => numeric::exact,
+        "inexact"     => numeric::inexact,
+        "rationalize" => numeric::rationalize,
+        "nan?"        => |args| numeric::float_predicate(args, false, f64::is_nan),
+        "infinite?"   => |args| numeric::float_predicate(args, false, f64::is_infinite),
+        "finite?"     => |args| numeric::float_predicate(args, true, f64::is_finite),
+        "ceiling"     => |args| numeric::round_with(args, Fraction::ceiling, f64::ceil),
+        "floor"       => |args| numeric::round_with(args, Fraction::floor, f64::floor),
+        "truncate"    => |args| numeric::round_with(args, Fraction::truncate, f64::trunc),
+        "round"       => |args| numeric::round_with(args, Fraction::round, f64::round_ties_even),
         "exp"         => call_float_fun!(exp),
-        "log"         => call_float_fun!(ln, log),
+        "log"         => numeric::log,
         "sin"         => call_float_fun!(sin),
         "cos"         => call_float_fun!(cos),
         "tan"         => call_float_fun!(tan),
         "asin"        => call_float_fun!(asin),
         "acos"        => call_float_fun!(acos),
         "atan"        => call_float_fun!(atan, atan2),
+        "make-rectangular" => numeric::make_rectangular,
+        "make-polar"  => numeric::make_polar,
+        "real-part"   => numeric::real_part,
+        "imag-part"   => numeric::imag_part,
+        "magnitude"   => numeric::magnitude,
+        "angle"       => numeric::angle,
         "number->string" => numeric::number_string,
         "string->number" => numeric::string_number,
 
@@ -77,14 +98,11 @@ pub fn env() -> EnvValues {
         ">=" => ordering::gte,
         "="  => ordering::eq,
 
-        "cond" => conditionals::cond,
-        "case" => conditionals::case,
-        "and"  => conditionals::and,
-        "or"   => conditionals::or,
-
         "cons"   => list::cons,
         "car"    => list::car,
         "cdr"    => list::cdr,
+        "set-car!" => list::set_car_bang,
+        "set-cdr!" => list::set_cdr_bang,
         "append" => list::append,
         "list-copy" => list::list_copy,
 
@@ -104,6 +122,45 @@ pub fn env() -> EnvValues {
         "string-replace-range!" => string::string_replace_range_em,
         "make-string"           => string::make_string,
 
+        "vector"         => vector::vector,
+        "make-vector"    => vector::make_vector,
+        "vector-length"  => vector::vector_length,
+        "vector-ref"     => vector::vector_ref,
+        "vector-set!"    => vector::vector_set_em,
+        "vector->list"   => vector::vector_to_list,
+        "list->vector"   => vector::list_to_vector,
+        "vector->string" => vector::vector_to_string,
+        "string->vector" => vector::string_to_vector,
+        "vector-copy"    => vector::vector_copy,
+        "vector-copy!"   => vector::vector_copy_em,
+        "vector-append"  => vector::vector_append,
+        "vector-fill!"   => vector::vector_fill_em,
+
+        "bytevector"          => bytevector::bytevector,
+        "make-bytevector"     => bytevector::make_bytevector,
+        "bytevector-length"   => bytevector::bytevector_length,
+        "bytevector-u8-ref"   => bytevector::bytevector_u8_ref,
+        "bytevector-u8-set!"  => bytevector::bytevector_u8_set_em,
+        "bytevector-copy"     => bytevector::bytevector_copy,
+        "bytevector-copy!"    => bytevector::bytevector_copy_em,
+        "bytevector-append"   => bytevector::bytevector_append,
+        "utf8->string"        => bytevector::utf8_to_string,
+        "string->utf8"        => bytevector::string_to_utf8,
+
+        "make-hash-table"          => hash_table::make_hash_table,
+        "alist->hash-table"        => hash_table::alist_to_hash_table,
+        "hash-table-ref/default"   => hash_table::hash_table_ref_default,
+        "hash-table-set!"          => hash_table::hash_table_set_em,
+        "hash-table-delete!"       => hash_table::hash_table_delete_em,
+        "hash-table-contains?"     => hash_table::hash_table_contains_qm,
+        "hash-table-exists?"       => hash_table::hash_table_contains_qm,
+        "hash-table-size"          => hash_table::hash_table_size,
+        "hash-table-keys"          => hash_table::hash_table_keys,
+        "hash-table-values"        => hash_table::hash_table_values,
+        "hash-table->alist"        => hash_table::hash_table_to_alist,
+        "hash-table-copy"          => hash_table::hash_table_copy,
+        "hash-table-clear!"        => hash_table::hash_table_clear_em,
+
         "load"         => system::load,
         "file-exists?" => system::file_exists_qm,
         "delete-file"  => system::delete_file,
@@ -115,15 +172,102 @@ pub fn env() -> EnvValues {
         "open-binary-output-file" => io::open_binary_output_file,
         "open-input-file"  => io::open_input_file,
         "open-output-file" => io::open_output_file,
+        "open-input-string"  => io::open_input_string,
+        "open-output-string" => io::open_output_string,
+        "get-output-string"  => io::get_output_string,
+        "make-custom-textual-input-port"  => io::make_custom_textual_input_port,
+        "make-custom-textual-output-port" => io::make_custom_textual_output_port,
+        "make-custom-binary-input-port"   => io::make_custom_binary_input_port,
+        "make-custom-binary-output-port"  => io::make_custom_binary_output_port,
         "read"             => io::read,
         "read-u8"          => io::read_u8,
+        "peek-u8"          => io::peek_u8,
+        "u8-ready?"        => io::u8_ready_qm,
         "read-line"        => io::read_line,
         "read-char"        => io::read_char,
+        "peek-char"        => io::peek_char,
+        "char-ready?"      => io::char_ready_qm,
+        "read-string"      => io::read_string,
+        "eof-object"       => io::eof_object,
         "read-all"         => io::read_all,
+        "read-bytevector"  => io::read_bytevector,
         "write"            => io::write,
+        "write-shared"     => io::write_shared,
+        "write-simple"     => io::write_simple,
         "write-string"     => io::write_string,
+        "write-u8"         => io::write_u8,
+        "flush-output-port" => io::flush_output_port,
+        "write-bytevector" => io::write_bytevector,
         "display"          => io::display,
+        "pretty-print"     => io::pretty_print,
+        "pp"               => io::pretty_print,
         "newline"          => io::newline,
-        "close-port"       => io::close_port
-    }
+
+        "values" => control::values,
+
+        "raise"                  => exception::raise,
+        "error"                  => exception::error,
+        "error-object-message"   => exception::error_object_message,
+        "error-object-irritants" => exception::error_object_irritants,
+        "file-error?"            => exception::file_error_qm,
+        "read-error?"            => exception::read_error_qm
+    };
+
+    // These get their arguments unevaluated
+    env.extend(environment! { new_syntax;
+        "λ"           => lang::lambda,
+        "lambda"      => lang::lambda,
+        "quote"       => lang::quote,
+        "quasiquote"  => lang::quasiquote,
+
+        "syntax-rules"  => lang::syntax_rules,
+        "define-syntax" => lang::define_syntax,
+        "let-syntax"    => lang::let_syntax,
+        "letrec-syntax" => lang::letrec_syntax,
+
+        "define-record-type" => record::define_record_type,
+
+        "close-port" => io::close_port
+    });
+
+    // These get their arguments unevaluated and evaluate
+    // some of them in tail position
+    env.extend(environment! { new_special_form;
+        "let"    => lang::let_,
+        "let*"   => lang::let_star,
+        "letrec" => lang::let_rec,
+
+        "receive"       => lang::receive,
+        "let-values"    => lang::let_values,
+        "let*-values"   => lang::let_star_values,
+        "define-values" => lang::define_values,
+
+        "cond" => conditionals::cond,
+        "case" => conditionals::case,
+        "and"  => conditionals::and,
+        "or"   => conditionals::or,
+
+        "guard" => exception::guard,
+
+        "parameterize" => parameter::parameterize
+    });
+
+    env.extend(environment! { new_parameter;
+        "current-input-port"  => port::current_input_parameter(),
+        "current-output-port" => port::current_output_parameter(),
+        "current-error-port"  => port::current_error_parameter()
+    });
+
+    env.extend(environment! { new_control;
+        "apply"                          => lang::apply,
+        "call/cc"                        => control::call_cc,
+        "call-with-current-continuation" => control::call_cc,
+        "dynamic-wind"                   => control::dynamic_wind,
+        "call-with-values"               => control::call_with_values,
+        "raise-continuable"              => exception::raise_continuable,
+        "with-exception-handler"         => exception::with_exception_handler,
+        "make-parameter"                 => parameter::make_parameter
+    });
+
+    env
 }
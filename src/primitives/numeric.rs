@@ -1,6 +1,8 @@
-use std::ops::{Add, Sub, Mul, Div};
-
-use utils::fraction;
+use utils::fraction::Fraction;
+use utils::bigint::BigInt;
+use utils::complex::Complex;
+use utils::funcs;
+use utils::radix;
 use utils::radix::Radix;
 use lexer::Token;
 use parser::SExpr;
@@ -17,166 +19,610 @@ pub fn calc(op_str: char, args: Args) -> SResult<SExpr> {
         _ => args_iter.next().ok_or_else(|| SErr::WrongArgCount(1,0))?
     };
 
+    if !"+-*/".contains(op_str) {
+        bail!("Not an arithmetic op: {}", op_str)
+    }
 
-    type I = fn(i64,i64)->i64;
-    type Fl = fn(f64,f64)->f64;
-    type Fr = fn(fraction::Fraction, fraction::Fraction)->fraction::Fraction;
-    let (opi,opfl,opfr): (I, Fl, Fr) = match op_str {
-        '+' => (Add::add,Add::add,Add::add),
-        '-' => (Sub::sub, Sub::sub, Sub::sub),
-        '*' => (Mul::mul, Mul::mul, Mul::mul),
-        '/' => (Div::div, Div::div, Div::div),
-        _   => bail!("Not an arithmetic op: {}", op_str)
-    };
+    args_iter.fold(Ok(init), |acc, x| arithmetic(op_str, acc?, x))
+}
 
+/// Applies `op` to two numbers. Integers are promoted to `BigInt`s when
+/// the result doesn't fit in an `i64`, exact results are demoted back to
+/// `i64`s when they fit.
+fn arithmetic(op: char, a: SExpr, b: SExpr) -> SResult<SExpr> {
     use lexer::Token::*;
     use parser::SExpr::*;
-    // Here we go, couldn't come up with something better
-    let result = args_iter.fold(Ok(init), |acc, x|{
-        let result = match (acc?, x) {
-            (Atom(Integer(a)), Atom(Integer(b))) => {
-                // Like it isnt ugly already
-                if op_str == '/' && b == 0 {
-                    serr!(DivisionByZero)
-                }
-                if op_str == '/' && a % b != 0 {
-                    Atom(Fraction(fraction::Fraction::new(a,b)))
-                } else {
-                    Atom(Integer(opi(a,b)))
-                }
-            },
-            (Atom(Integer(a)), Atom(Float(b))) =>
-                Atom(Float(opfl(a as f64, b))),
-            (Atom(Float(a)), Atom(Integer(b))) =>
-                Atom(Float(opfl(a,b as f64))),
-            (Atom(Float(a)), Atom(Float(b))) =>
-                Atom(Float(opfl(a,b))),
-            (Atom(Fraction(a)), Atom(Fraction(b))) =>
-                Atom(Fraction(opfr(a,b))),
-            (Atom(Fraction(a)), Atom(Integer(b))) =>
-                Atom(Fraction(opfr(a,From::from(b)))),
-            (Atom(Integer(a)), Atom(Fraction(b))) =>
-                Atom(Fraction(opfr(From::from(a), b))),
-            (Atom(Fraction(a)), Atom(Float(b))) =>
-                Atom(Float(opfl(a.into(),b))),
-            (Atom(Float(a)), Atom(Fraction(b))) =>
-                Atom(Float(opfl(a,b.into()))),
-            (a,b) => bail!(TypeMismatch => "number", SExpr::List(vec![a, b]))
-        };
-
-        Ok(result)
-    })?;
-
-    // If it's an whole fraction, return it as int
-    let fixed_result = if let Atom(Fraction(f)) = result {
-        if f.is_int() {
-            Atom(Integer(f.n))
-        } else {
-            result
+
+    match (a, b) {
+        (Atom(Integer(a)), Atom(Integer(b))) => {
+            if op == '/' && b == 0 {
+                serr!(DivisionByZero)
+            }
+
+            let result = match op {
+                '+' => a.checked_add(b),
+                '-' => a.checked_sub(b),
+                '*' => a.checked_mul(b),
+                _ if a.checked_rem(b) == Some(0) => a.checked_div(b),
+                _ => None
+            };
+
+            match result {
+                Some(x) => Ok(sint!(x)),
+                None => rational_arithmetic(op, a.into(), b.into())
+            }
+        },
+        (Atom(ref a), Atom(ref b)) if a.is_exact() && b.is_exact() =>
+            rational_arithmetic(op, a.as_rational().unwrap(), b.as_rational().unwrap()),
+        (a, b) => {
+            if !a.is_numeric() || !b.is_numeric() {
+                bail!(TypeMismatch => "number", slist![a, b])
+            }
+
+            if is_complex(&a) || is_complex(&b) {
+                let (a, b) = (a.into_complex()?, b.into_complex()?);
+                return Ok(match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    _ => a / b
+                }.into())
+            }
+
+            let (a, b) = (a.into_float()?, b.into_float()?);
+            Ok(sfloat!(match op {
+                '+' => a + b,
+                '-' => a - b,
+                '*' => a * b,
+                _ => a / b
+            }))
         }
-    } else {
-        result
-    };
+    }
+}
+
+fn rational_arithmetic(op: char, a: Fraction, b: Fraction) -> SResult<SExpr> {
+    if op == '/' && b.n.is_zero() {
+        serr!(DivisionByZero)
+    }
 
-    Ok(fixed_result)
+    Ok(match op {
+        '+' => a + b,
+        '-' => a - b,
+        '*' => a * b,
+        _ => a / b
+    }.into())
+}
+
+pub fn quotient(args: Args) -> SResult<SExpr> {
+    let (x, y) = args.evaled()?.own_two()?;
+    int_quotient(x, y)
+}
+
+pub fn remainder(args: Args) -> SResult<SExpr> {
+    let (x, y) = args.evaled()?.own_two()?;
+    int_remainder(x, y)
 }
 
 pub fn modulo(args: Args) -> SResult<SExpr> {
-    let (x_, y_) = args.evaled()?.own_two()?;
-    let x = x_.as_int()?;
-    let y = y_.as_int()?;
+    let (x, y) = args.evaled()?.own_two()?;
+    int_modulo(x, y)
+}
 
-    Ok(sint!((x % y + y) % y))
+pub fn floor_quotient(args: Args) -> SResult<SExpr> {
+    let (x, y) = args.evaled()?.own_two()?;
+    int_floor_quotient(x, y)
 }
 
-pub fn remainder(args: Args) -> SResult<SExpr> {
+/// `(floor/ n1 n2)`, returns the floor quotient and the floor remainder.
+pub fn floor_slash(args: Args) -> SResult<SExpr> {
     let (x, y) = args.evaled()?.own_two()?;
+    Ok(SExpr::Values(vec![int_floor_quotient(x.clone(), y.clone())?, int_modulo(x, y)?]))
+}
 
-    Ok(sint!((x.as_int()? % y.as_int()?)))
+/// `(truncate/ n1 n2)`, returns the quotient and the remainder.
+pub fn truncate_slash(args: Args) -> SResult<SExpr> {
+    let (x, y) = args.evaled()?.own_two()?;
+    Ok(SExpr::Values(vec![int_quotient(x.clone(), y.clone())?, int_remainder(x, y)?]))
 }
 
-pub fn numerator(args: Args) -> SResult<SExpr> {
-    let num = args.evaled()?.own_one()?;
-    let result = match num {
-        SExpr::Atom(Token::Integer(i)) => (fraction::Fraction::from(i).n),
-        SExpr::Atom(Token::Float(i)) => (fraction::Fraction::from(i).n),
-        SExpr::Atom(Token::Fraction(f)) => f.n,
-        x => bail!(TypeMismatch => "number", x)
+fn int_quotient(x: SExpr, y: SExpr) -> SResult<SExpr> {
+    integer_division(x, y, i64::checked_div, |x, y| x / y)
+}
+
+fn int_remainder(x: SExpr, y: SExpr) -> SResult<SExpr> {
+    integer_division(x, y, i64::checked_rem, |x, y| x % y)
+}
+
+fn int_floor_quotient(x: SExpr, y: SExpr) -> SResult<SExpr> {
+    integer_division(x, y,
+        |x, y| x.checked_div(y).map(|q| if x % y != 0 && (x < 0) != (y < 0) { q - 1 } else { q }),
+        |x, y| {
+            let (q, r) = x.div_rem(y);
+            if !r.is_zero() && x.is_negative() != y.is_negative() { &q - &BigInt::from(1) } else { q }
+        })
+}
+
+fn int_modulo(x: SExpr, y: SExpr) -> SResult<SExpr> {
+    integer_division(x, y,
+        |x, y| x.checked_rem(y).map(|r| if r != 0 && (r < 0) != (y < 0) { r + y } else { r }),
+        |x, y| {
+            let r = x % y;
+            if !r.is_zero() && r.is_negative() != y.is_negative() { &r + y } else { r }
+        })
+}
+
+fn integer_division(x: SExpr, y: SExpr, small: fn(i64, i64) -> Option<i64>, big: fn(&BigInt, &BigInt) -> BigInt) -> SResult<SExpr> {
+    if as_bigint(&y)?.is_zero() {
+        serr!(DivisionByZero)
+    }
+
+    integer_op(x, y, small, big)
+}
+
+pub fn gcd(args: Args) -> SResult<SExpr> {
+    args.evaled()?
+        .into_iter()
+        .fold(Ok(sint!(0)), |acc, x| integer_op(acc?, x,
+            |x, y| if x == i64::MIN || y == i64::MIN { None } else { Some(funcs::gcd(x, y)) },
+            |x, y| x.gcd(y)))
+}
+
+pub fn lcm(args: Args) -> SResult<SExpr> {
+    args.evaled()?
+        .into_iter()
+        .fold(Ok(sint!(1)), |acc, x| integer_op(acc?, x,
+            |_, _| None,
+            |x, y| {
+                let gcd = x.gcd(y);
+                if gcd.is_zero() { gcd } else { (x * y).abs() / gcd }
+            }))
+}
+
+/// `(expt base power)`, exact if `base` is exact and `power` is an exact
+/// integer, or an exact fraction whose root of `base` is exact.
+pub fn expt(args: Args) -> SResult<SExpr> {
+    let (base, power) = args.evaled()?.own_two()?;
+    if let (SExpr::Atom(ref base), SExpr::Atom(ref power)) = (&base, &power) {
+        if let (Some(base), Some(power)) = (base.as_rational(), power.as_rational()) {
+            if let Some(result) = exact_expt(&base, &power)? {
+                return Ok(result.into())
+            }
+        }
+    }
+
+    if is_complex(&base) || is_complex(&power) {
+        return Ok(base.into_complex()?.powc(power.into_complex()?).into())
+    }
+
+    match power {
+        SExpr::Atom(Token::Integer(power)) if power.unsigned_abs() <= i32::MAX as u64 =>
+            Ok(sfloat!(base.into_float()?.powi(power as i32))),
+        power => {
+            let (base, power) = (base.into_float()?, power.into_float()?);
+            let result = base.powf(power);
+
+            // Negative numbers to non-integer powers are complex
+            if result.is_nan() && !base.is_nan() && !power.is_nan() {
+                Ok(Complex::from(base).powc(Complex::from(power)).into())
+            } else {
+                Ok(sfloat!(result))
+            }
+        }
+    }
+}
+
+fn exact_expt(base: &Fraction, power: &Fraction) -> SResult<Option<Fraction>> {
+    if power.n.is_negative() && base.n.is_zero() {
+        serr!(DivisionByZero)
+    }
+
+    let (k, root) = match (power.n.abs().to_i64(), power.d.to_i64()) {
+        (Some(k), Some(root)) if k <= u32::MAX as i64 && root <= u32::MAX as i64 => (k as u32, root as u32),
+        _ if power.is_int() => bail!(Generic => "expt: the power is too big"),
+        _ => return Ok(None)
+    };
+
+    // Roots of negative numbers are complex
+    if root != 1 && base.n.is_negative() {
+        return Ok(None)
+    }
+
+    let (n, d) = match (exact_root(&base.n, root), exact_root(&base.d, root)) {
+        (Some(n), Some(d)) => (n, d),
+        _ => return Ok(None)
+    };
+
+    let result = Fraction::new(n.pow(k), d.pow(k));
+    if power.n.is_negative() {
+        Ok(Some(Fraction::from(1) / result))
+    } else {
+        Ok(Some(result))
+    }
+}
+
+/// Returns the `k`th root of a non-negative `x` if it is an integer.
+fn exact_root(x: &BigInt, k: u32) -> Option<BigInt> {
+    let root = match k {
+        1 => return Some(x.clone()),
+        2 => x.sqrt(),
+        _ => BigInt::from_f64(x.to_f64().powf(1.0 / k as f64).round())?
     };
 
-    Ok(sint!(result))
+    if &root.pow(k) == x { Some(root) } else { None }
 }
 
-pub fn denominator(args: Args) -> SResult<SExpr> {
+/// `(sqrt z)`, exact for exact numbers that are squares of rationals.
+pub fn sqrt(args: Args) -> SResult<SExpr> {
+    let num = args.evaled()?.own_one()?;
+    if let SExpr::Atom(ref x) = num {
+        if let Some(x) = x.as_rational() {
+            if !x.n.is_negative() {
+                if let (Some(n), Some(d)) = (exact_root(&x.n, 2), exact_root(&x.d, 2)) {
+                    return Ok(Fraction::new(n, d).into())
+                }
+            }
+        }
+    }
+
+    float_or_complex(num, f64::sqrt, Complex::sqrt)
+}
+
+/// `(exact-integer-sqrt k)`, returns the integer square root of `k` and
+/// the remainder.
+pub fn exact_integer_sqrt(args: Args) -> SResult<SExpr> {
     let num = args.evaled()?.own_one()?;
-    let result = match num {
-        SExpr::Atom(Token::Integer(i)) => (fraction::Fraction::from(i).d),
-        SExpr::Atom(Token::Float(i)) => (fraction::Fraction::from(i).d),
-        SExpr::Atom(Token::Fraction(f)) => f.d,
+    if is_float(&num) {
+        bail!(TypeMismatch => "exact integer", num)
+    }
+
+    let k = as_bigint(&num)?;
+    if k.is_negative() {
+        bail!(TypeMismatch => "non-negative integer", num)
+    }
+
+    let s = k.sqrt();
+    let rest = &k - &(&s * &s);
+    Ok(SExpr::Values(vec![s.into(), rest.into()]))
+}
+
+/// Rounding procedures: exact numbers are rounded with `exact`, floats
+/// with `inexact` and stay floats.
+pub fn round_with(args: Args, exact: fn(&Fraction) -> BigInt, inexact: fn(f64) -> f64) -> SResult<SExpr> {
+    match args.evaled()?.own_one()? {
+        SExpr::Atom(Token::Float(x)) => Ok(sfloat!(inexact(x))),
+        SExpr::Atom(Token::Fraction(x)) => Ok(exact(&x).into()),
+        x@SExpr::Atom(Token::Integer(_)) | x@SExpr::Atom(Token::BigInt(_)) => Ok(x),
         x => bail!(TypeMismatch => "number", x)
+    }
+}
+
+/// `(exact z)`, the exact value of a float.
+pub fn exact(args: Args) -> SResult<SExpr> {
+    match args.evaled()?.own_one()? {
+        SExpr::Atom(Token::Float(x)) => match Fraction::from_f64(x) {
+            Some(x) => Ok(x.into()),
+            None => bail!("exact: {} has no exact representation", x)
+        },
+        x@SExpr::Atom(Token::Complex(_)) => bail!("exact: {} has no exact representation", x),
+        x => if x.is_numeric() { Ok(x) } else { bail!(TypeMismatch => "number", x) }
+    }
+}
+
+/// `(inexact z)`, the closest float to a number.
+pub fn inexact(args: Args) -> SResult<SExpr> {
+    let num = args.evaled()?.own_one()?;
+    if !num.is_numeric() {
+        bail!(TypeMismatch => "number", num)
+    }
+
+    if is_complex(&num) {
+        return Ok(num)
+    }
+
+    Ok(sfloat!(num.into_float()?))
+}
+
+/// `(rationalize x y)`, the simplest rational that differs from `x` by no
+/// more than `y`. Inexact if one of the arguments is inexact.
+pub fn rationalize(args: Args) -> SResult<SExpr> {
+    let (x, y) = args.evaled()?.own_two()?;
+    let inexact = is_float(&x) || is_float(&y);
+    let (x, y) = match (exact_value(&x)?, exact_value(&y)?) {
+        (Some(x), Some(y)) => (x, y),
+        // One of them is an infinity or NaN
+        _ => {
+            let (x, y) = (x.into_float()?, y.into_float()?);
+            return Ok(sfloat!(if x.is_finite() && y.is_infinite() { 0.0 } else { x + y * 0.0 }))
+        }
     };
 
-    Ok(sint!(result))
+    let y = if y.n.is_negative() { Fraction::new(-y.n, y.d) } else { y };
+    let result = simplest_between(x.clone() - y.clone(), x + y);
+    if inexact {
+        Ok(sfloat!(f64::from(&result)))
+    } else {
+        Ok(result.into())
+    }
 }
 
-pub fn number_string(args: Args) -> SResult<SExpr> {
-    if args.len() == 1 {
-        let num = args.evaled()?.own_one()?;
-        Ok(sstr!(num.to_string()))
-    } else if args.len() == 2 {
-        let (num, radix) = args.evaled()?.own_two()?;
-        Ok(sstr!(Radix::new(num.into_float()?, radix.into_int()? as u32)?.to_string()))
+/// The simplest rational in `[lo, hi]`, the one with the smallest
+/// denominator.
+fn simplest_between(lo: Fraction, hi: Fraction) -> Fraction {
+    let zero = Fraction::from(0);
+    if lo > zero {
+        simplest_positive(lo, hi)
+    } else if hi < zero {
+        let result = simplest_positive(zero.clone() - hi, zero.clone() - lo);
+        zero - result
     } else {
-        bail!(WrongArgCount => 2 as usize, args.len())
+        zero
     }
 }
 
-pub fn string_number(args: Args) -> SResult<SExpr> {
-    if args.len() == 1 {
-        use lexer::parse_number;
-        let num_str = args.evaled()?.own_one()?.into_str()?;
-        let num_token = parse_number(&num_str)
-            .ok_or_else(|| SErr::new_generic(&format!("Can't parse as number: {}", num_str)))?;
-        Ok(SExpr::Atom(num_token))
-    } else if args.len() == 2 {
-        bail!(Generic => "// FIXME: not implemented")
+fn simplest_positive(lo: Fraction, hi: Fraction) -> Fraction {
+    let floor = lo.floor();
+    if lo.is_int() {
+        lo
+    } else if floor < hi.floor() {
+        Fraction::from(&floor + &BigInt::from(1))
     } else {
-        bail!(WrongArgCount => 2 as usize, args.len())
+        let floor = Fraction::from(floor);
+        let one = Fraction::from(1);
+        let rest = simplest_positive(one.clone() / (hi - floor.clone()), one.clone() / (lo - floor.clone()));
+        floor + one / rest
     }
+}
+
+/// Checks a float property, exact numbers are always finite. Complex
+/// numbers are finite if both parts are, NaN or infinite if one of them is.
+pub fn float_predicate(args: Args, exact: bool, predicate: fn(f64) -> bool) -> SResult<SExpr> {
+    match args.evaled()?.own_one()? {
+        SExpr::Atom(Token::Float(x)) => Ok(sbool!(predicate(x))),
+        SExpr::Atom(Token::Complex(z)) if exact => Ok(sbool!(predicate(z.re) && predicate(z.im))),
+        SExpr::Atom(Token::Complex(z)) => Ok(sbool!(predicate(z.re) || predicate(z.im))),
+        x => if x.is_numeric() { Ok(sbool!(exact)) } else { bail!(TypeMismatch => "number", x) }
+    }
+}
 
+/// `(log z [base])`
+pub fn log(args: Args) -> SResult<SExpr> {
+    let evaled = args.evaled()?;
+    match evaled.len() {
+        1 => float_or_complex(evaled.own_one()?, f64::ln, Complex::ln),
+        2 => {
+            let (z, base) = evaled.own_two()?;
+            if z.is_complex() || base.is_complex() {
+                return Ok(z.into_complex()?.log(base.into_complex()?).into())
+            }
+
+            let z = float_or_complex(z, f64::ln, Complex::ln)?;
+            let base = float_or_complex(base, f64::ln, Complex::ln)?;
+            arithmetic('/', z, base)
+        },
+        x => bail!(WrongArgCount => 2 as usize, x)
+    }
+}
+
+/// Applies a float function, or its complex version if the number is
+/// complex or out of the real domain of the function, like `(asin 2)`.
+pub fn float_or_complex(num: SExpr, real: fn(f64) -> f64, complex: fn(Complex) -> Complex) -> SResult<SExpr> {
+    if let SExpr::Atom(Token::Complex(z)) = num {
+        return Ok(complex(z).into())
+    }
+
+    let x = num.into_float()?;
+    let result = real(x);
+    if result.is_nan() && !x.is_nan() {
+        Ok(complex(Complex::from(x)).into())
+    } else {
+        Ok(sfloat!(result))
+    }
 }
 
+pub fn make_rectangular(args: Args) -> SResult<SExpr> {
+    let (re, im) = args.evaled()?.own_two()?;
+    if is_exact_zero(&im) {
+        return Ok(re)
+    }
+
+    Ok(Complex::new(re.into_float()?, im.into_float()?).into())
+}
+
+pub fn make_polar(args: Args) -> SResult<SExpr> {
+    let (magnitude, angle) = args.evaled()?.own_two()?;
+    if is_exact_zero(&angle) {
+        return Ok(magnitude)
+    }
+
+    Ok(Complex::from_polar(magnitude.into_float()?, angle.into_float()?).into())
+}
+
+pub fn real_part(args: Args) -> SResult<SExpr> {
+    match args.evaled()?.own_one()? {
+        SExpr::Atom(Token::Complex(z)) => Ok(sfloat!(z.re)),
+        x => if x.is_numeric() { Ok(x) } else { bail!(TypeMismatch => "number", x) }
+    }
+}
+
+/// `(imag-part z)`, exact zero for real numbers.
+pub fn imag_part(args: Args) -> SResult<SExpr> {
+    match args.evaled()?.own_one()? {
+        SExpr::Atom(Token::Complex(z)) => Ok(sfloat!(z.im)),
+        x => if x.is_numeric() { Ok(sint!(0)) } else { bail!(TypeMismatch => "number", x) }
+    }
+}
+
+/// `(magnitude z)`, the absolute value for real numbers.
+pub fn magnitude(args: Args) -> SResult<SExpr> {
+    match args.evaled()?.own_one()? {
+        SExpr::Atom(Token::Complex(z)) => Ok(sfloat!(z.magnitude())),
+        SExpr::Atom(Token::Float(x)) => Ok(sfloat!(x.abs())),
+        SExpr::Atom(ref x) if x.is_exact() => {
+            let x = x.as_rational().unwrap();
+            Ok(Fraction::new(x.n.abs(), x.d).into())
+        },
+        x => bail!(TypeMismatch => "number", x)
+    }
+}
+
+/// `(angle z)`, exact zero for non-negative exact numbers.
+pub fn angle(args: Args) -> SResult<SExpr> {
+    match args.evaled()?.own_one()? {
+        SExpr::Atom(Token::Complex(z)) => Ok(sfloat!(z.angle())),
+        SExpr::Atom(Token::Float(x)) => Ok(sfloat!(0f64.atan2(x))),
+        SExpr::Atom(ref x) if x.is_exact() => if x.as_rational().unwrap().n.is_negative() {
+            Ok(sfloat!(::std::f64::consts::PI))
+        } else {
+            Ok(sint!(0))
+        },
+        x => bail!(TypeMismatch => "number", x)
+    }
+}
+
+/// `(numerator q)`, a float if `q` is a float.
+pub fn numerator(args: Args) -> SResult<SExpr> {
+    let num = args.evaled()?.own_one()?;
+    let inexact = is_float(&num);
+    match exact_value(&num)? {
+        Some(x) if inexact => Ok(sfloat!(x.n.to_f64())),
+        Some(x) => Ok(x.n.into()),
+        None => bail!(TypeMismatch => "rational", num)
+    }
+}
+
+/// `(denominator q)`, a float if `q` is a float.
+pub fn denominator(args: Args) -> SResult<SExpr> {
+    let num = args.evaled()?.own_one()?;
+    let inexact = is_float(&num);
+    match exact_value(&num)? {
+        Some(x) if inexact => Ok(sfloat!(x.d.to_f64())),
+        Some(x) => Ok(x.d.into()),
+        None => bail!(TypeMismatch => "rational", num)
+    }
+}
+
+pub fn number_string(args: Args) -> SResult<SExpr> {
+    let (num, rest) = args.evaled()?.own_one_rest()?;
+    let radix = match rest.len() {
+        0 => 10,
+        1 => rest[0].as_int()? as u32,
+        x => bail!(WrongArgCount => 2 as usize, x + 1)
+    };
+
+    match num {
+        SExpr::Atom(x@Token::Integer(_)) | SExpr::Atom(x@Token::BigInt(_))
+            | SExpr::Atom(x@Token::Fraction(_)) | SExpr::Atom(x@Token::Float(_))
+            | SExpr::Atom(x@Token::Complex(_)) => {
+            Ok(sstr!(Radix::new(x, radix)?.to_string()))
+        },
+        x => bail!(TypeMismatch => "number", x)
+    }
+}
+
+/// `(string->number string [radix])`, returns `#f` if the string is not a
+/// number.
+pub fn string_number(args: Args) -> SResult<SExpr> {
+    let (num_str, rest) = args.evaled()?.own_one_rest()?;
+    let radix = match rest.len() {
+        0 => 10,
+        1 => rest[0].as_int()? as u32,
+        x => bail!(WrongArgCount => 2 as usize, x + 1)
+    };
+
+    if radix < 2 || radix > 36 {
+        bail!(Generic => "Unsupported radix")
+    }
+
+    Ok(radix::parse_number(&num_str.into_str()?, radix)
+       .map(SExpr::Atom)
+       .unwrap_or(sbool!(false)))
+}
+
+/// Makes a primitive from a float function, the complex version of the
+/// function is used for complex numbers. With two arguments only real
+/// numbers are accepted.
 #[macro_export]
 macro_rules! call_float_fun(
     ($e: ident) => {
         |args| {
+            use utils::complex::Complex;
             let num = args.evaled()?.own_one()?;
-            let result = num.into_float()?.$e();
-            if result.trunc() == result {
-                Ok((result as i64).into())
-            } else {
-                Ok(result.into())
-            }
+            numeric::float_or_complex(num, f64::$e, Complex::$e)
         }
     };
     ($e: ident, $e1: ident) => {
         |args| {
             use serr::SErr;
+            use utils::complex::Complex;
             let evaled = args.evaled()?;
-            let result = match evaled.len() {
-                1 => evaled.own_one()?.into_float()?.$e(),
+            match evaled.len() {
+                1 => numeric::float_or_complex(evaled.own_one()?, f64::$e, Complex::$e),
                 2 => {
                     let (f1, f2) = evaled.own_two()?;
-                    f1.into_float()?.$e1(f2.into_float()?)
+                    Ok(sfloat!(f1.into_float()?.$e1(f2.into_float()?)))
                 },
                 x => bail!(WrongArgCount => 2 as usize, x)
-            };
-
-            if result.trunc() == result {
-                Ok((result as i64).into())
-            } else {
-                Ok(result.into())
             }
         }
     }
 );
+
+//
+// Helpers
+//
+/// Applies an integer operation on `i64`s if `small` can compute the
+/// result, on `BigInt`s otherwise. Integral floats are accepted too, the
+/// result is a float then.
+fn integer_op(x: SExpr, y: SExpr, small: fn(i64, i64) -> Option<i64>, big: fn(&BigInt, &BigInt) -> BigInt) -> SResult<SExpr> {
+    if is_float(&x) || is_float(&y) {
+        let result = big(&as_bigint(&x)?, &as_bigint(&y)?);
+        return Ok(sfloat!(result.to_f64()))
+    }
+
+    if let (SExpr::Atom(Token::Integer(a)), SExpr::Atom(Token::Integer(b))) = (&x, &y) {
+        if let Some(result) = small(*a, *b) {
+            return Ok(sint!(result))
+        }
+    }
+
+    Ok(big(&as_bigint(&x)?, &as_bigint(&y)?).into())
+}
+
+fn as_bigint(x: &SExpr) -> SResult<BigInt> {
+    match x {
+        SExpr::Atom(Token::Integer(x)) => Ok(BigInt::from(*x)),
+        SExpr::Atom(Token::BigInt(x)) => Ok(x.clone()),
+        SExpr::Atom(Token::Float(f)) if f.is_finite() && f.trunc() == *f => Ok(BigInt::from_f64(*f).unwrap()),
+        x => bail!(TypeMismatch => "integer", x)
+    }
+}
+
+fn is_float(x: &SExpr) -> bool {
+    match x {
+        SExpr::Atom(Token::Float(_)) => true,
+        _ => false
+    }
+}
+
+fn is_complex(x: &SExpr) -> bool {
+    match x {
+        SExpr::Atom(Token::Complex(_)) => true,
+        _ => false
+    }
+}
+
+fn is_exact_zero(x: &SExpr) -> bool {
+    match x {
+        SExpr::Atom(Token::Integer(0)) => true,
+        _ => false
+    }
+}
+
+/// The exact value of a number, `None` for infinities and NaN.
+fn exact_value(x: &SExpr) -> SResult<Option<Fraction>> {
+    match x {
+        SExpr::Atom(Token::Float(x)) => Ok(Fraction::from_f64(*x)),
+        SExpr::Atom(ref x) if x.is_exact() => Ok(x.as_rational()),
+        x => bail!(TypeMismatch => "number", x)
+    }
+}
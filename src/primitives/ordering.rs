@@ -1,8 +1,7 @@
 use std::cmp::PartialOrd as po;
-use std::cmp::PartialEq as pe;
+use std::cmp::Ordering;
 use parser::SExpr;
 use evaluator::Args;
-use env::EnvRef;
 use serr::{SErr, SResult};
 
 pub fn lt(args: Args) -> SResult<SExpr> {
@@ -22,29 +21,29 @@ pub fn gte(args: Args) -> SResult<SExpr> {
 }
 
 pub fn eq(args: Args) -> SResult<SExpr> {
-    compare(args, pe::eq)
+    // Not `PartialEq`, numbers of different types can be equal
+    compare(args, |x, y| x.partial_cmp(y) == Some(Ordering::Equal))
 }
 
 fn compare<F>(args: Args, op: F) -> SResult<SExpr>
 where F: Fn(&SExpr,&SExpr) -> bool {
-    Ok(sbool!(check(&args, op, &args.env)?))
+    Ok(sbool!(check(&args.evaled()?, op)?))
 }
 
-fn check<F>(xs: &[SExpr], op: F, env: &EnvRef) -> SResult<bool>
+fn check<F>(xs: &[SExpr], op: F) -> SResult<bool>
 where F: Fn(&SExpr,&SExpr) -> bool {
     match xs {
         [] | [_] => Ok(true),
         _ => {
-            let x1 = xs[0].eval(env)?;
-            let x2 = xs[1].eval(env)?;
-            let rest = &xs[2..];
+            let x1 = &xs[0];
+            let x2 = &xs[1];
             if !((x1.is_numeric() && x2.is_numeric())
                  || (x1.is_str() && x2.is_str())
                  || (x1.is_chr() && x2.is_chr())) {
-                bail!(TypeMismatch => "number or string or char", slist![x1, x2])
+                bail!(TypeMismatch => "number or string or char", slist![x1.clone(), x2.clone()])
             }
 
-            Ok(op(&x1, &x2) && check(rest, op, env)?)
+            Ok(op(x1, x2) && check(&xs[1..], op)?)
         }
     }
 }
@@ -0,0 +1,114 @@
+use parser::SExpr;
+use parser::SExprs;
+use env::EnvRef;
+use evaluator;
+use evaluator::{Args, State, ContRef, Cont, Frame};
+use procedure::{ProcedureData, ParameterData};
+use primitives::vector::index;
+use serr::{SErr, SResult};
+
+/// `(make-parameter value [converter])`
+/// The converter is applied to the initial value and to the values
+/// given with `parameterize`.
+pub fn make_parameter(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (value, mut rest) = args.own_one_rest()?;
+    match rest.len() {
+        0 => Ok(State::Return(ProcedureData::new_parameter(ParameterData::new(value, None)), k)),
+        1 => {
+            let converter = rest.remove(0);
+            converter.as_proc()?;
+
+            let k = Cont::push(Frame::Call(new_parameter, vec![converter.clone()], env.clone_ref()), k);
+            Ok(State::Apply(converter, vec![value], env, k))
+        },
+        n => bail!(WrongArgCount => 2 as usize, n + 1)
+    }
+}
+
+fn new_parameter(value: SExpr, mut converter: SExprs, _env: EnvRef, k: ContRef) -> SResult<State> {
+    let parameter = ParameterData::new(value, Some(converter.remove(0)));
+    Ok(State::Return(ProcedureData::new_parameter(parameter), k))
+}
+
+/// `(parameterize ((param value) ...) body ...)`
+/// The parameters and the values are evaluated, then the body is evaluated
+/// with the parameters bound to the converted values.
+pub fn parameterize(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (bindings, body) = args.own_one_rest()?;
+    if body.is_empty() {
+        bail!(Generic => "Expected an expression, found an empty body")
+    }
+
+    // (with-parameters (lambda () body ...) param value ...)
+    let mut call = vec![
+        ProcedureData::new_control(with_parameters),
+        ProcedureData::new_compound(SExpr::Nil, body, &env)?
+    ];
+    for binding in bindings.into_list()? {
+        let mut parts = binding.clone().into_list()?;
+        if parts.len() != 2 {
+            bail!(UnexpectedForm => binding)
+        }
+
+        call.append(&mut parts);
+    }
+
+    Ok(State::Eval(SExpr::list(call), env, k))
+}
+
+fn with_parameters(args: Args, k: ContRef) -> SResult<State> {
+    let env = args.env();
+    let (thunk, rest) = args.own_one_rest()?;
+    for parameter in rest.iter().step_by(2) {
+        as_parameter(parameter)?;
+    }
+
+    // [index, thunk, param, value, ...], see `convert_parameters`
+    let mut state = vec![sint!(0), thunk];
+    state.extend(rest);
+    convert_parameters(state, env, k)
+}
+
+/// Applies the converters to the values of `state`, starting from the
+/// binding at its index. The converters are applied one after another
+/// through `converted`, then the thunk is applied with the new values.
+fn convert_parameters(mut state: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    let mut i = index(state[0].clone())?;
+    while 2 + 2 * i < state.len() {
+        if let Some(converter) = as_parameter(&state[2 + 2 * i])?.converter {
+            state[0] = sint!(i as i64);
+            let value = state[3 + 2 * i].clone();
+            let k = Cont::push(Frame::Call(converted, state, env.clone_ref()), k);
+            return Ok(State::Apply((*converter).clone(), vec![value], env, k))
+        }
+
+        i += 1;
+    }
+
+    let mut iter = state.into_iter().skip(1);
+    let thunk = iter.next().unwrap();
+    let mut bindings = vec![];
+    while let (Some(parameter), Some(value)) = (iter.next(), iter.next()) {
+        bindings.push((as_parameter(&parameter)?, value));
+    }
+
+    // The old values are put back when the body returns, see `Frame::Unwind`.
+    evaluator::push_parameters(bindings);
+    Ok(State::Apply(thunk, vec![], env, Cont::push(Frame::Unwind, k)))
+}
+
+fn converted(value: SExpr, mut state: SExprs, env: EnvRef, k: ContRef) -> SResult<State> {
+    let i = index(state[0].clone())?;
+    state[3 + 2 * i] = value;
+    state[0] = sint!(i as i64 + 1);
+    convert_parameters(state, env, k)
+}
+
+fn as_parameter(x: &SExpr) -> SResult<ParameterData> {
+    match x {
+        SExpr::Procedure(ProcedureData::Parameter(x)) => Ok(x.clone()),
+        x => bail!(TypeMismatch => "parameter", x)
+    }
+}
@@ -30,7 +30,13 @@ pub const PRELUDE: &'static str = "
 (define (boolean? x) (eq? (typeof x) 'boolean))
 (define (char? x) (eq? (typeof x) 'chr))
 (define (string? x) (eq? (typeof x) 'str))
-(define (integer? x) (eq? (typeof x) 'integer))
+(define (exact-integer? x) (eq? (typeof x) 'integer))
+(define (integer? x)
+  (or (exact-integer? x)
+      (and (eq? (typeof x) 'float) (finite? x) (= x (round x)))))
+(define (rational? x)
+  (or (exact? x)
+      (and (eq? (typeof x) 'float) (finite? x))))
 (define (inexact? x) (not (exact? x)))
 (define (exact? x)
   (define type (typeof x))
@@ -40,27 +46,40 @@ pub const PRELUDE: &'static str = "
   (define type (typeof x))
   (or (eq? type 'integer)
       (eq? type 'fraction)
-      (eq? type 'fraction)))
+      (eq? type 'float)
+      (eq? type 'complex)))
+(define (real? x) (and (number? x) (not (eq? (typeof x) 'complex))))
+(define complex? number?)
 (define (pair? x)
   (define type (typeof x))
   (and (not (null? x))
     (or (eq? type 'list)
         (eq? type 'list-dotted))))
 (define (list? x) (eq? (typeof x) 'list))
+(define (vector? x) (eq? (typeof x) 'vector))
+(define (bytevector? x) (eq? (typeof x) 'bytevector))
+(define (hash-table? x) (eq? (typeof x) 'hash-table))
+(define (error-object? x) (eq? (typeof x) 'condition))
+(define (eof-object? x) (eq? (typeof x) 'eof))
 (define (output-port? x)
   (define type (typeof x))
   (or (eq? type 'port-std-out)
+      (eq? type 'port-std-err)
       (eq? type 'port-binary-out)
-      (eq? type 'port-textual-out)))
+      (eq? type 'port-textual-out)
+      (eq? type 'port-string-out)))
 (define (input-port? x)
   (define type (typeof x))
   (or (eq? type 'port-std-in)
       (eq? type 'port-binary-in)
-      (eq? type 'port-textual-in)))
+      (eq? type 'port-textual-in)
+      (eq? type 'port-string-in)))
 (define (textual-port? x)
   (define type (typeof x))
   (or (eq? type 'port-textual-in)
-      (eq? type 'port-textual-out)))
+      (eq? type 'port-textual-out)
+      (eq? type 'port-string-in)
+      (eq? type 'port-string-out)))
 (define (binary-port? x)
   (define type (typeof x))
   (or (eq? type 'port-binary-in)
@@ -73,11 +92,12 @@ pub const PRELUDE: &'static str = "
 (define zero? (curry = 0))
 (define positive? (curry < 0))
 (define negative? (curry > 0))
-(define (odd? num)  (= (remainder num 2) 1))
+(define (odd? num)  (not (even? num)))
 (define (even? num) (= (remainder num 2) 0))
 (define (abs num) (if (negative? num) (- num) num))
-(define (gcd a b) (if (= b 0) (abs a) (gcd b (modulo a b))))
-(define (lcm a b) (/ (abs (* a b)) (gcd a b)))
+(define (square x) (* x x))
+(define exact->inexact inexact)
+(define inexact->exact exact)
 (define (1+ n) (+ n 1))
 (define (1- n) (- n 1))
 
@@ -92,8 +112,14 @@ pub const PRELUDE: &'static str = "
 (define (filter pred lst) (foldr (lambda (x y) (if (pred x) (cons x y) y)) '() lst))
 (define (reverse lst) (fold (flip cons) '() lst))
 (define (length lst) (fold (lambda (x y) (+ x 1)) 0 lst))
-(define (max first . rest) (fold (lambda (old new) (if (> old new) old new)) first rest))
-(define (min first . rest) (fold (lambda (old new) (if (< old new) old new)) first rest))
+
+;; The result of max and min is inexact if any argument is inexact
+(define (max first . rest)
+  (let ((x (fold (lambda (old new) (if (> old new) old new)) first rest)))
+    (if (and (exact? x) (not (null? (filter inexact? (cons first rest))))) (inexact x) x)))
+(define (min first . rest)
+  (let ((x (fold (lambda (old new) (if (< old new) old new)) first rest)))
+    (if (and (exact? x) (not (null? (filter inexact? (cons first rest))))) (inexact x) x)))
 (define (list-tail lst n) (if (<= n 0) lst (list-tail (cdr lst) (- n 1))))
 (define (list-head lst n) (if (<= n 0) '() (cons (car lst) (list-head (cdr lst) (- n 1)))))
 (define (list-ref lst n) (car (list-tail lst n)))
@@ -183,6 +209,59 @@ pub const PRELUDE: &'static str = "
 (define char->integer (curry convert-type 'integer))
 (define integer->char (curry convert-type 'chr))
 
+;; vector
+(define (vector-map f . vs)
+  (define len (apply min (map vector-length vs)))
+  (define result (make-vector len))
+  (let loop ((i 0))
+    (if (< i len)
+        (begin
+          (vector-set! result i (apply f (map (lambda (v) (vector-ref v i)) vs)))
+          (loop (+ i 1)))
+        result)))
+
+(define (vector-for-each f . vs)
+  (define len (apply min (map vector-length vs)))
+  (let loop ((i 0))
+    (if (< i len)
+        (begin
+          (apply f (map (lambda (v) (vector-ref v i)) vs))
+          (loop (+ i 1))))))
+
+;; hash tables
+(define (hash-table-ref table key . rest)
+  (define missing (list 'missing))
+  (define value (hash-table-ref/default table key missing))
+  (cond
+    ((not (eq? value missing))
+     (if (and (pair? rest) (pair? (cdr rest))) ((cadr rest) value) value))
+    ((pair? rest) ((car rest)))
+    (else (error \"hash-table-ref: no such key\" key))))
+
+(define (hash-table-update! table key f . rest)
+  (hash-table-set! table key (f (apply hash-table-ref table key rest))))
+
+(define (hash-table-update!/default table key f default)
+  (hash-table-set! table key (f (hash-table-ref/default table key default))))
+
+(define (hash-table-walk table f)
+  (let loop ((alist (hash-table->alist table)))
+    (if (pair? alist)
+        (begin
+          (f (caar alist) (cdar alist))
+          (loop (cdr alist))))))
+
+;; Both (hash-table-fold table kons knil) from SRFI-69 and
+;; (hash-table-fold kons knil table) from SRFI-125 are accepted.
+(define (hash-table-fold x y z)
+  (define (fold-alist kons knil alist)
+    (if (pair? alist)
+        (fold-alist kons (kons (caar alist) (cdar alist) knil) (cdr alist))
+        knil))
+  (if (hash-table? x)
+      (fold-alist y z (hash-table->alist x))
+      (fold-alist x y (hash-table->alist z))))
+
 ;; ports
 (define (println x) (display x) (newline))
 
@@ -195,4 +274,31 @@ pub const PRELUDE: &'static str = "
   (define f (open-input-file str))
   (proc f)
   (close-port f))
+
+(define (call-with-output-string proc)
+  (define port (open-output-string))
+  (proc port)
+  (get-output-string port))
+
+(define (with-output-to-string thunk)
+  (define port (open-output-string))
+  (parameterize ((current-output-port port))
+    (thunk))
+  (get-output-string port))
+
+(define (with-output-to-file str thunk)
+  (define port (open-output-file str))
+  (define result
+    (parameterize ((current-output-port port))
+      (thunk)))
+  (close-port port)
+  result)
+
+(define (with-input-from-file str thunk)
+  (define port (open-input-file str))
+  (define result
+    (parameterize ((current-input-port port))
+      (thunk)))
+  (close-port port)
+  result)
 ";
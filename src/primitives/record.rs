@@ -0,0 +1,131 @@
+use parser::SExpr;
+use evaluator::Args;
+use procedure::ProcedureData;
+use record::{RecordTypeData, RecordData};
+use expander::base_name;
+use serr::{SErr, SResult};
+
+/// `(define-record-type <name> (constructor field ...) predicate (field accessor [modifier]) ...)`
+///
+/// The procedures are compound procedures that call the primitives below
+/// with the record type, so they are created like any other procedure.
+pub fn define_record_type(args: Args) -> SResult<SExpr> {
+    let env = args.env();
+    let mut iter = args.into_iter();
+    let type_name = iter.next()
+        .ok_or_else(|| SErr::new_id_not_found("nothing"))?
+        .into_symbol()?;
+    let constructor = iter.next()
+        .ok_or_else(|| SErr::new_expr_not_found("nothing"))?;
+    let predicate = iter.next()
+        .ok_or_else(|| SErr::new_expr_not_found("nothing"))?;
+
+    let specs = iter.map(|x| x.into_list())
+        .collect::<SResult<Vec<_>>>()?;
+    let fields = specs.iter()
+        .map(|spec| spec.get(0).cloned().ok_or(SErr::FoundNothing)?.into_symbol())
+        .collect::<SResult<Vec<_>>>()?;
+    let field_index = |name: &str| fields.iter()
+        .position(|x| x == name)
+        .ok_or_else(|| SErr::new_generic(&format!("define-record-type: unknown field `{}`", base_name(name))));
+
+    let rtype = RecordTypeData::new(base_name(&type_name).to_string(), fields.clone());
+    let rtype_expr = SExpr::RecordType(rtype.clone());
+    env.define(type_name, rtype_expr.clone());
+
+    if constructor.to_bool() {
+        // (constructor field ...) => (lambda (field ...) (make-record <type> index ... field ...))
+        let (name, params) = constructor.list_own_one_rest()?;
+        let mut body = vec![ProcedureData::new_primitive(make_record), rtype_expr.clone()];
+        for param in &params {
+            body.push(field_index(param.as_symbol()?)?.into());
+        }
+        body.extend(params.iter().cloned());
+
+        let procedure = ProcedureData::new_compound(SExpr::list(params), vec![SExpr::list(body)], &env)?;
+        env.define(name.into_symbol()?, procedure);
+    }
+
+    if predicate.to_bool() {
+        let body = slist![ProcedureData::new_primitive(record_qm), rtype_expr.clone(), ssymbol!("obj")];
+        let procedure = ProcedureData::new_compound(slist![ssymbol!("obj")], vec![body], &env)?;
+        env.define(predicate.into_symbol()?, procedure);
+    }
+
+    for spec in specs {
+        let mut spec_iter = spec.into_iter();
+        let index: SExpr = field_index(spec_iter.next().unwrap().as_symbol()?)?.into();
+
+        if let Some(accessor) = spec_iter.next() {
+            let body = slist![ProcedureData::new_primitive(record_ref), rtype_expr.clone(),
+                              ssymbol!("record"), index.clone()];
+            let procedure = ProcedureData::new_compound(slist![ssymbol!("record")], vec![body], &env)?;
+            env.define(accessor.into_symbol()?, procedure);
+        }
+
+        if let Some(modifier) = spec_iter.next() {
+            let body = slist![ProcedureData::new_primitive(record_set_em), rtype_expr.clone(),
+                              ssymbol!("record"), index, ssymbol!("value")];
+            let procedure = ProcedureData::new_compound(slist![ssymbol!("record"), ssymbol!("value")], vec![body], &env)?;
+            env.define(modifier.into_symbol()?, procedure);
+        }
+    }
+
+    Ok(SExpr::Unspecified)
+}
+
+/// `(make-record <type> index ... value ...)`, fields that are not given
+/// a value are left unspecified.
+fn make_record(args: Args) -> SResult<SExpr> {
+    let (rtype_, rest) = args.evaled()?.own_one_rest()?;
+    let rtype = rtype_.into_record_type()?;
+    let (indexes, inits) = rest.split_at(rest.len() / 2);
+
+    let mut values = vec![SExpr::Unspecified; rtype.0.fields.len()];
+    for (index, init) in indexes.iter().zip(inits) {
+        values[index.as_int()? as usize] = init.clone();
+    }
+
+    Ok(SExpr::Record(RecordData::new(rtype, values)))
+}
+
+fn record_qm(args: Args) -> SResult<SExpr> {
+    let (rtype_, obj) = args.evaled()?.own_two()?;
+    let rtype = rtype_.into_record_type()?;
+
+    Ok(sbool!(match obj {
+        SExpr::Record(ref x) => x.is_a(&rtype),
+        _ => false
+    }))
+}
+
+fn record_ref(args: Args) -> SResult<SExpr> {
+    let (rtype_, record_, index) = args.evaled()?.own_three()?;
+    let record = record_of(rtype_, record_)?;
+    let values = record.values.borrow();
+
+    Ok(values[index.as_int()? as usize].clone())
+}
+
+fn record_set_em(args: Args) -> SResult<SExpr> {
+    let (rtype_, record_, index, value) = args.evaled()?.own_four()?;
+    let record = record_of(rtype_, record_)?;
+    record.values.borrow_mut()[index.as_int()? as usize] = value;
+
+    Ok(SExpr::Unspecified)
+}
+
+//
+// Helpers
+//
+fn record_of(rtype_: SExpr, record_: SExpr) -> SResult<RecordData> {
+    let rtype = rtype_.into_record_type()?;
+    match record_ {
+        SExpr::Record(x) => if x.is_a(&rtype) {
+            Ok(x)
+        } else {
+            bail!(TypeMismatch => rtype.name(), SExpr::Record(x))
+        },
+        x => bail!(TypeMismatch => rtype.name(), x)
+    }
+}
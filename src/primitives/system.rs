@@ -3,7 +3,7 @@ use std::fs::{remove_file, read_to_string};
 use std::env;
 use std::process::Command;
 
-use lexer::tokenize;
+use lexer::{tokenize, Source};
 use parser::{parse, SExpr};
 use evaluator::Args;
 use port::current_output_port;
@@ -35,17 +35,18 @@ pub fn get_environment_variables(_args: Args) -> SResult<SExpr> {
         .map(|(key, val)| sdottedlist![sstr!(key); sstr!(val)])
         .collect();
 
-    Ok(SExpr::List(vars))
+    Ok(SExpr::list(vars))
 }
 
 pub fn load(args: Args) -> SResult<SExpr> {
     let env = args.env();
-    let scm = read_to_string(get_path_from_args(args)?)?;
+    let path = get_path_from_args(args)?;
+    let scm = read_to_string(&path)?;
 
-    for sexpr in parse(tokenize(&mut scm.chars().peekable()))? {
-        let result = sexpr.eval(&env)?;
+    for (sexpr, span) in parse(tokenize(&Source::new(&path, &scm))?)? {
+        let result = sexpr.eval_at(&span, &env)?;
         if !result.is_unspecified() {
-            current_output_port().write_string(&format!("{}\n", result))?;
+            current_output_port()?.write_string(&format!("{}\n", result))?;
         }
     }
 
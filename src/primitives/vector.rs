@@ -0,0 +1,191 @@
+use parser::{SExpr, SExprs};
+use evaluator::Args;
+use serr::{SErr, SResult};
+use utils::new_rc_ref_cell;
+
+fn new_vector(xs: SExprs) -> SExpr {
+    SExpr::Vector(new_rc_ref_cell(xs))
+}
+
+pub fn vector(args: Args) -> SResult<SExpr> {
+    Ok(new_vector(args.evaled()?.into_iter().collect()))
+}
+
+pub fn make_vector(args: Args) -> SResult<SExpr> {
+    let mut iter = args.evaled()?.into_iter();
+    let len = iter.next()
+        .ok_or_else(|| SErr::WrongArgCount(1, 0))?
+        .into_int()?;
+    let fill = iter.next()
+        .unwrap_or(SExpr::Unspecified);
+
+    if len < 0 {
+        bail!(TypeMismatch => "non-negative integer", sint!(len))
+    }
+
+    Ok(new_vector(vec![fill; len as usize]))
+}
+
+pub fn vector_length(args: Args) -> SResult<SExpr> {
+    let vector = args.evaled()?.own_one()?.as_vector()?;
+    let len = vector.borrow().len();
+    Ok(len.into())
+}
+
+pub fn vector_ref(args: Args) -> SResult<SExpr> {
+    let (vector_, k_) = args.evaled()?.own_two()?;
+    let vector = vector_.as_vector()?;
+    let k = index(k_)?;
+    let xs = vector.borrow();
+
+    xs.get(k)
+        .cloned()
+        .ok_or_else(|| SErr::IndexOutOfBounds(xs.len(), k))
+}
+
+pub fn vector_set_em(args: Args) -> SResult<SExpr> {
+    let (vector_, k_, obj) = args.evaled()?.own_three()?;
+    let vector = vector_.as_vector()?;
+    let k = index(k_)?;
+    let mut xs = vector.borrow_mut();
+    let len = xs.len();
+
+    *xs.get_mut(k).ok_or_else(|| SErr::IndexOutOfBounds(len, k))? = obj;
+    Ok(SExpr::Unspecified)
+}
+
+pub fn vector_to_list(args: Args) -> SResult<SExpr> {
+    let (vector, start, end) = vector_range(args)?;
+    Ok(SExpr::list(vector[start..end].to_vec()))
+}
+
+pub fn list_to_vector(args: Args) -> SResult<SExpr> {
+    let list = args.evaled()?.own_one()?.into_list()?;
+    Ok(new_vector(list))
+}
+
+pub fn vector_to_string(args: Args) -> SResult<SExpr> {
+    let (vector, start, end) = vector_range(args)?;
+    let string = vector[start..end].iter()
+        .map(|x| x.clone().into_chr())
+        .collect::<SResult<String>>()?;
+
+    Ok(sstr!(string))
+}
+
+pub fn string_to_vector(args: Args) -> SResult<SExpr> {
+    let (string_, rest) = args.evaled()?.own_one_rest()?;
+    let chars = string_.into_str()?
+        .chars()
+        .map(|c| schr!(c))
+        .collect::<SExprs>();
+    let (start, end) = bounds(chars.len(), rest)?;
+
+    Ok(new_vector(chars[start..end].to_vec()))
+}
+
+pub fn vector_copy(args: Args) -> SResult<SExpr> {
+    let (vector, start, end) = vector_range(args)?;
+    Ok(new_vector(vector[start..end].to_vec()))
+}
+
+/// `(vector-copy! to at from [start [end]])`
+pub fn vector_copy_em(args: Args) -> SResult<SExpr> {
+    let mut iter = args.evaled()?.into_iter();
+    let to = iter.next()
+        .ok_or_else(|| SErr::WrongArgCount(3, 0))?
+        .as_vector()?;
+    let at = index(iter.next().ok_or_else(|| SErr::WrongArgCount(3, 1))?)?;
+    let from = iter.next()
+        .ok_or_else(|| SErr::WrongArgCount(3, 2))?
+        .as_vector()?;
+
+    // `to` and `from` may be the same vector, so the elements are copied
+    // out before `to` is borrowed mutably.
+    let xs = {
+        let from = from.borrow();
+        let (start, end) = bounds(from.len(), iter.collect())?;
+        from[start..end].to_vec()
+    };
+
+    let mut to = to.borrow_mut();
+    if at > to.len() || to.len() - at < xs.len() {
+        bail!(IndexOutOfBounds => to.len(), at + xs.len())
+    }
+
+    for (i, x) in xs.into_iter().enumerate() {
+        to[at + i] = x;
+    }
+
+    Ok(SExpr::Unspecified)
+}
+
+pub fn vector_append(args: Args) -> SResult<SExpr> {
+    let mut result = vec![];
+    for x in args.evaled()?.into_iter() {
+        result.extend(x.as_vector()?.borrow().iter().cloned());
+    }
+
+    Ok(new_vector(result))
+}
+
+/// `(vector-fill! vector fill [start [end]])`
+pub fn vector_fill_em(args: Args) -> SResult<SExpr> {
+    let mut iter = args.evaled()?.into_iter();
+    let vector = iter.next()
+        .ok_or_else(|| SErr::WrongArgCount(2, 0))?
+        .as_vector()?;
+    let fill = iter.next()
+        .ok_or_else(|| SErr::WrongArgCount(2, 1))?;
+
+    let mut xs = vector.borrow_mut();
+    let (start, end) = bounds(xs.len(), iter.collect())?;
+    for x in &mut xs[start..end] {
+        *x = fill.clone();
+    }
+
+    Ok(SExpr::Unspecified)
+}
+
+//
+// Helpers
+//
+pub fn index(k: SExpr) -> SResult<usize> {
+    let k = k.into_int()?;
+    if k < 0 {
+        bail!(TypeMismatch => "non-negative integer", sint!(k))
+    }
+
+    Ok(k as usize)
+}
+
+/// Parses the optional `start` and `end` arguments of a sequence with
+/// length `len`.
+pub fn bounds(len: usize, range: SExprs) -> SResult<(usize, usize)> {
+    if range.len() > 2 {
+        bail!(WrongArgCount => 2 as usize, range.len())
+    }
+
+    let mut iter = range.into_iter();
+    let start = iter.next().map_or(Ok(0), index)?;
+    let end = iter.next().map_or(Ok(len), index)?;
+
+    if end > len {
+        bail!(IndexOutOfBounds => len, end)
+    } else if start > end {
+        bail!(IndexOutOfBounds => end, start)
+    }
+
+    Ok((start, end))
+}
+
+/// Returns the elements of a `(f vector [start [end]])` call alongside
+/// the range.
+fn vector_range(args: Args) -> SResult<(SExprs, usize, usize)> {
+    let (vector_, rest) = args.evaled()?.own_one_rest()?;
+    let vector = vector_.as_vector()?;
+    let xs = vector.borrow().clone();
+    let (start, end) = bounds(xs.len(), rest)?;
+
+    Ok((xs, start, end))
+}
@@ -1,30 +1,115 @@
+use std::ptr;
+use std::rc::Rc;
+
 use env::Env;
 use env::EnvRef;
 use lexer::Token;
 use parser::SExpr;
 use parser::SExprs;
-use evaluator::Args;
+use evaluator::{Args, State, ContRef, Winders, Handlers};
+use expander::base_name;
 use serr::{SErr, SResult};
+use utils::{new_rc_ref_cell, RcRefCell};
 
 type PrimitiveProcedure = fn(Args) -> SResult<SExpr>;
+type ControlProcedure = fn(Args, ContRef) -> SResult<State>;
 
-/// A `Procedure` may be either primitive or compound(user-defined).
+/// A `Procedure` may be either primitive, compound(user-defined),
+/// a continuation captured by `call/cc` or a parameter object.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ProcedureData {
     Primitive(PrimitiveData),
-    Compound(CompoundData)
+    Compound(CompoundData),
+    Continuation(ContinuationData),
+    Parameter(ParameterData)
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct PrimitiveData {
-    fun: PrimitiveProcedure,
+#[derive(Debug, Clone)]
+pub enum PrimitiveData {
+    /// Gets its arguments evaluated.
+    Procedure(PrimitiveProcedure),
+    /// Gets its arguments as they are, like `quote` or `lambda`.
+    Syntax(PrimitiveProcedure),
+    /// Gets its arguments as they are alongside the current continuation,
+    /// like `cond` or `let`, so it can evaluate them in tail position.
+    SpecialForm(ControlProcedure),
+    /// Gets its arguments evaluated alongside the current continuation
+    /// and decides what the evaluator does next, like `call/cc`.
+    Control(ControlProcedure),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct CompoundData {
-    params: Param,
-    pub body: Box<SExpr>,
-    env: EnvRef
+    params: Rc<Param>,
+    pub body: Rc<SExpr>,
+    env: EnvRef,
+    /// The name the procedure is first defined with, for backtraces.
+    pub name: Option<Rc<String>>
+}
+
+#[derive(Debug, Clone)]
+pub struct ContinuationData {
+    pub k: ContRef,
+    pub winders: Winders,
+    pub handlers: Handlers,
+    /// Id of the evaluation that captured the continuation.
+    pub run: usize
+}
+
+impl PartialEq for ContinuationData {
+    fn eq(&self, other: &ContinuationData) -> bool {
+        Rc::ptr_eq(&self.k, &other.k)
+    }
+}
+
+/// A parameter object, calling it without arguments gives its value.
+/// `parameterize` changes the value for the extent of its body.
+#[derive(Debug, Clone)]
+pub struct ParameterData {
+    pub value: RcRefCell<SExpr>,
+    /// Applied to the values the parameter is bound to with `parameterize`,
+    /// it may check or convert them.
+    pub converter: Option<Rc<SExpr>>
+}
+
+impl PrimitiveData {
+    /// The address of the function, the copies of a primitive share it.
+    pub fn id(&self) -> usize {
+        match *self {
+            PrimitiveData::Procedure(x) | PrimitiveData::Syntax(x) => x as usize,
+            PrimitiveData::SpecialForm(x) | PrimitiveData::Control(x) => x as usize,
+        }
+    }
+}
+
+impl PartialEq for PrimitiveData {
+    /// Primitives are the same if they're copies of the same function
+    /// pointer, e.g. both are read from the same binding. The same function
+    /// isn't guaranteed to have one address otherwise.
+    fn eq(&self, other: &PrimitiveData) -> bool {
+        use self::PrimitiveData::*;
+        match (self, other) {
+            (Procedure(x), Procedure(y)) | (Syntax(x), Syntax(y)) => ptr::fn_addr_eq(*x, *y),
+            (SpecialForm(x), SpecialForm(y)) | (Control(x), Control(y)) => ptr::fn_addr_eq(*x, *y),
+            _ => false
+        }
+    }
+}
+
+impl PartialEq for ParameterData {
+    fn eq(&self, other: &ParameterData) -> bool {
+        Rc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl ParameterData {
+    pub fn new(value: SExpr, converter: Option<SExpr>) -> ParameterData {
+        ParameterData { value: new_rc_ref_cell(value), converter: converter.map(Rc::new) }
+    }
+
+    pub fn get(&self) -> SExpr {
+        self.value.borrow().clone()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -37,104 +122,133 @@ pub enum Param {
 impl ProcedureData {
     /// Creates user defined procedure,
     /// a `SExpr::Procedure(ProcedureData::Compound)`.
-    pub fn new_compound(params_expr: SExpr, mut body: SExprs, env: &EnvRef) -> SResult<SExpr> {
-        let params = match params_expr {
-            SExpr::Atom(Token::Symbol(x)) => {
-                Param::Single(x)
-            },
-            SExpr::List(xs) => {
-                let names = xs.into_iter()
-                    .map(|x| x.into_symbol())
-                    .collect::<SResult<_>>()?;
-
-                Param::Fixed(names)
-            },
-            SExpr::DottedList(xs, y) => {
-                let names = xs.into_iter()
-                    .map(|x| x.into_symbol())
-                    .collect::<SResult<_>>()?;
-
-                // FIXME: what if its an another list or dotted list?
-                let rest = y.into_symbol()?;
-                Param::Multi(names, rest)
-            },
-            x => bail!(TypeMismatch => "parameter list", x)
-        };
-
+    pub fn new_compound(params_expr: SExpr, body: SExprs, env: &EnvRef) -> SResult<SExpr> {
+        Ok(ProcedureData::new_compound_with(Param::new(params_expr)?, body, env))
+    }
 
+    /// Like `new_compound` with a parameter list that is parsed already.
+    pub fn new_compound_with(params: Param, mut body: SExprs, env: &EnvRef) -> SExpr {
         // Wrap body in begin: (begin body)
         let body_expr = if body.len() == 1 {
             body.into_iter().next().unwrap()
         } else {
             let mut body_vec = vec![ssymbol!("begin")];
             body_vec.append(&mut body);
-            SExpr::List(body_vec)
+            SExpr::list(body_vec)
         };
 
 
-        let proc = SExpr::Procedure(ProcedureData::Compound(CompoundData {
-            params,
-            body: Box::new(body_expr),
-            env: env.clone_ref()
-        }));
-
-        Ok(proc)
+        SExpr::Procedure(ProcedureData::Compound(CompoundData {
+            params: Rc::new(params),
+            body: Rc::new(body_expr),
+            env: env.clone_ref(),
+            name: None
+        }))
     }
 
     /// Creates a primitive function,
     /// a `SExpr::Procedure(ProcedureData::Primitive)`
     pub fn new_primitive(fun: PrimitiveProcedure) -> SExpr {
-        SExpr::Procedure(ProcedureData::Primitive(PrimitiveData { fun }))
+        SExpr::Procedure(ProcedureData::Primitive(PrimitiveData::Procedure(fun)))
     }
 
-    pub fn apply(&self, args: Args) -> SResult<SExpr> {
-        match self {
-            ProcedureData::Primitive(x) => x.apply(args),
-            ProcedureData::Compound(x) => x.apply(args),
-        }
+    /// Creates a primitive syntax, a primitive function that gets
+    /// its arguments unevaluated.
+    pub fn new_syntax(fun: PrimitiveProcedure) -> SExpr {
+        SExpr::Procedure(ProcedureData::Primitive(PrimitiveData::Syntax(fun)))
+    }
+
+    /// Creates a primitive syntax that has access to the continuation.
+    pub fn new_special_form(fun: ControlProcedure) -> SExpr {
+        SExpr::Procedure(ProcedureData::Primitive(PrimitiveData::SpecialForm(fun)))
+    }
+
+    pub fn new_parameter(parameter: ParameterData) -> SExpr {
+        SExpr::Procedure(ProcedureData::Parameter(parameter))
+    }
+
+    /// Creates a primitive function that has access to the continuation.
+    pub fn new_control(fun: ControlProcedure) -> SExpr {
+        SExpr::Procedure(ProcedureData::Primitive(PrimitiveData::Control(fun)))
     }
 }
 
 impl CompoundData {
-    pub fn build_env(&self, args: Args) -> SResult<EnvRef> {
-        let mut inner_env = Env::new(self.env.clone_ref());
-        match self.params {
+    /// Names the procedure in `value` if it is a compound procedure without
+    /// a name, e.g. `(define f (lambda ...))` names the procedure `f`.
+    pub fn name_procedure(value: SExpr, name: &str) -> SExpr {
+        match value {
+            SExpr::Procedure(ProcedureData::Compound(ref x)) if x.name.is_none() => {
+                let mut named = x.clone();
+                named.name = Some(Rc::new(base_name(name).to_string()));
+                SExpr::Procedure(ProcedureData::Compound(named))
+            },
+            x => x
+        }
+    }
+
+    pub fn build_env(&self, args: SExprs) -> SResult<EnvRef> {
+        let inner_env = Env::new(self.env.clone_ref()).into_ref();
+        self.params.bind(args, &inner_env)?;
+        Ok(inner_env)
+    }
+}
+
+impl Param {
+    /// Parses a parameter list: `x`, `(x y)` or `(x y . z)`.
+    pub fn new(params_expr: SExpr) -> SResult<Param> {
+        let params = match params_expr {
+            SExpr::Atom(Token::Symbol(x)) => {
+                Param::Single(x.to_string())
+            },
+            x@SExpr::Nil | x@SExpr::Pair(_) => {
+                let (xs, y) = x.list_parts()?;
+                let names = xs.into_iter()
+                    .map(|x| x.into_symbol())
+                    .collect::<SResult<_>>()?;
+
+                if y.is_nil() {
+                    Param::Fixed(names)
+                } else {
+                    Param::Multi(names, y.into_symbol()?)
+                }
+            },
+            x => bail!(TypeMismatch => "parameter list", x)
+        };
+
+        Ok(params)
+    }
+
+    /// Defines the parameters in `env` with the values in `args`.
+    pub fn bind(&self, args: SExprs, env: &EnvRef) -> SResult<()> {
+        match self {
             Param::Single(ref x) => {
-                inner_env.define(x.to_string(), SExpr::List(args.eval()?));
+                env.define(x.to_string(), SExpr::list(args));
             },
             Param::Fixed(ref xs) => {
                 if xs.len() != args.len() {
                     bail!(WrongArgCount => xs.len(), args.len())
                 }
-                inner_env.pack(xs.as_slice(), args.eval()?);
+
+                for (name, arg) in xs.iter().zip(args.into_iter()) {
+                    env.define(name.clone(), arg);
+                }
             },
             Param::Multi(ref xs, ref y) => {
                 if args.len() < xs.len() {
                     bail!(WrongArgCount => xs.len(), args.len())
                 }
 
-                let mut evaled_args = args.eval()?.into_iter();
+                let mut evaled_args = args.into_iter();
                 for name in xs {
-                    inner_env.define(name.clone(), evaled_args.next().unwrap());
+                    env.define(name.clone(), evaled_args.next().unwrap());
                 }
 
-                let rest = evaled_args.take_while(|_| true).collect::<SExprs>();
-                inner_env.define(y.clone(), SExpr::List(rest));
+                let rest = evaled_args.collect::<SExprs>();
+                env.define(y.clone(), SExpr::list(rest));
             }
         };
 
-        Ok(inner_env.into_ref())
-    }
-
-    pub fn apply(&self, args: Args) -> SResult<SExpr> {
-        let inner_env = self.build_env(args)?;
-        self.body.eval(&inner_env)
-    }
-}
-
-
-impl PrimitiveData {
-    pub fn apply(&self, args: Args) -> SResult<SExpr> {
-        (self.fun)(args)
+        Ok(())
     }
 }
@@ -0,0 +1,61 @@
+use std::rc::Rc;
+
+use parser::SExprs;
+use utils::{new_rc_ref_cell, RcRefCell};
+
+/// A record type created by `define-record-type`.
+#[derive(Debug, Clone)]
+pub struct RecordTypeData(pub Rc<RecordType>);
+
+#[derive(Debug)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+/// An instance of a record type.
+#[derive(Debug, Clone)]
+pub struct RecordData {
+    pub rtype: RecordTypeData,
+    pub values: RcRefCell<SExprs>,
+}
+
+impl RecordTypeData {
+    pub fn new(name: String, fields: Vec<String>) -> RecordTypeData {
+        RecordTypeData(Rc::new(RecordType { name, fields }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// A number that identifies the type as long as it is alive.
+    pub fn id(&self) -> usize {
+        &*self.0 as *const _ as usize
+    }
+}
+
+impl PartialEq for RecordTypeData {
+    /// Every `define-record-type` creates a new type, even if the
+    /// definitions are the same.
+    fn eq(&self, other: &RecordTypeData) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl RecordData {
+    pub fn new(rtype: RecordTypeData, values: SExprs) -> RecordData {
+        RecordData { rtype, values: new_rc_ref_cell(values) }
+    }
+
+    pub fn is_a(&self, rtype: &RecordTypeData) -> bool {
+        self.rtype == *rtype
+    }
+}
+
+impl PartialEq for RecordData {
+    /// `equal?` compares records like `eqv?` does.
+    fn eq(&self, other: &RecordData) -> bool {
+        Rc::ptr_eq(&self.values, &other.values)
+    }
+}
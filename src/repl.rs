@@ -14,13 +14,13 @@ pub fn run(env: &EnvRef) {
         io::stdout().flush().unwrap();
         io::stdin().read_line(&mut line).unwrap();
 
-        let tokens = lexer::tokenize(&mut line.chars().peekable());
-        let sexprs = parser::parse(tokens);
+        let tokens = lexer::tokenize(&lexer::Source::new("repl", &line));
+        let sexprs = tokens.and_then(parser::parse);
 
         match sexprs {
             Ok(sexprs) => {
-                for sexpr in sexprs {
-                    let evaluated = sexpr.eval(env);
+                for (sexpr, span) in sexprs {
+                    let evaluated = sexpr.eval_at(&span, env);
 
                     match evaluated {
                         Ok(evaluated) => {
@@ -2,9 +2,11 @@ use std::fmt;
 use std::error::Error;
 use std::io;
 use std::env;
+use std::rc::Rc;
 
-use lexer::Token;
+use lexer::{Token, Span};
 use parser::SExpr;
+use procedure::ContinuationData;
 
 pub type SResult<T> = Result<T, SErr>;
 
@@ -15,8 +17,9 @@ pub enum SErr {
     EnvNotFound,
     DivisionByZero,
     UnexpectedForm(SExpr),
-    UnexpectedToken(Token),
-    NotExpectedToken(Token, Token),
+    /// The source can't be read: where, what is expected and what is found.
+    /// Where the error is, if it is read from the source code.
+    ReadError(Option<Span>, String, String),
     Cast(String, SExpr),
     UnboundVar(String),
     NotAProcedure(SExpr),
@@ -26,6 +29,22 @@ pub enum SErr {
     WrongPort(/*proc: */String, /*port: */String),
     //TODO: what about Trace(String, Box<SErr>)
 
+    /// Created by `error`: a message and irritants.
+    Error(String, Vec<SExpr>),
+    /// An object raised by `raise` that is not handled.
+    Raised(SExpr),
+
+    /// Not really an error, used for jumping to a continuation that
+    /// belongs to an evaluation further down the stack.
+    Escape(ContinuationData, SExpr),
+
+    /// An error that is not handled, with the location of the form that
+    /// was being evaluated when it happened.
+    Located(Span, Box<SErr>),
+    /// An error that is not handled, with the procedure calls that were
+    /// being evaluated when it happened.
+    Traced(Vec<String>, Box<SErr>),
+
     // Converted errors
     IOErr(io::Error),
     VarErr(env::VarError)
@@ -35,12 +54,21 @@ impl fmt::Display for SErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let output = match self {
             SErr::Generic(x) => x.to_string(),
+            SErr::Error(x, irritants) => {
+                let mut output = x.to_string();
+                for irritant in irritants {
+                    output.push_str(&format!(" {}", irritant));
+                }
+                output
+            },
+            SErr::Raised(SExpr::Condition(x)) => x.0.to_string(),
+            SErr::Raised(x) => format!("Uncaught exception: {}", x),
             SErr::FoundNothing => "Expected some expression or token, found nothing.".to_string(),
             SErr::EnvNotFound => "Environment not found. (Probably an unbound variable)".to_string(),
             SErr::DivisionByZero => "Division by zero".to_string(),
             SErr::UnexpectedForm(x) => format!("Expression is in unexpected form: {}", x),
-            SErr::UnexpectedToken(x) => format!("Not expected this token: {}", x),
-            SErr::NotExpectedToken(x, y) => format!("Expected one of {}, found {}", x, y),
+            SErr::ReadError(Some(span), x, y) => format!("{}: Expected {}, found {}{}", span, x, y, source_line(span)),
+            SErr::ReadError(None, x, y) => format!("Expected {}, found {}", x, y),
             SErr::Cast(typ, x) => format!("Can't convert {} to {}", x, typ),
             SErr::UnboundVar(x) => format!("Unbound variable: {}", x),
             SErr::NotAProcedure(x) => format!("Wrong type to apply, not a procedure: {}", x),
@@ -48,6 +76,15 @@ impl fmt::Display for SErr {
             SErr::IndexOutOfBounds(x, y) => format!("Index out of bounds. Max size: {}, requested: {}", x, y),
             SErr::TypeMismatch(x, y) => format!("Expected a {}, found this: {}", x, y),
             SErr::WrongPort(x, y) => format!("Can't apply function `{}` to a port type of {}", x, y),
+            SErr::Escape(_, x) => format!("Continuation escaped with: {}", x),
+            SErr::Located(span, x) => format!("{}: {}{}", span, x, source_line(span)),
+            SErr::Traced(calls, x) => {
+                let mut output = format!("{}\nBacktrace (innermost first):", x);
+                for call in calls {
+                    output.push_str(&format!("\n  {}", call));
+                }
+                output
+            },
             SErr::IOErr(x) => x.to_string(),
             SErr::VarErr(x) => x.to_string()
         };
@@ -56,16 +93,33 @@ impl fmt::Display for SErr {
     }
 }
 
+/// The line that `span` points to with a marker under the column, if the
+/// source is known.
+fn source_line(span: &Span) -> String {
+    match span.source_line() {
+        Some(line) => {
+            // Keep the tabs so that the marker lines up
+            let indent: String = line.chars()
+                .take(span.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            format!("\n    {}\n    {}^", line, indent)
+        },
+        None => String::new()
+    }
+}
+
 impl Error for SErr {
     fn description(&self) -> &str {
         match self {
             SErr::Generic(_) => "An error.",
+            SErr::Error(_, _) => "An error.",
+            SErr::Raised(_) => "Uncaught exception.",
             SErr::FoundNothing => "Expected some expression or token, found nothing.",
             SErr::EnvNotFound => "Environment not found. (Probably an unbound variable)",
             SErr::DivisionByZero => "Division by zero",
             SErr::UnexpectedForm(_) => "Expression is in unexpected form.",
-            SErr::UnexpectedToken(_) => "Unexpected token.",
-            SErr::NotExpectedToken(_, _) => "Unexpected token.",
+            SErr::ReadError(_, _, _) => "Read error.",
             SErr::Cast(_, _) => "Failed conversion.",
             SErr::UnboundVar(_) => "Unbound variable.",
             SErr::NotAProcedure(_) => "Not a procedure.",
@@ -73,6 +127,9 @@ impl Error for SErr {
             SErr::IndexOutOfBounds(_, _) => "Index out of bounds.",
             SErr::TypeMismatch(_, _) => "Type mismatch.",
             SErr::WrongPort(_, _) => "Wrong type of port.",
+            SErr::Escape(_, _) => "Continuation escape.",
+            SErr::Located(_, x) => x.description(),
+            SErr::Traced(_, x) => x.description(),
             SErr::IOErr(x) => x.description(),
             SErr::VarErr(x) => x.description()
         }
@@ -80,12 +137,91 @@ impl Error for SErr {
 }
 
 impl SErr {
-    pub fn new_generic(s: &str) -> SErr {
-        SErr::Generic(s.to_string())
+    /// The message of the error, as seen by `error-object-message`.
+    pub fn message(&self) -> String {
+        match self {
+            SErr::Error(x, _) => x.to_string(),
+            SErr::ReadError(_, x, y) => format!("Expected {}, found {}", x, y),
+            SErr::UnexpectedForm(_) => "Expression is in unexpected form".to_string(),
+            SErr::Cast(typ, _) => format!("Can't convert to {}", typ),
+            SErr::UnboundVar(_) => "Unbound variable".to_string(),
+            SErr::NotAProcedure(_) => "Wrong type to apply, not a procedure".to_string(),
+            SErr::WrongArgCount(_, _) => "Wrong arg count; expected, found".to_string(),
+            SErr::IndexOutOfBounds(_, _) => "Index out of bounds. Max size, requested".to_string(),
+            SErr::TypeMismatch(x, _) => format!("Expected a {}", x),
+            x => x.to_string()
+        }
+    }
+
+    /// The objects related to the error, as seen by `error-object-irritants`.
+    pub fn irritants(&self) -> Vec<SExpr> {
+        match self {
+            SErr::Error(_, xs) => xs.clone(),
+            SErr::UnexpectedForm(x) => vec![x.clone()],
+            SErr::Cast(_, x) => vec![x.clone()],
+            SErr::UnboundVar(x) => vec![SExpr::Atom(Token::Symbol(x.as_str().into()))],
+            SErr::NotAProcedure(x) => vec![x.clone()],
+            SErr::WrongArgCount(x, y) => vec![SExpr::from(*x), SExpr::from(*y)],
+            SErr::IndexOutOfBounds(x, y) => vec![SExpr::from(*x), SExpr::from(*y)],
+            SErr::TypeMismatch(_, x) => vec![x.clone()],
+            _ => vec![]
+        }
     }
 
-    pub fn new_unbound_var(s: &str) -> SErr {
-        SErr::UnboundVar(s.to_string())
+    pub fn is_file_error(&self) -> bool {
+        match self {
+            SErr::IOErr(_) => true,
+            _ => false
+        }
+    }
+
+    pub fn is_read_error(&self) -> bool {
+        match self {
+            SErr::ReadError(_, _, _) => true,
+            _ => false
+        }
+    }
+
+    /// Attaches the location to the error, unless it already has one.
+    pub fn located(self, span: Option<Span>) -> SErr {
+        match (self, span) {
+            (x, _) if x.is_annotated() => x,
+            (x, Some(span)) => SErr::Located(span, Box::new(x)),
+            (x, None) => x
+        }
+    }
+
+    /// Attaches the backtrace to the error, unless it already has one.
+    pub fn traced(self, calls: Vec<String>) -> SErr {
+        match self {
+            SErr::Traced(_, _) | SErr::Escape(_, _) => self,
+            x => if calls.is_empty() {
+                x
+            } else {
+                SErr::Traced(calls, Box::new(x))
+            }
+        }
+    }
+
+    fn is_annotated(&self) -> bool {
+        match self {
+            SErr::Located(_, _) | SErr::Traced(_, _) | SErr::Escape(_, _) => true,
+            _ => false
+        }
+    }
+
+    /// Converts the error into the object that gets passed to
+    /// exception handlers.
+    pub fn into_condition(self) -> SExpr {
+        match self {
+            SErr::Raised(x) => x,
+            SErr::Located(_, x) | SErr::Traced(_, x) => x.into_condition(),
+            x => SExpr::Condition(ConditionData(Rc::new(x)))
+        }
+    }
+
+    pub fn new_generic(s: &str) -> SErr {
+        SErr::Generic(s.to_string())
     }
 
     pub fn new_unexpected_form(x: &SExpr) -> SErr {
@@ -101,6 +237,16 @@ impl SErr {
     }
 }
 
+/// An error as a Scheme object.
+#[derive(Debug, Clone)]
+pub struct ConditionData(pub Rc<SErr>);
+
+impl PartialEq for ConditionData {
+    fn eq(&self, other: &ConditionData) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
 impl From<io::Error> for SErr {
     fn from(error: io::Error) -> Self {
         SErr::IOErr(error)
@@ -0,0 +1,491 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Sub, Mul, Div, Rem, Neg};
+
+/// An arbitrary-precision integer. The magnitude is stored as base 2^32
+/// digits, least significant first, without leading zeros. Zero has no
+/// digits and is never negative.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn zero() -> Self {
+        BigInt { negative: false, digits: vec![] }
+    }
+
+    fn new(negative: bool, mut digits: Vec<u32>) -> Self {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+
+        BigInt { negative: negative && !digits.is_empty(), digits }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn abs(&self) -> Self {
+        BigInt { negative: false, digits: self.digits.clone() }
+    }
+
+    /// Returns the number as an `i64` if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None
+        }
+
+        let magnitude = self.digits.iter()
+            .rev()
+            .fold(0u64, |acc, &x| (acc << 32) | x as u64);
+
+        if self.negative {
+            if magnitude <= i64::MIN.unsigned_abs() {
+                Some((magnitude as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else if magnitude <= i64::MAX as u64 {
+            Some(magnitude as i64)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self.digits.iter()
+            .rev()
+            .fold(0.0, |acc, &x| acc * 4294967296.0 + x as f64);
+
+        if self.negative { -magnitude } else { magnitude }
+    }
+
+    /// Converts an integral float. Returns `None` for infinities and NaN.
+    pub fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() {
+            return None
+        }
+
+        let x = x.trunc();
+        if x.abs() < 9.2e18 {
+            return Some(BigInt::from(x as i64))
+        }
+
+        // x = mantissa * 2^exponent, exponent is positive here
+        let bits = x.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as usize - 1075;
+        let mantissa = (bits & 0xf_ffff_ffff_ffff) | 0x10_0000_0000_0000;
+        let magnitude = BigInt::from(mantissa as i64).shl(exponent);
+
+        Some(if x < 0.0 { -magnitude } else { magnitude })
+    }
+
+    /// Multiplies the number by 2^bits.
+    pub fn shl(&self, bits: usize) -> Self {
+        let mut digits = vec![0; bits / 32];
+        digits.extend(shl_digits(&self.digits, (bits % 32) as u32));
+
+        BigInt::new(self.negative, digits)
+    }
+
+    /// Number of bits needed to store the magnitude.
+    pub fn bits(&self) -> usize {
+        match self.digits.last() {
+            Some(x) => self.digits.len() * 32 - x.leading_zeros() as usize,
+            None => 0
+        }
+    }
+
+    /// The integer square root, the biggest `x` where `x * x <= self`. The
+    /// number must be non-negative.
+    pub fn sqrt(&self) -> Self {
+        if self.is_zero() {
+            return BigInt::zero()
+        }
+
+        // Newton's method, starting from a number that is bigger than
+        // the root
+        let mut x = BigInt::from(1).shl((self.bits() + 1) / 2);
+        loop {
+            let y = (&x + &(self / &x)).shr(1);
+            if y >= x {
+                return x
+            }
+            x = y;
+        }
+    }
+
+    /// Divides the magnitude by 2^bits.
+    pub fn shr(&self, bits: usize) -> Self {
+        if bits / 32 >= self.digits.len() {
+            return BigInt::zero()
+        }
+
+        let shift = (bits % 32) as u32;
+        let digits = &self.digits[bits / 32..];
+        let result = (0..digits.len())
+            .map(|i| {
+                let high = if shift == 0 { 0 } else { digits.get(i + 1).map_or(0, |x| x << (32 - shift)) };
+                (digits[i] >> shift) | high
+            })
+            .collect();
+
+        BigInt::new(self.negative, result)
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> Self {
+        let mut result = BigInt::from(1);
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent % 2 == 1 {
+                result = &result * &base;
+            }
+
+            exponent /= 2;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+
+        result
+    }
+
+    /// Truncating division, the remainder has the sign of the dividend.
+    pub fn div_rem(&self, other: &BigInt) -> (BigInt, BigInt) {
+        if other.is_zero() {
+            panic!("BigInt division by zero")
+        }
+
+        let (q, r) = divrem_digits(&self.digits, &other.digits);
+        (BigInt::new(self.negative != other.negative, q), BigInt::new(self.negative, r))
+    }
+
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let mut a = self.abs();
+        let mut b = other.abs();
+        while !b.is_zero() {
+            let r = a.div_rem(&b).1;
+            a = b;
+            b = r;
+        }
+
+        a
+    }
+
+    /// Parses an optionally signed string of digits in the given radix.
+    pub fn parse(value: &str, radix: u32) -> Option<Self> {
+        let (negative, body) = match value.chars().next()? {
+            '-' => (true, &value[1..]),
+            '+' => (false, &value[1..]),
+            _ => (false, value)
+        };
+
+        if body.is_empty() {
+            return None
+        }
+
+        let mut digits = vec![];
+        for c in body.chars() {
+            mul_add_small(&mut digits, radix, c.to_digit(radix)?);
+        }
+
+        Some(BigInt::new(negative, digits))
+    }
+
+    pub fn to_str_radix(&self, radix: u32) -> String {
+        let mut result = vec![];
+        let mut digits = self.digits.clone();
+        while !digits.is_empty() {
+            let (q, r) = divrem_small(&digits, radix);
+            result.push(::std::char::from_digit(r, radix).unwrap());
+            digits = q;
+        }
+
+        if result.is_empty() {
+            result.push('0');
+        }
+        if self.negative {
+            result.push('-');
+        }
+
+        result.iter().rev().collect()
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(x: i64) -> Self {
+        let magnitude = x.unsigned_abs();
+        BigInt::new(x < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_str_radix(10))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_digits(&self.digits, &other.digits),
+            (true, true) => cmp_digits(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Neg for &'a BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.digits.clone())
+    }
+}
+
+impl<'a, 'b> Add<&'b BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: &BigInt) -> BigInt {
+        if self.negative == rhs.negative {
+            return BigInt::new(self.negative, add_digits(&self.digits, &rhs.digits))
+        }
+
+        match cmp_digits(&self.digits, &rhs.digits) {
+            Ordering::Less => BigInt::new(rhs.negative, sub_digits(&rhs.digits, &self.digits)),
+            _ => BigInt::new(self.negative, sub_digits(&self.digits, &rhs.digits)),
+        }
+    }
+}
+
+impl<'a, 'b> Sub<&'b BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn sub(self, rhs: &BigInt) -> BigInt {
+        self + &-rhs
+    }
+}
+
+impl<'a, 'b> Mul<&'b BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: &BigInt) -> BigInt {
+        BigInt::new(self.negative != rhs.negative, mul_digits(&self.digits, &rhs.digits))
+    }
+}
+
+impl<'a, 'b> Div<&'b BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn div(self, rhs: &BigInt) -> BigInt {
+        self.div_rem(rhs).0
+    }
+}
+
+impl<'a, 'b> Rem<&'b BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn rem(self, rhs: &BigInt) -> BigInt {
+        self.div_rem(rhs).1
+    }
+}
+
+macro_rules! forward_owned_op(
+    ($tr: ident, $f: ident) => {
+        impl $tr for BigInt {
+            type Output = BigInt;
+
+            fn $f(self, rhs: BigInt) -> BigInt {
+                (&self).$f(&rhs)
+            }
+        }
+    }
+);
+
+forward_owned_op!(Add, add);
+forward_owned_op!(Sub, sub);
+forward_owned_op!(Mul, mul);
+forward_owned_op!(Div, div);
+forward_owned_op!(Rem, rem);
+
+impl Neg for BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        -&self
+    }
+}
+
+//
+// Operations on magnitudes
+//
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for i in 0..long.len() {
+        let sum = long[i] as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    result.push(carry as u32);
+
+    result
+}
+
+/// `a - b`, `a` must be greater than or equal to `b`.
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for i in 0..a.len() {
+        let diff = a[i] as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        result.push(diff as u32);
+        borrow = if diff < 0 { 1 } else { 0 };
+    }
+
+    result
+}
+
+fn mul_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let product = x as u64 * y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+
+    result
+}
+
+/// `digits = digits * m + a`
+fn mul_add_small(digits: &mut Vec<u32>, m: u32, a: u32) {
+    let mut carry = a as u64;
+    for x in digits.iter_mut() {
+        let product = *x as u64 * m as u64 + carry;
+        *x = product as u32;
+        carry = product >> 32;
+    }
+
+    if carry > 0 {
+        digits.push(carry as u32);
+    }
+}
+
+fn divrem_small(a: &[u32], b: u32) -> (Vec<u32>, u32) {
+    let mut q = vec![0u32; a.len()];
+    let mut r = 0u64;
+    for i in (0..a.len()).rev() {
+        let current = (r << 32) | a[i] as u64;
+        q[i] = (current / b as u64) as u32;
+        r = current % b as u64;
+    }
+
+    while q.last() == Some(&0) {
+        q.pop();
+    }
+
+    (q, r as u32)
+}
+
+/// Shifts left by less than 32 bits, the result has one more digit.
+fn shl_digits(a: &[u32], shift: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u32;
+    for &x in a {
+        result.push((x << shift) | carry);
+        carry = if shift == 0 { 0 } else { x >> (32 - shift) };
+    }
+    result.push(carry);
+
+    result
+}
+
+/// Long division, Knuth's algorithm D.
+fn divrem_digits(u: &[u32], v: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_digits(u, v) == Ordering::Less {
+        return (vec![], u.to_vec())
+    }
+
+    if v.len() == 1 {
+        let (q, r) = divrem_small(u, v[0]);
+        return (q, vec![r])
+    }
+
+    // Normalize so that the top digit of the divisor has its high bit set
+    let shift = v[v.len() - 1].leading_zeros();
+    let vn = &shl_digits(v, shift)[..v.len()];
+    let mut un = shl_digits(u, shift);
+    let n = v.len();
+    let m = u.len();
+    let mut q = vec![0u32; m - n + 1];
+
+    for j in (0..m - n + 1).rev() {
+        let numerator = ((un[j + n] as u64) << 32) | un[j + n - 1] as u64;
+        let mut qhat = numerator / vn[n - 1] as u64;
+        let mut rhat = numerator % vn[n - 1] as u64;
+        while qhat >> 32 != 0 || qhat * vn[n - 2] as u64 > ((rhat << 32) | un[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += vn[n - 1] as u64;
+            if rhat >> 32 != 0 {
+                break
+            }
+        }
+
+        // un[j..j+n+1] -= qhat * vn
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = qhat * vn[i] as u64 + carry;
+            carry = product >> 32;
+            let diff = un[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+            un[i + j] = diff as u32;
+            borrow = if diff < 0 { 1 } else { 0 };
+        }
+        let diff = un[j + n] as i64 - borrow - carry as i64;
+        un[j + n] = diff as u32;
+
+        // qhat was one too large, add the divisor back
+        if diff < 0 {
+            qhat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = un[i + j] as u64 + vn[i] as u64 + carry;
+                un[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            un[j + n] = un[j + n].wrapping_add(carry as u32);
+        }
+
+        q[j] = qhat as u32;
+    }
+
+    // Unnormalize the remainder
+    let r = (0..n)
+        .map(|i| if shift == 0 { un[i] } else { (un[i] >> shift) | (un[i + 1] << (32 - shift)) })
+        .collect();
+
+    (q, r)
+}
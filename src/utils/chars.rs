@@ -36,6 +36,18 @@ fn read_one_byte(reader: &mut Read) -> Option<io::Result<u8>> {
     }
 }
 
+/// Decodes the first character of `bytes`, `None` if they don't start
+/// with a complete one.
+pub fn first_char(bytes: &[u8]) -> Option<char> {
+    let width = utf8_char_width(*bytes.first()?);
+    if width == 0 || bytes.len() < width {
+        return None
+    }
+
+    str::from_utf8(&bytes[..width]).ok()
+        .and_then(|s| s.chars().next())
+}
+
 /// An iterator over the `char`s of a reader.
 /// (A modified version of deprecated struct from here:
 /// https://doc.rust-lang.org/1.26.0/src/std/io/mod.rs.html#2005)
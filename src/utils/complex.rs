@@ -0,0 +1,134 @@
+use std::f64::consts::FRAC_PI_2;
+use std::ops::{Add, Sub, Mul, Div, Neg};
+
+/// An inexact complex number. Exact parts are converted to floats, like
+/// most Schemes do.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    pub fn from_polar(magnitude: f64, angle: f64) -> Self {
+        Complex::new(magnitude * angle.cos(), magnitude * angle.sin())
+    }
+
+    pub fn magnitude(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn angle(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn exp(self) -> Self {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    /// The principal value of the natural logarithm.
+    pub fn ln(self) -> Self {
+        Complex::new(self.magnitude().ln(), self.angle())
+    }
+
+    /// The logarithm in the given base.
+    pub fn log(self, base: Complex) -> Self {
+        self.ln() / base.ln()
+    }
+
+    /// The principal square root, its real part is never negative.
+    pub fn sqrt(self) -> Self {
+        let m = self.magnitude();
+        Complex::new(((m + self.re) / 2.0).sqrt(), ((m - self.re) / 2.0).sqrt().copysign(self.im))
+    }
+
+    /// `self` raised to the power `w`, `exp(w * ln(self))`.
+    pub fn powc(self, w: Complex) -> Self {
+        if self.re == 0.0 && self.im == 0.0 {
+            return self
+        }
+
+        (w * self.ln()).exp()
+    }
+
+    pub fn sin(self) -> Self {
+        Complex::new(self.re.sin() * self.im.cosh(), self.re.cos() * self.im.sinh())
+    }
+
+    pub fn cos(self) -> Self {
+        Complex::new(self.re.cos() * self.im.cosh(), -self.re.sin() * self.im.sinh())
+    }
+
+    pub fn tan(self) -> Self {
+        self.sin() / self.cos()
+    }
+
+    /// `-i ln(iz + sqrt(1 - z^2))`
+    pub fn asin(self) -> Self {
+        let i = Complex::new(0.0, 1.0);
+        let one = Complex::new(1.0, 0.0);
+        -i * (i * self + (one - self * self).sqrt()).ln()
+    }
+
+    pub fn acos(self) -> Self {
+        Complex::new(FRAC_PI_2, 0.0) - self.asin()
+    }
+
+    /// `(ln(1 + iz) - ln(1 - iz)) / 2i`
+    pub fn atan(self) -> Self {
+        let i = Complex::new(0.0, 1.0);
+        let one = Complex::new(1.0, 0.0);
+        ((one + i * self).ln() - (one - i * self).ln()) / Complex::new(0.0, 2.0)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Complex::new(self.re * rhs.re - self.im * rhs.im, self.re * rhs.im + self.im * rhs.re)
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        let d = rhs.re * rhs.re + rhs.im * rhs.im;
+        Complex::new((self.re * rhs.re + self.im * rhs.im) / d, (self.im * rhs.re - self.re * rhs.im) / d)
+    }
+}
+
+impl Neg for Complex {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(x: f64) -> Complex {
+        Complex::new(x, 0.0)
+    }
+}
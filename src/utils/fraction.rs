@@ -1,23 +1,21 @@
-use std::num::ParseIntError;
+use std::cmp::Ordering;
 use std::ops::{Add, Sub, Mul, Div};
-use std::str::FromStr;
-use std::f64;
 
-use utils::funcs::gcd;
+use utils::bigint::BigInt;
 
-#[derive(Debug, PartialOrd, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Fraction {
-    pub n: i64,
-    pub d: i64
+    pub n: BigInt,
+    pub d: BigInt
 }
 
 impl Fraction {
-    pub fn new(n: i64, d: i64) -> Self {
-        if d == 0 {
+    pub fn new(n: BigInt, d: BigInt) -> Self {
+        if d.is_zero() {
             panic!("Divide by zero dude!")
         }
 
-        if d < 0 {
+        if d.is_negative() {
             Self { n: -n, d: -d }.reduce()
         } else {
             Self { n, d }.reduce()
@@ -25,27 +23,75 @@ impl Fraction {
     }
 
     pub fn reduce(&self) -> Self {
-        let gcd = gcd(self.n.abs(), self.d.abs());
+        let gcd = self.n.gcd(&self.d);
         Self {
-            n: self.n / gcd,
-            d: self.d / gcd
+            n: &self.n / &gcd,
+            d: &self.d / &gcd
         }
     }
 
     pub fn is_int(&self) -> bool {
-        self.d == 1
+        self.d == BigInt::from(1)
+    }
+
+    /// The exact value of a float. Returns `None` for infinities and NaN.
+    pub fn from_f64(x: f64) -> Option<Fraction> {
+        if !x.is_finite() {
+            return None
+        }
+
+        // x = mantissa * 2^exponent
+        let bits = x.to_bits();
+        let biased_exponent = ((bits >> 52) & 0x7ff) as i64;
+        let fraction = (bits & 0xf_ffff_ffff_ffff) as i64;
+        let (mantissa, exponent) = if biased_exponent == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | 1 << 52, biased_exponent - 1075)
+        };
+        let mantissa = BigInt::from(if x < 0.0 { -mantissa } else { mantissa });
+
+        if exponent >= 0 {
+            Some(Fraction::from(mantissa.shl(exponent as usize)))
+        } else {
+            Some(Fraction::new(mantissa, BigInt::from(1).shl(-exponent as usize)))
+        }
     }
-}
 
-impl FromStr for Fraction {
-    type Err = ParseIntError;
+    /// The biggest integer that is not bigger than the fraction.
+    pub fn floor(&self) -> BigInt {
+        let (q, r) = self.n.div_rem(&self.d);
+        if r.is_negative() { &q - &BigInt::from(1) } else { q }
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut splitted = s.split('/');
-        let n = splitted.next().unwrap().parse::<i64>()?;
-        let d = splitted.next().unwrap().parse::<i64>()?;
+    pub fn ceiling(&self) -> BigInt {
+        let (q, r) = self.n.div_rem(&self.d);
+        if !r.is_zero() && !r.is_negative() { &q + &BigInt::from(1) } else { q }
+    }
 
-        Ok(Fraction::new(n, d))
+    pub fn truncate(&self) -> BigInt {
+        &self.n / &self.d
+    }
+
+    /// Rounds to the nearest integer, to the even one on ties.
+    pub fn round(&self) -> BigInt {
+        let two = BigInt::from(2);
+        let floor = self.floor();
+        let twice_diff = &(&(&self.n * &two) - &(&floor * &(&self.d * &two))) - &self.d;
+        if twice_diff.is_negative() {
+            floor
+        } else if twice_diff.is_zero() && (&floor % &two).is_zero() {
+            floor
+        } else {
+            &floor + &BigInt::from(1)
+        }
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Fraction) -> Option<Ordering> {
+        // Denominators are always positive
+        (&self.n * &other.d).partial_cmp(&(&other.n * &self.d))
     }
 }
 
@@ -53,7 +99,7 @@ impl Add for Fraction {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self {
-        Fraction::new(self.n * rhs.d + rhs.n * self.d, self.d * rhs.d)
+        Fraction::new(&self.n * &rhs.d + &rhs.n * &self.d, &self.d * &rhs.d)
     }
 }
 
@@ -61,7 +107,7 @@ impl Sub for Fraction {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self {
-        Fraction::new(self.n * rhs.d - rhs.n * self.d, self.d * rhs.d)
+        Fraction::new(&self.n * &rhs.d - &rhs.n * &self.d, &self.d * &rhs.d)
     }
 }
 
@@ -69,7 +115,7 @@ impl Mul for Fraction {
     type Output = Self;
 
     fn mul(self, rhs: Self) -> Self {
-        Fraction::new(self.n * rhs.n, self.d * rhs.d)
+        Fraction::new(&self.n * &rhs.n, &self.d * &rhs.d)
     }
 }
 
@@ -77,54 +123,43 @@ impl Div for Fraction {
     type Output = Self;
 
     fn div(self, rhs: Self) -> Self {
-        Fraction::new(self.n * rhs.d, self.d * rhs.n)
+        Fraction::new(&self.n * &rhs.d, &self.d * &rhs.n)
     }
 }
 
 impl From<i64> for Fraction {
     fn from(i: i64) -> Fraction {
-        Fraction::new(i, 1)
+        Fraction::from(BigInt::from(i))
     }
 }
 
-impl From<Fraction> for f64 {
-    fn from(f: Fraction) -> f64 {
-        f.n as f64 / f.d as f64
+impl From<BigInt> for Fraction {
+    fn from(i: BigInt) -> Fraction {
+        Fraction { n: i, d: BigInt::from(1) }
     }
 }
 
-impl From<f64> for Fraction {
-    // https://rosettacode.org/wiki/Convert_decimal_number_to_rational#Rust
-    fn from(mut n: f64) -> Fraction {
-        let flag_neg  = n < 0.0;
-        if flag_neg { n *= -1.0 }
-        if n < f64::MIN_POSITIVE {
-            return Fraction::new(0, 1)
-        }
-        if (n - n.round()).abs() < f64::EPSILON {
-            return Fraction::new(n.round() as i64, 1)
-        }
-        let mut a : isize = 0;
-        let mut b : isize = 1;
-        let mut c : isize = n.ceil() as isize;
-        let mut d : isize = 1;
-        let aux1 = isize::max_value()/2;
-        while c < aux1  && d < aux1 {
-            let aux2 : f64 = (a as f64 + c as f64)/(b as f64 + d as f64);
-            if (n - aux2).abs() < f64::EPSILON { break }
-            if n > aux2 {
-                a += c;
-                b += d;
-            } else {
-                c += a;
-                d += b;
-            }
+impl<'a> From<&'a Fraction> for f64 {
+    fn from(f: &Fraction) -> f64 {
+        let n = f.n.to_f64();
+        let d = f.d.to_f64();
+        if n.is_finite() && d.is_finite() {
+            return n / d
         }
 
-        if flag_neg {
-            Fraction::new(-(a+c) as i64, (b+d) as i64)
+        // Too big for floats, drop the digits that don't fit anyway
+        let shift = f.n.bits().max(f.d.bits()) - 1000;
+        let d = f.d.shr(shift);
+        if d.is_zero() {
+            n / d.to_f64()
         } else {
-            Fraction::new((a+c) as i64, (b+d) as i64)
+            f.n.shr(shift).to_f64() / d.to_f64()
         }
     }
 }
+
+impl From<Fraction> for f64 {
+    fn from(f: Fraction) -> f64 {
+        f64::from(&f)
+    }
+}
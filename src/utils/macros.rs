@@ -1,10 +1,19 @@
 #[macro_export]
 macro_rules! environment(
+    { $ctor:ident; $($key:expr => $value:expr),* } => {
+        {
+            use env::EnvValues;
+            use procedure::ProcedureData;
+            let mut m = EnvValues::default();
+            $(m.insert($key.to_string(), ProcedureData::$ctor($value));)*
+            m
+        }
+    };
     { $($key:expr => $value:expr),* } => {
         {
             use env::EnvValues;
             use procedure::ProcedureData;
-            let mut m = EnvValues::new();
+            let mut m = EnvValues::default();
             $(m.insert($key.to_string(), ProcedureData::new_primitive($value));)*
             m
         }
@@ -17,7 +26,7 @@ macro_rules! slist(
     [ $($item:expr),* ] => {
         {
             use parser::SExpr;
-            SExpr::List(vec![$($item),*])
+            SExpr::list(vec![$($item),*])
         }
     };
 );
@@ -27,7 +36,7 @@ macro_rules! sdottedlist(
     [ $($item:expr),* ; $last:expr ] => {
         {
             use parser::SExpr;
-            SExpr::DottedList(vec![$($item),*], Box::new($last))
+            SExpr::dottedlist(vec![$($item),*], $last)
         }
     };
 );
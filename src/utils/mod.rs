@@ -1,6 +1,8 @@
 #[macro_use]
 pub mod macros;
+pub mod bigint;
 pub mod fraction;
+pub mod complex;
 pub mod funcs;
 pub mod chars;
 pub mod radix;
@@ -1,17 +1,20 @@
 use std::fmt;
 use std::char;
 
+use lexer::Token;
+use utils::fraction::Fraction;
+use utils::bigint::BigInt;
+use utils::complex::Complex;
 use serr::{SErr, SResult};
 
-// FIXME: only works for integer
-// https://stackoverflow.com/a/50282149/3716130
+/// Formats a number in the given radix, like `number->string` does.
 pub struct Radix {
-    x: f64,
+    x: Token,
     radix: u32,
 }
 
 impl Radix {
-    pub fn new(x: f64, radix: u32) -> SResult<Self> {
+    pub fn new(x: Token, radix: u32) -> SResult<Self> {
         if radix < 2 || radix > 36 {
             bail!(Generic => "Unsupported radix")
         } else {
@@ -22,35 +25,295 @@ impl Radix {
 
 impl fmt::Display for Radix {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut x = self.x;
-        // Good for binary formatting of `u128`s
-        let mut result = ['\0'; 128];
-        let mut used = 0;
-        let negative = x < 0.0;
-        if negative {
-            x*=-1.0;
-        }
-        let mut x = x as u32;
-        loop {
-            let m = x % self.radix;
-            x /= self.radix;
-
-            result[used] = char::from_digit(m, self.radix).unwrap();
-            used += 1;
-
-            if x == 0 {
-                break;
-            }
+        match self.x {
+            Token::Integer(x) => write!(f, "{}", format_int(x, self.radix)),
+            Token::BigInt(ref x) => write!(f, "{}", x.to_str_radix(self.radix)),
+            Token::Fraction(ref x) => write!(f, "{}/{}", x.n.to_str_radix(self.radix), x.d.to_str_radix(self.radix)),
+            Token::Float(x) if self.radix == 10 || !x.is_finite() => write!(f, "{}", format_float(x)),
+            Token::Float(x) => {
+                if x.is_sign_negative() {
+                    write!(f, "-")?;
+                }
+
+                let x = x.abs();
+                write!(f, "{}.", format_uint(x.trunc() as u64, self.radix))?;
+
+                // At most as many digits as needed to show every bit
+                let mut fract = x.fract();
+                for _ in 0..64 {
+                    if fract == 0.0 {
+                        break
+                    }
+
+                    fract *= self.radix as f64;
+                    write!(f, "{}", char::from_digit(fract.trunc() as u32, self.radix).unwrap())?;
+                    fract = fract.fract();
+                }
+
+                Ok(())
+            },
+            Token::Complex(x) => {
+                let re = Radix { x: Token::Float(x.re), radix: self.radix }.to_string();
+                let im = Radix { x: Token::Float(x.im), radix: self.radix }.to_string();
+                write!(f, "{}", join_complex(re, im))
+            },
+            ref x => write!(f, "{}", x)
         }
+    }
+}
+
+/// Formats a float so that it is read back as an inexact number: `1.0`,
+/// `+inf.0` or `+nan.0`.
+pub fn format_float(x: f64) -> String {
+    if x.is_nan() {
+        "+nan.0".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "+inf.0" } else { "-inf.0" }.to_string()
+    } else if x.fract() == 0.0 {
+        format!("{}.0", x)
+    } else {
+        format!("{}", x)
+    }
+}
+
+/// Formats a complex number as `1.0+2.0i`.
+pub fn format_complex(x: Complex) -> String {
+    join_complex(format_float(x.re), format_float(x.im))
+}
+
+fn join_complex(re: String, im: String) -> String {
+    // Infinities and NaN already have a sign
+    if im.starts_with('+') || im.starts_with('-') {
+        format!("{}{}i", re, im)
+    } else {
+        format!("{}+{}i", re, im)
+    }
+}
+
+fn format_int(x: i64, radix: u32) -> String {
+    if x < 0 {
+        format!("-{}", format_uint(x.unsigned_abs(), radix))
+    } else {
+        format_uint(x as u64, radix)
+    }
+}
 
-        if negative {
-            write!(f, "-")?;
+fn format_uint(mut x: u64, radix: u32) -> String {
+    let mut digits = vec![];
+    loop {
+        digits.push(char::from_digit((x % radix as u64) as u32, radix).unwrap());
+        x /= radix as u64;
+
+        if x == 0 {
+            break
         }
+    }
 
-        for c in result[..used].iter().rev() {
-            write!(f, "{}", c)?;
+    digits.iter().rev().collect()
+}
+
+/// Parses a number in R7RS syntax. The `#x`, `#b`, `#o` and `#d` prefixes
+/// change the radix and `#e`, `#i` make the number exact or inexact.
+/// `radix` is used if there is no radix prefix. Complex numbers are
+/// written as `1+2i`, `-i` or `1@2` in polar form.
+pub fn parse_number(value: &str, radix: u32) -> Option<Token> {
+    let mut radix = radix;
+    let mut radix_prefix = false;
+    let mut exactness = None;
+    let mut rest = value;
+    while rest.starts_with('#') {
+        match rest[1..].chars().next()?.to_ascii_lowercase() {
+            'x' | 'b' | 'o' | 'd' if !radix_prefix => {
+                radix = match rest[1..2].to_ascii_lowercase().as_str() {
+                    "x" => 16,
+                    "b" => 2,
+                    "o" => 8,
+                    _ => 10
+                };
+                radix_prefix = true;
+            },
+            'e' if exactness.is_none() => exactness = Some(true),
+            'i' if exactness.is_none() => exactness = Some(false),
+            _ => return None
         }
 
-        Ok(())
+        rest = &rest[2..];
+    }
+
+    // Polar form, magnitude@angle
+    if let Some(pos) = rest.find('@') {
+        let magnitude = parse_real(&rest[..pos], radix)?;
+        let angle = parse_real(&rest[pos + 1..], radix)?;
+        if angle.is_exact_zero() {
+            return real_token(magnitude, exactness)
+        }
+
+        return inexact_complex(Complex::from_polar(magnitude.inexact, angle.inexact), exactness)
+    }
+
+    if rest.len() > 1 && rest.ends_with(|c| c == 'i' || c == 'I') {
+        let body = &rest[..rest.len() - 1];
+
+        // The imaginary part starts with the last sign that isn't the
+        // sign of an exponent
+        let pos = body.char_indices()
+            .rev()
+            .find(|&(i, c)| (c == '+' || c == '-')
+                  && (i == 0 || radix != 10 || !body[..i].ends_with(|c| c == 'e' || c == 'E')))?
+            .0;
+        let (re, im) = body.split_at(pos);
+        let re = if re.is_empty() { parse_real("0", radix)? } else { parse_real(re, radix)? };
+        let im = if im.len() == 1 { parse_real(&format!("{}1", im), radix)? } else { parse_real(im, radix)? };
+        if im.is_exact_zero() {
+            return real_token(re, exactness)
+        }
+
+        return inexact_complex(Complex::new(re.inexact, im.inexact), exactness)
+    }
+
+    real_token(parse_real(rest, radix)?, exactness)
+}
+
+/// Complex numbers are always inexact, so `#e` is not allowed for them.
+fn inexact_complex(x: Complex, exactness: Option<bool>) -> Option<Token> {
+    if exactness == Some(true) {
+        None
+    } else {
+        Some(Token::complex(x))
     }
 }
+
+fn real_token(real: Real, exactness: Option<bool>) -> Option<Token> {
+    match exactness {
+        Some(true) => real.exact.map(exact_token),
+        Some(false) => Some(Token::Float(real.inexact)),
+        None if real.is_exact => real.exact.map(exact_token),
+        None => Some(Token::Float(real.inexact))
+    }
+}
+
+/// Exponents bigger than this make numbers like `#e1e100000000` that take
+/// forever to compute.
+const MAX_EXACT_EXPONENT: u32 = 10000;
+
+/// A parsed real number, both as an exact and an inexact number.
+struct Real {
+    /// Numerator and denominator, if the number is finite.
+    exact: Option<(BigInt, BigInt)>,
+    inexact: f64,
+    /// Integers and fractions are exact, decimals are not.
+    is_exact: bool,
+}
+
+impl Real {
+    fn is_exact_zero(&self) -> bool {
+        self.is_exact && self.inexact == 0.0
+    }
+}
+
+fn parse_real(value: &str, radix: u32) -> Option<Real> {
+    let (negative, body) = match value.chars().next()? {
+        '-' => (true, &value[1..]),
+        '+' => (false, &value[1..]),
+        _ => (false, value)
+    };
+    let signed = |x: f64| if negative { -x } else { x };
+
+    // +inf.0 and +nan.0 must have a sign
+    if body.len() != value.len() {
+        match body.to_ascii_lowercase().as_str() {
+            "inf.0" => return Some(Real { exact: None, inexact: signed(::std::f64::INFINITY), is_exact: false }),
+            "nan.0" => return Some(Real { exact: None, inexact: ::std::f64::NAN, is_exact: false }),
+            _ => ()
+        }
+    }
+
+    if let Some(pos) = body.find('/') {
+        let n = parse_uint(&body[..pos], radix)?;
+        let d = parse_uint(&body[pos + 1..], radix)?;
+        if d.is_zero() {
+            return None
+        }
+
+        let fraction = Fraction::new(if negative { -n } else { n }, d);
+        return Some(Real {
+            inexact: f64::from(&fraction),
+            exact: Some((fraction.n, fraction.d)),
+            is_exact: true
+        })
+    }
+
+    // Decimals: digits, an optional point and more digits and an exponent,
+    // the exponent is only possible in radix 10 because `e` is a digit in
+    // radix 16.
+    let (mantissa, exponent_part) = match body.find(|c| c == 'e' || c == 'E') {
+        Some(pos) if radix == 10 => (&body[..pos], Some(&body[pos + 1..])),
+        _ => (body, None)
+    };
+    let (int_part, fract_part) = match mantissa.find('.') {
+        Some(pos) => (&mantissa[..pos], Some(&mantissa[pos + 1..])),
+        None => (mantissa, None)
+    };
+    let fract_digits = fract_part.unwrap_or("");
+    if int_part.is_empty() && fract_digits.is_empty() {
+        return None
+    }
+
+    let exponent = match exponent_part {
+        Some(x) => match x.parse::<i32>() {
+            Ok(x) => x,
+            Err(_) => return None
+        },
+        None => 0
+    };
+    if !is_digits(int_part, radix) || !is_digits(fract_digits, radix) {
+        return None
+    }
+
+    // n / d * 10^exponent, huge exponents are only read as inexact
+    let exact = parse_uint(&format!("{}{}", int_part, fract_digits), radix)
+        .filter(|_| exponent.unsigned_abs() <= MAX_EXACT_EXPONENT)
+        .map(|n| {
+            let n = if negative { -n } else { n };
+            let d = BigInt::from(radix as i64).pow(fract_digits.len() as u32);
+            let scale = BigInt::from(10).pow(exponent.unsigned_abs());
+            if exponent < 0 {
+                (n, d * scale)
+            } else {
+                (n * scale, d)
+            }
+        });
+
+    let inexact = if radix == 10 {
+        body.parse::<f64>().ok()?
+    } else {
+        let int = int_part.chars()
+            .fold(0.0, |acc, c| acc * radix as f64 + c.to_digit(radix).unwrap() as f64);
+        let fract = fract_digits.chars()
+            .rev()
+            .fold(0.0, |acc, c| (acc + c.to_digit(radix).unwrap() as f64) / radix as f64);
+        int + fract
+    };
+
+    Some(Real {
+        exact,
+        inexact: signed(inexact.abs()),
+        is_exact: fract_part.is_none() && exponent_part.is_none()
+    })
+}
+
+fn exact_token((n, d): (BigInt, BigInt)) -> Token {
+    Token::rational(Fraction::new(n, d))
+}
+
+fn is_digits(value: &str, radix: u32) -> bool {
+    value.chars().all(|c| c.is_digit(radix))
+}
+
+fn parse_uint(value: &str, radix: u32) -> Option<BigInt> {
+    if value.is_empty() || !is_digits(value, radix) {
+        return None
+    }
+
+    BigInt::parse(value, radix)
+}